@@ -0,0 +1,65 @@
+extern crate gameboy_tools;
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use gameboy_tools::rom_disasm::RomDisassembly;
+
+use clap::{App, Arg};
+
+fn main() -> Result<(), std::io::Error> {
+    let matches = App::new("gb-disasm")
+        .version("1.0")
+        .about("Disassemble a whole Gameboy ROM to RGBDS source")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("disasm")
+                .help("Directory to write the .asm files to"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input Gameboy file")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    let filename = matches.value_of("INPUT").unwrap();
+    let out_dir = Path::new(matches.value_of("output").unwrap());
+
+    let rom = fs::read(filename)?;
+    let dis = RomDisassembly::new(&rom);
+
+    fs::create_dir_all(out_dir)?;
+
+    let mut main = BufWriter::new(File::create(out_dir.join("game.asm"))?);
+    writeln!(main, "; Disassembly of {}", filename)?;
+    writeln!(main, "; Rebuild with RGBDS 0.6 or later:")?;
+    writeln!(
+        main,
+        ";   rgbasm -o game.o game.asm && rgblink -o game.gb game.o"
+    )?;
+    writeln!(main)?;
+
+    for bank in 0..dis.banks() {
+        let bank_filename = format!("bank_{:02X}.asm", bank);
+        let mut out = BufWriter::new(File::create(out_dir.join(&bank_filename))?);
+        dis.write_bank(bank, &mut out)?;
+
+        writeln!(main, "INCLUDE \"{}\"", bank_filename)?;
+    }
+
+    println!(
+        "{} banks, {} of {} bytes traced as code, written to {}",
+        dis.banks(),
+        dis.code_bytes(),
+        rom.len(),
+        out_dir.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+extern crate gameboy_tools;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use gameboy::gameboy::GameBoy;
+use gameboy::model::Model;
+use gameboy::movie::Movie;
+use gameboy_tools::headless::{parse_press, parse_script, write_png, Runner, Verdict};
+
+use clap::{App, Arg};
+
+fn main() -> Result<(), std::io::Error> {
+    let matches = App::new("gb-headless")
+        .version("1.0")
+        .about("Run a Gameboy ROM without a window, e.g. for automated testing")
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .help("Stop after this many frames (default 3600 unless --cycles is given)"),
+        )
+        .arg(
+            Arg::with_name("cycles")
+                .long("cycles")
+                .takes_value(true)
+                .help("Stop after this many cycles"),
+        )
+        .arg(
+            Arg::with_name("model")
+                .long("model")
+                .takes_value(true)
+                .possible_values(&["dmg0", "dmg", "mgb", "sgb", "cgb", "agb"])
+                .default_value("dmg")
+                .help("Hardware model to emulate"),
+        )
+        .arg(
+            Arg::with_name("boot-rom")
+                .long("boot-rom")
+                .takes_value(true)
+                .help("Boot ROM image to run before the cartridge"),
+        )
+        .arg(
+            Arg::with_name("mcycle-timing")
+                .long("mcycle-timing")
+                .help("Advance the Gpu & timer on each memory access"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .help("Input script, lines of <frame> <button> press|release"),
+        )
+        .arg(
+            Arg::with_name("press")
+                .long("press")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Press a button, as button@frame[+hold]"),
+        )
+        .arg(
+            Arg::with_name("movie")
+                .long("movie")
+                .takes_value(true)
+                .help("Play back a movie, running for its length unless --frames is given"),
+        )
+        .arg(
+            Arg::with_name("screenshot")
+                .long("screenshot")
+                .takes_value(true)
+                .help("Write the final screen to this PNG file"),
+        )
+        .arg(
+            Arg::with_name("keep-running")
+                .long("keep-running")
+                .help("Run for the full frames/cycles even once a result is known"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input Gameboy file")
+                .required(true)
+                .index(1),
+        )
+        .get_matches();
+
+    let filename = matches.value_of("INPUT").unwrap();
+    let rom_contents = fs::read(filename)?;
+    let model: Model = matches.value_of("model").unwrap().parse().unwrap();
+
+    let mut gb = match matches.value_of("boot-rom") {
+        Some(boot_filename) => {
+            GameBoy::with_boot_rom(rom_contents, model, fs::read(boot_filename)?).unwrap_or_else(
+                |e| {
+                    eprintln!("{}: {}", boot_filename, e);
+                    process::exit(2);
+                },
+            )
+        }
+        None => GameBoy::new(rom_contents, model),
+    };
+    gb.set_mcycle_timing(matches.is_present("mcycle-timing"));
+    gb.set_serial_echo(false);
+
+    let mut events = match matches.value_of("input") {
+        Some(script) => parse_script(&fs::read_to_string(script)?).unwrap_or_else(|e| {
+            eprintln!("{}: {}", script, e);
+            process::exit(2);
+        }),
+        None => Vec::new(),
+    };
+    for press in matches.values_of("press").into_iter().flatten() {
+        events.extend_from_slice(&parse_press(press).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2);
+        }));
+    }
+
+    let mut movie_frames = None;
+    if let Some(movie_filename) = matches.value_of("movie") {
+        let played = Movie::parse(&fs::read(movie_filename)?, gb.rom(), model).and_then(|movie| {
+            movie_frames = Some(movie.frames());
+            gb.play_movie(movie)
+        });
+        if let Err(e) = played {
+            eprintln!("{}: {}", movie_filename, e);
+            process::exit(2);
+        }
+    }
+
+    let max_cycles = matches
+        .value_of("cycles")
+        .map(|c| c.parse().expect("Invalid cycles"));
+    let max_frames = match matches.value_of("frames") {
+        Some(frames) => Some(frames.parse().expect("Invalid frames")),
+        None if max_cycles.is_some() => None,
+        None => Some(movie_frames.unwrap_or(3600)),
+    };
+
+    let mut runner = Runner::new(gb, events);
+    let verdict = runner.run(max_frames, max_cycles, !matches.is_present("keep-running"));
+
+    let serial = runner.serial();
+    if !serial.is_empty() {
+        println!("{}", serial.trim_end());
+    }
+
+    if let Some(screenshot) = matches.value_of("screenshot") {
+        let (rgba, width, height) = runner.screen();
+        write_png(Path::new(screenshot), rgba, width, height)?;
+    }
+
+    println!(
+        "{:?} after {} frames, {} cycles",
+        verdict,
+        runner.frames(),
+        runner.gameboy().cycles
+    );
+
+    process::exit(match verdict {
+        Verdict::Passed => 0,
+        Verdict::Failed => 1,
+        Verdict::Unknown => 2,
+    });
+}
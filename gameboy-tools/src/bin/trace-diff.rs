@@ -0,0 +1,82 @@
+extern crate gameboy_tools;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
+
+use gameboy_tools::trace_diff::first_divergence;
+
+use clap::{App, Arg};
+use flate2::read::GzDecoder;
+
+fn main() -> Result<(), io::Error> {
+    let matches = App::new("trace-diff")
+        .version("1.0")
+        .about("Find the first instruction where two execution traces differ")
+        .arg(
+            Arg::with_name("context")
+                .short("c")
+                .long("context")
+                .takes_value(true)
+                .default_value("5")
+                .help("Matching lines to show before the difference"),
+        )
+        .arg(
+            Arg::with_name("EXPECTED")
+                .help("Reference trace, e.g. a Gameboy Doctor log (.gz allowed)")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("ACTUAL")
+                .help("Trace to check (.gz allowed)")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    let context = matches
+        .value_of("context")
+        .unwrap()
+        .parse()
+        .expect("Invalid context");
+
+    let expected = open(matches.value_of("EXPECTED").unwrap())?;
+    let actual = open(matches.value_of("ACTUAL").unwrap())?;
+
+    let divergence = match first_divergence(expected, actual, context)? {
+        Some(divergence) => divergence,
+        None => {
+            println!("Traces match");
+            return Ok(());
+        }
+    };
+
+    println!("Traces diverge at line {}", divergence.line);
+    for line in &divergence.context {
+        println!("  {}", line);
+    }
+    println!(
+        "- {}",
+        divergence.expected.as_deref().unwrap_or("<end of trace>")
+    );
+    println!(
+        "+ {}",
+        divergence.actual.as_deref().unwrap_or("<end of trace>")
+    );
+    if !divergence.fields.is_empty() {
+        println!("Differs in: {}", divergence.fields.join(", "));
+    }
+
+    process::exit(1);
+}
+
+fn open(filename: &str) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(filename)?;
+
+    if filename.ends_with(".gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use gameboy::gameboy::{GameBoy, RunStop};
+use gameboy::gpu::{GB_HSIZE, GB_VSIZE};
+use gameboy::input::Button;
+use gameboy::sgb::{SGB_HSIZE, SGB_VSIZE};
+
+const GB_WIDTH: u32 = GB_HSIZE as u32;
+const GB_HEIGHT: u32 = GB_VSIZE as u32;
+const SGB_WIDTH: u32 = SGB_HSIZE as u32;
+const SGB_HEIGHT: u32 = SGB_VSIZE as u32;
+
+// Registers Mooneye test ROMs leave behind on success & failure
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+/// Parse an input script, one `<frame> <button> press|release` per line.
+/// Anything after a `#` is a comment.
+pub fn parse_script(text: &str) -> Result<Vec<InputEvent>, String> {
+    let mut events = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let event = match parts.as_slice() {
+            [frame, button, action] => InputEvent {
+                frame: frame
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid frame {}", number + 1, frame))?,
+                button: button.parse()?,
+                pressed: match *action {
+                    "press" => true,
+                    "release" => false,
+                    _ => return Err(format!("line {}: unknown action {}", number + 1, action)),
+                },
+            },
+            _ => {
+                return Err(format!(
+                    "line {}: expected <frame> <button> press|release",
+                    number + 1
+                ))
+            }
+        };
+
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// `button@frame[+hold]`: press `button` on `frame`, releasing it `hold`
+/// frames later (default 1)
+pub fn parse_press(s: &str) -> Result<[InputEvent; 2], String> {
+    let invalid = || format!("Invalid press {}, expected button@frame[+hold]", s);
+
+    let mut parts = s.splitn(2, '@');
+    let button: Button = parts.next().ok_or_else(invalid)?.parse()?;
+
+    let mut timing = parts.next().ok_or_else(invalid)?.splitn(2, '+');
+    let frame: u64 = timing
+        .next()
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(invalid)?;
+    let hold: u64 = match timing.next() {
+        Some(hold) => hold.parse().map_err(|_| invalid())?,
+        None => 1,
+    };
+
+    Ok([
+        InputEvent {
+            frame,
+            button,
+            pressed: true,
+        },
+        InputEvent {
+            frame: frame + hold,
+            button,
+            pressed: false,
+        },
+    ])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Passed,
+    Failed,
+    Unknown,
+}
+
+/// Runs a GameBoy without a display, feeding it scripted inputs and watching
+/// for the ways test ROMs report their result: Blargg's print "Passed" or
+/// "Failed" over serial, Mooneye's run `LD B,B` with a Fibonacci sequence (or
+/// all 0x42 on failure) in the registers.
+pub struct Runner {
+    gb: GameBoy,
+    events: Vec<InputEvent>,
+    next_event: usize,
+    frames: u64,
+    verdict: Verdict,
+}
+
+impl Runner {
+    pub fn new(gb: GameBoy, mut events: Vec<InputEvent>) -> Runner {
+        events.sort_by_key(|e| e.frame);
+
+        Runner {
+            gb,
+            events,
+            next_event: 0,
+            frames: 0,
+            verdict: Verdict::Unknown,
+        }
+    }
+
+    /// Run until `max_frames` frames or `max_cycles` cycles have passed,
+    /// stopping early once the result is known if `stop_on_verdict`
+    pub fn run(
+        &mut self,
+        max_frames: Option<u64>,
+        max_cycles: Option<u64>,
+        stop_on_verdict: bool,
+    ) -> Verdict {
+        loop {
+            let out_of_frames = max_frames.is_some_and(|max| self.frames >= max);
+            let out_of_cycles = max_cycles.is_some_and(|max| self.gb.cycles >= max);
+            if out_of_frames || out_of_cycles {
+                break;
+            }
+            if stop_on_verdict && self.verdict != Verdict::Unknown {
+                break;
+            }
+
+            self.step(max_cycles);
+        }
+
+        self.verdict
+    }
+
+    /// Run to the end of the frame, or until `max_cycles` or a Mooneye result
+    fn step(&mut self, max_cycles: Option<u64>) {
+        while let Some(event) = self.events.get(self.next_event) {
+            if event.frame > self.frames {
+                break;
+            }
+            self.gb.input().set_input(event.button, event.pressed);
+            self.next_event += 1;
+        }
+
+        let frames = self.gb.frames;
+        let summary = self.gb.run_until(|gb| {
+            gb.frames != frames
+                || max_cycles.is_some_and(|max| gb.cycles >= max)
+                || mooneye_verdict(gb).is_some()
+        });
+
+        if let RunStop::Debugger(_) = summary.stop {
+            // Nobody to hand over to, so carry on
+            self.gb.debugger().resume();
+        }
+
+        if let Some(verdict) = mooneye_verdict(&self.gb) {
+            self.verdict = verdict;
+        }
+
+        if summary.frame {
+            self.frames += 1;
+            self.check_serial();
+        }
+    }
+
+    fn check_serial(&mut self) {
+        let serial = self.serial();
+        if serial.contains("Passed") {
+            self.verdict = Verdict::Passed;
+        } else if serial.contains("Failed") {
+            self.verdict = Verdict::Failed;
+        }
+    }
+
+    pub fn serial(&self) -> String {
+        String::from_utf8_lossy(self.gb.serial_output()).into_owned()
+    }
+
+    pub fn verdict(&self) -> Verdict {
+        self.verdict
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn gameboy(&self) -> &GameBoy {
+        &self.gb
+    }
+
+    pub fn gameboy_mut(&mut self) -> &mut GameBoy {
+        &mut self.gb
+    }
+
+    /// The screen as RGBA, with its width & height. Includes the border when
+    /// running as a Super Game Boy.
+    pub fn screen(&self) -> (&[u8], u32, u32) {
+        match self.gb.sgb_buffer_vec() {
+            Some(buffer) => (buffer, SGB_WIDTH, SGB_HEIGHT),
+            None => (self.gb.buffer_vec(), GB_WIDTH, GB_HEIGHT),
+        }
+    }
+}
+
+/// Result of a Mooneye test about to run `LD B,B`, if that's what it is
+fn mooneye_verdict(gb: &GameBoy) -> Option<Verdict> {
+    let cpu = &gb.cpu;
+    if cpu.halted || gb.mem.get(cpu.pc) != 0x40 {
+        return None;
+    }
+
+    match [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l] {
+        MOONEYE_PASS => Some(Verdict::Passed),
+        MOONEYE_FAIL => Some(Verdict::Failed),
+        _ => None,
+    }
+}
+
+pub fn write_png(path: &Path, rgba: &[u8], width: u32, height: u32) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(rgba).map_err(io::Error::other)
+}
+
+/// RGBA pixels of a PNG, with its width & height
+pub fn read_png(path: &Path) -> io::Result<(Vec<u8>, u32, u32)> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+    buffer.truncate(info.buffer_size());
+
+    let rgba = match (info.color_type, info.bit_depth) {
+        (png::ColorType::Rgba, png::BitDepth::Eight) => buffer,
+        (png::ColorType::Rgb, png::BitDepth::Eight) => buffer
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        (colour, depth) => {
+            return Err(io::Error::other(format!(
+                "Unsupported PNG format {:?} {:?}",
+                colour, depth
+            )))
+        }
+    };
+
+    Ok((rgba, info.width, info.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gameboy::model::Model;
+
+    #[test]
+    fn input_script() {
+        let events =
+            parse_script("# title screen\n60 start press\n\n65 Start release # done\n").unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                InputEvent {
+                    frame: 60,
+                    button: Button::Start,
+                    pressed: true
+                },
+                InputEvent {
+                    frame: 65,
+                    button: Button::Start,
+                    pressed: false
+                },
+            ]
+        );
+
+        assert!(parse_script("60 start").is_err());
+        assert!(parse_script("60 turbo press").is_err());
+    }
+
+    #[test]
+    fn press() {
+        let [press, release] = parse_press("a@10+3").unwrap();
+        assert_eq!(
+            (press.frame, press.button, press.pressed),
+            (10, Button::A, true)
+        );
+        assert_eq!((release.frame, release.pressed), (13, false));
+
+        assert_eq!(parse_press("down@7").unwrap()[1].frame, 8);
+        assert!(parse_press("down").is_err());
+    }
+
+    #[test]
+    fn mooneye_signature() {
+        // ld b,3 / ld c,5 / ld d,8 / ld e,13 / ld h,21 / ld l,34 / ld b,b / jr @
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x100..0x10F].copy_from_slice(&[
+            0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE,
+        ]);
+
+        let mut runner = Runner::new(GameBoy::new(rom, Model::Dmg), Vec::new());
+        assert_eq!(runner.run(Some(10), None, true), Verdict::Passed);
+        assert_eq!(runner.frames(), 0);
+    }
+}
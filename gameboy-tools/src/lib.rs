@@ -0,0 +1,5 @@
+extern crate gameboy;
+
+pub mod headless;
+pub mod rom_disasm;
+pub mod trace_diff;
@@ -0,0 +1,344 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+
+use gameboy::instruction::{CpuRegister, Instruction};
+
+pub const BANK_SIZE: usize = 0x4000;
+
+/// Places execution starts without anything jumping there: the RST vectors,
+/// the interrupt vectors & the cartridge entry point
+const ENTRY_POINTS: [u16; 14] = [
+    0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x40, 0x48, 0x50, 0x58, 0x60, 0x100,
+];
+
+// Bytes per line of data
+const DATA_LINE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Byte {
+    Data,
+    Opcode,
+    Operand,
+}
+
+/// One line of output, along with the bytes it assembles to
+pub struct Line<'a> {
+    pub label: Option<String>,
+    pub text: String,
+    pub bytes: &'a [u8],
+}
+
+/// Code/data split of a whole ROM, found by following every jump & call from
+/// the entry points. Everything addressed by ROM offset, `bank * 0x4000 +
+/// address within the bank`.
+pub struct RomDisassembly<'a> {
+    rom: &'a [u8],
+    bytes: Vec<Byte>,
+    // Offset of the ROM each jump/call instruction goes to, where known
+    targets: HashMap<usize, usize>,
+    labels: BTreeSet<usize>,
+}
+
+impl<'a> RomDisassembly<'a> {
+    pub fn new(rom: &'a [u8]) -> RomDisassembly<'a> {
+        let mut dis = RomDisassembly {
+            rom,
+            bytes: vec![Byte::Data; rom.len()],
+            targets: HashMap::new(),
+            labels: BTreeSet::new(),
+        };
+
+        let mut pending: Vec<(usize, Option<u8>)> = ENTRY_POINTS
+            .iter()
+            .map(|&addr| (usize::from(addr), None))
+            .filter(|&(offset, _)| offset < rom.len())
+            .collect();
+        dis.labels.extend(pending.iter().map(|&(offset, _)| offset));
+
+        while let Some((offset, switched)) = pending.pop() {
+            dis.trace(offset, switched, &mut pending);
+        }
+
+        // Somewhere in the middle of an instruction can't be given a label,
+        // anything going there keeps its numeric address
+        let bytes = &dis.bytes;
+        dis.labels.retain(|&offset| bytes[offset] != Byte::Operand);
+
+        dis
+    }
+
+    pub fn banks(&self) -> usize {
+        self.rom.len().div_ceil(BANK_SIZE)
+    }
+
+    /// Number of bytes found to be code
+    pub fn code_bytes(&self) -> usize {
+        self.bytes.iter().filter(|&&b| b != Byte::Data).count()
+    }
+
+    /// Follow code from `offset` until it jumps away unconditionally, queuing
+    /// up any other paths it could take. `switched` is a guess at which bank
+    /// is mapped at 0x4000, so calls from bank 0 can be followed.
+    fn trace(
+        &mut self,
+        mut offset: usize,
+        mut switched: Option<u8>,
+        pending: &mut Vec<(usize, Option<u8>)>,
+    ) {
+        let bank = offset / BANK_SIZE;
+        let bank_end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+        let mut last_a = None;
+
+        while offset < bank_end && self.bytes[offset] == Byte::Data {
+            let bytes = &self.rom[offset..bank_end.min(offset + 3)];
+            let instr = Instruction::decode(bytes);
+            let len = match (bytes[0], &instr) {
+                // STOP is followed by a byte which is skipped
+                (0x10, _) => 2,
+                (_, Instruction::ILLEGAL) => return,
+                (_, instr) => usize::from(Instruction::mem_size(instr)),
+            };
+
+            // Whatever this is runs into the next bank or the middle of
+            // something already traced, so it can't really be code
+            if offset + len > bank_end
+                || self.bytes[offset + 1..offset + len]
+                    .iter()
+                    .any(|&b| b != Byte::Data)
+            {
+                return;
+            }
+
+            self.bytes[offset] = Byte::Opcode;
+            for b in &mut self.bytes[offset + 1..offset + len] {
+                *b = Byte::Operand;
+            }
+
+            let pc = cpu_address(offset);
+            if let Some(target) = instr
+                .target(pc)
+                .and_then(|t| self.rom_offset(bank, t, switched))
+            {
+                self.targets.insert(offset, target);
+                self.labels.insert(target);
+                pending.push((target, switched));
+            }
+
+            // Catch the usual `ld a, BANK(x)` / `ld [$2000], a` bank switch
+            match instr {
+                Instruction::LDI8 {
+                    val,
+                    reg: CpuRegister::A,
+                } => last_a = Some(val),
+                Instruction::STAA { addr } if (0x2000..0x4000).contains(&addr) => {
+                    switched = last_a.map(|bank| bank.max(1))
+                }
+                _ => last_a = None,
+            }
+
+            match instr {
+                Instruction::JP { .. }
+                | Instruction::JR { .. }
+                | Instruction::JPA
+                | Instruction::RET
+                | Instruction::RETI => return,
+                _ => {}
+            }
+
+            offset += len;
+        }
+    }
+
+    /// ROM offset of `addr` as seen from code in `bank`
+    fn rom_offset(&self, bank: usize, addr: u16, switched: Option<u8>) -> Option<usize> {
+        let offset = match addr {
+            0x0000..=0x3FFF => usize::from(addr),
+            0x4000..=0x7FFF => {
+                let target_bank = if bank != 0 {
+                    bank
+                } else if self.banks() <= 2 {
+                    // No MBC, bank 1 is the only option
+                    1
+                } else {
+                    usize::from(switched?)
+                };
+                target_bank * BANK_SIZE + usize::from(addr - 0x4000)
+            }
+            _ => return None,
+        };
+
+        if offset < self.rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn label(&self, offset: usize) -> String {
+        format!(
+            "Label_{:02X}_{:04X}",
+            offset / BANK_SIZE,
+            cpu_address(offset)
+        )
+    }
+
+    /// Source for one bank, covering every byte of it
+    pub fn bank_lines(&self, bank: usize) -> Vec<Line<'a>> {
+        let start = bank * BANK_SIZE;
+        let end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+
+        let mut lines = Vec::new();
+        let mut offset = start;
+
+        while offset < end {
+            let label = if self.labels.contains(&offset) {
+                Some(self.label(offset))
+            } else {
+                None
+            };
+
+            let (text, len) = if self.bytes[offset] == Byte::Opcode {
+                self.instruction(offset)
+            } else {
+                // Data runs up to the next code or label
+                let len = (offset + 1..end.min(offset + DATA_LINE))
+                    .find(|&o| self.bytes[o] != Byte::Data || self.labels.contains(&o))
+                    .unwrap_or_else(|| end.min(offset + DATA_LINE))
+                    - offset;
+                (data_directive(&self.rom[offset..offset + len]), len)
+            };
+
+            lines.push(Line {
+                label,
+                text,
+                bytes: &self.rom[offset..offset + len],
+            });
+            offset += len;
+        }
+
+        lines
+    }
+
+    fn instruction(&self, offset: usize) -> (String, usize) {
+        let len = 1 + self.bytes[offset + 1..]
+            .iter()
+            .take_while(|&&b| b == Byte::Operand)
+            .count();
+        let bytes = &self.rom[offset..offset + len];
+
+        // rgbasm always follows STOP with 0
+        if bytes[0] == 0x10 {
+            let text = if bytes[1] == 0 {
+                "STOP".to_string()
+            } else {
+                data_directive(bytes)
+            };
+            return (text, len);
+        }
+
+        let target = self
+            .targets
+            .get(&offset)
+            .filter(|t| self.labels.contains(t))
+            .map(|&t| (cpu_address(t), self.label(t)));
+        let symbol = |addr: u16| match &target {
+            Some((target_addr, name)) if *target_addr == addr => Some(name.clone()),
+            _ => None,
+        };
+
+        let text = Instruction::decode(bytes).format(Some(cpu_address(offset)), &symbol);
+        (text, len)
+    }
+
+    pub fn write_bank<W: Write>(&self, bank: usize, out: &mut W) -> io::Result<()> {
+        if bank == 0 {
+            writeln!(out, "SECTION \"ROM Bank $00\", ROM0[$0000]")?;
+        } else {
+            writeln!(
+                out,
+                "SECTION \"ROM Bank ${:02X}\", ROMX[$4000], BANK[${:02X}]",
+                bank, bank
+            )?;
+        }
+
+        for line in self.bank_lines(bank) {
+            if let Some(label) = line.label {
+                writeln!(out)?;
+                writeln!(out, "{}::", label)?;
+            }
+            writeln!(out, "    {}", line.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Address the CPU sees a ROM offset at, when its bank is mapped
+fn cpu_address(offset: usize) -> u16 {
+    if offset < BANK_SIZE {
+        offset as u16
+    } else {
+        (0x4000 + offset % BANK_SIZE) as u16
+    }
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    format!("DB {}", bytes.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0xFF; 4 * BANK_SIZE];
+
+        // Entry point: nop / jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // ld a, 2 / ld [$2000], a / call $4000 / stop $01 / jr @-2
+        rom[0x150..0x15A]
+            .copy_from_slice(&[0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x10, 0x01]);
+        rom[0x15A..0x15C].copy_from_slice(&[0x18, 0xFE]);
+        // Bank 2: ld hl, $4007 / ld a, [hl+] / ret, then data
+        rom[0x8000..0x8005].copy_from_slice(&[0x21, 0x07, 0x40, 0x2A, 0xC9]);
+        rom[0x8005..0x8008].copy_from_slice(&[0xD3, 0x10, 0x20]);
+
+        rom
+    }
+
+    #[test]
+    fn follows_bank_switches() {
+        let rom = test_rom();
+        let dis = RomDisassembly::new(&rom);
+
+        let text =
+            |bank| -> Vec<String> { dis.bank_lines(bank).into_iter().map(|l| l.text).collect() };
+
+        assert!(text(0).contains(&"CALL Label_02_4000".to_string()));
+        assert!(text(0).contains(&"DB $10,$01".to_string()));
+        assert!(text(0).contains(&"JR Label_00_015A".to_string()));
+        assert_eq!(&text(2)[..3], &["LD HL,$4007", "LD A,[HL+]", "RET"]);
+        assert_eq!(
+            text(2)[3],
+            "DB $D3,$10,$20,$FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF,$FF"
+        );
+        // Nothing calls into bank 1 or 3
+        assert!(text(1).iter().all(|t| t.starts_with("DB")));
+    }
+
+    #[test]
+    fn covers_every_byte() {
+        let rom = test_rom();
+        let dis = RomDisassembly::new(&rom);
+
+        let mut reassembled = Vec::new();
+        for bank in 0..dis.banks() {
+            for line in dis.bank_lines(bank) {
+                reassembled.extend_from_slice(line.bytes);
+            }
+        }
+
+        assert_eq!(reassembled, rom);
+    }
+}
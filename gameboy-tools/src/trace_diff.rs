@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+/// Where two instruction traces first disagree
+#[derive(Debug)]
+pub struct Divergence {
+    /// 1-based line number of the first differing line
+    pub line: usize,
+    /// None when that trace ended first
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    /// `NAME:value` fields whose values differ, e.g. `F` or `PCMEM`
+    pub fields: Vec<String>,
+    /// Matching lines leading up to the divergence
+    pub context: Vec<String>,
+}
+
+/// Compare two traces line by line, e.g. a Gameboy Doctor reference log
+/// against our own, keeping up to `context` of the preceding lines
+pub fn first_divergence<E: BufRead, A: BufRead>(
+    expected: E,
+    actual: A,
+    context: usize,
+) -> io::Result<Option<Divergence>> {
+    let mut expected = expected.lines();
+    let mut actual = actual.lines();
+    let mut previous = VecDeque::with_capacity(context + 1);
+    let mut line = 0;
+
+    loop {
+        line += 1;
+
+        let e = expected.next().transpose()?;
+        let a = actual.next().transpose()?;
+
+        let (e, a) = match (e, a) {
+            (None, None) => return Ok(None),
+            (Some(e), Some(a)) if e.trim_end() == a.trim_end() => {
+                previous.push_back(e);
+                if previous.len() > context {
+                    previous.pop_front();
+                }
+                continue;
+            }
+            (e, a) => (e, a),
+        };
+
+        let fields = match (&e, &a) {
+            (Some(e), Some(a)) => differing_fields(e, a),
+            _ => Vec::new(),
+        };
+
+        return Ok(Some(Divergence {
+            line,
+            expected: e,
+            actual: a,
+            fields,
+            context: previous.into_iter().collect(),
+        }));
+    }
+}
+
+/// Names of the `NAME:value` fields which differ between two lines
+fn differing_fields(expected: &str, actual: &str) -> Vec<String> {
+    let fields = |line: &str| -> Vec<(String, String)> {
+        line.split_whitespace()
+            .filter_map(|field| {
+                let mut parts = field.splitn(2, ':');
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect()
+    };
+
+    let actual = fields(actual);
+
+    fields(expected)
+        .into_iter()
+        .filter(|(name, value)| {
+            actual
+                .iter()
+                .find(|(n, _)| n == name)
+                .is_none_or(|(_, v)| v != value)
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCE: &str = "\
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,13,02,CE
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0213 PCMEM:AF,21,FF,DF
+A:00 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0214 PCMEM:21,FF,DF,0E
+";
+
+    #[test]
+    fn identical() {
+        let divergence = first_divergence(REFERENCE.as_bytes(), REFERENCE.as_bytes(), 2).unwrap();
+        assert!(divergence.is_none());
+    }
+
+    #[test]
+    fn reports_first_difference() {
+        let ours = REFERENCE.replace("A:00 F:80", "A:00 F:C0");
+        let divergence = first_divergence(REFERENCE.as_bytes(), ours.as_bytes(), 2)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(divergence.line, 4);
+        assert_eq!(divergence.fields, vec!["F"]);
+        assert_eq!(divergence.context.len(), 2);
+        assert!(divergence.context[1].contains("PC:0213"));
+    }
+
+    #[test]
+    fn shorter_trace() {
+        let ours: String = REFERENCE
+            .lines()
+            .take(2)
+            .map(|l| format!("{}\n", l))
+            .collect();
+        let divergence = first_divergence(REFERENCE.as_bytes(), ours.as_bytes(), 0)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(divergence.line, 3);
+        assert!(divergence.actual.is_none());
+        assert!(divergence.context.is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+/// The CPU's view of the rest of the machine. `Memory` is the real one, but
+/// `Cpu` & `Instruction` work with anything implementing this, e.g. a flat
+/// RAM for testing the CPU in isolation.
+///
+/// Each `read`, `write`, `fetch` & `tick` takes one M-cycle (4 clock cycles),
+/// so an implementation can advance anything else on the bus in step with
+/// the CPU.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// An M-cycle without a memory access, e.g. an internal delay
+    fn tick(&mut self);
+
+    /// Look at memory without any side effects or time passing, for decoding
+    /// & debugging
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Opcode & operand fetch
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    /// Little endian, low byte first
+    #[inline]
+    fn read16(&mut self, addr: u16) -> u16 {
+        let low = self.read(addr);
+        let high = self.read(addr.wrapping_add(1));
+
+        (u16::from(high) << 8) | u16::from(low)
+    }
+
+    #[inline]
+    fn write16(&mut self, addr: u16, val: u16) {
+        self.write(addr, (val & 0x00FF) as u8);
+        self.write(addr.wrapping_add(1), ((val & 0xFF00) >> 8) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bus;
+    use crate::cpu::Cpu;
+    use crate::interrupt::Interrupt;
+
+    struct Ram {
+        bytes: Vec<u8>,
+        mcycles: usize,
+    }
+
+    impl Bus for Ram {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.mcycles += 1;
+            self.bytes[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.mcycles += 1;
+            self.bytes[addr as usize] = val;
+        }
+
+        fn tick(&mut self) {
+            self.mcycles += 1;
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.bytes[addr as usize]
+        }
+    }
+
+    #[test]
+    fn custom_bus() {
+        let mut ram = Ram {
+            bytes: vec![0; 0x10000],
+            mcycles: 0,
+        };
+        // call $0200 / ... / ret
+        ram.bytes[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        ram.bytes[0x200] = 0xC9;
+
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xD000;
+
+        assert_eq!(cpu.cycle(&mut ram, false), 24);
+        assert_eq!((cpu.pc, ram.mcycles), (0x200, 6));
+        assert_eq!(&ram.bytes[0xCFFE..0xD000], &[0x03, 0x01]);
+
+        assert_eq!(cpu.cycle(&mut ram, false), 16);
+        assert_eq!((cpu.pc, cpu.sp, ram.mcycles), (0x103, 0xD000, 10));
+
+        assert!(cpu.interrupt(&mut ram, Interrupt::Timer));
+        assert_eq!((cpu.pc, ram.mcycles), (0x50, 15));
+        assert!(!cpu.interrupts);
+    }
+
+    #[test]
+    fn internal_cycles() {
+        let mut ram = Ram {
+            bytes: vec![0; 0x10000],
+            mcycles: 0,
+        };
+        // inc bc / add sp,1 / jr +0 / jp $0100
+        ram.bytes[0x100..0x108].copy_from_slice(&[0x03, 0xE8, 0x01, 0x18, 0x00, 0xC3, 0x00, 0x01]);
+
+        let mut cpu = Cpu::new();
+        let mut mcycles = 0;
+        for &expected in &[2, 4, 3, 4] {
+            let cycles = cpu.cycle(&mut ram, false);
+            mcycles += expected;
+            assert_eq!((usize::from(cycles), ram.mcycles), (expected * 4, mcycles));
+        }
+        assert_eq!(cpu.pc, 0x100);
+    }
+}
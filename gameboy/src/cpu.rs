@@ -1,6 +1,8 @@
+use crate::bus::Bus;
 use crate::instruction::Instruction;
 use crate::interrupt;
-use crate::memory::Memory;
+use crate::model::Model;
+use crate::state::{StateError, StateReader, StateWriter};
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct Cpu {
@@ -67,7 +69,88 @@ impl Cpu {
         Cpu::default()
     }
 
-    pub fn cycle(&mut self, mem: &mut Memory, debug: bool) -> u8 {
+    /// Register state left behind by the boot ROM of `model`. The DMG & MGB
+    /// boot ROMs leave H & C set unless the header checksum byte is zero.
+    pub fn post_boot(model: Model, header_checksum: u8) -> Cpu {
+        let dmg_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::Dmg => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::Agb => (0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
+
+        Cpu {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            ..Cpu::default()
+        }
+    }
+
+    /// Register state at power on, before a boot ROM has run
+    pub fn power_on() -> Cpu {
+        Cpu {
+            pc: 0x0000,
+            sp: 0x0000,
+            a: 0x00,
+            b: 0x00,
+            c: 0x00,
+            d: 0x00,
+            e: 0x00,
+            f: 0x00,
+            h: 0x00,
+            l: 0x00,
+            interrupts: false,
+            jumped: false,
+            halted: false,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.pc);
+        state.u16(self.sp);
+        for reg in [self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l].iter() {
+            state.u8(*reg);
+        }
+        state.bool(self.interrupts);
+        state.bool(self.jumped);
+        state.bool(self.halted);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pc = state.u16()?;
+        self.sp = state.u16()?;
+        for reg in [
+            &mut self.a,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.f,
+            &mut self.h,
+            &mut self.l,
+        ]
+        .iter_mut()
+        {
+            **reg = state.u8()?;
+        }
+        self.interrupts = state.bool()?;
+        self.jumped = state.bool()?;
+        self.halted = state.bool()?;
+
+        Ok(())
+    }
+
+    pub fn cycle<B: Bus>(&mut self, mem: &mut B, debug: bool) -> u8 {
         if self.pc == 0xC303 {
             println!("Got here");
         }
@@ -76,7 +159,18 @@ impl Cpu {
             return 8;
         }
 
-        let instr = Instruction::read(mem, self.pc);
+        // Opcode & operand fetch, one M-cycle per byte. The opcode alone
+        // decides how many operand bytes follow.
+        let mut bytes = [mem.fetch(self.pc), 0, 0];
+        let mut instr = Instruction::decode(&bytes);
+
+        let size = Instruction::mem_size(&instr);
+        if size > 1 {
+            for i in 1..size {
+                bytes[i as usize] = mem.fetch(self.pc.wrapping_add(i));
+            }
+            instr = Instruction::decode(&bytes);
+        }
 
         if debug {
             println!("Instruction: {:?}", &instr);
@@ -86,7 +180,7 @@ impl Cpu {
 
         // If we jumped we shouldn't skip over current instr
         if !self.jumped {
-            self.pc += Instruction::mem_size(&instr);
+            self.pc = self.pc.wrapping_add(Instruction::mem_size(&instr));
         }
 
         self.jumped = false;
@@ -94,7 +188,9 @@ impl Cpu {
         cycles
     }
 
-    pub fn interrupt(&mut self, mem: &mut Memory, int: interrupt::Interrupt) -> bool {
+    /// Returns true if the interrupt was serviced, taking 20 cycles. Clearing
+    /// its request flag is left to the caller.
+    pub fn interrupt<B: Bus>(&mut self, mem: &mut B, int: interrupt::Interrupt) -> bool {
         self.halted = false;
 
         if !self.interrupts {
@@ -119,18 +215,18 @@ impl Cpu {
             }
         };
 
-        // Push current pc onto stack, and reset pc to targetpc
-        self.sp -= 2;
-        mem.set16(self.sp, self.pc);
+        // Two idle cycles, push current pc onto stack, and reset pc to targetpc
+        mem.tick();
+        mem.tick();
+        self.push(mem, self.pc);
 
         self.pc = targetpc;
-
-        interrupt::reset_interrupt(int, mem);
+        mem.tick();
 
         // Further interrupts are disabled until re-enabled (RETI / EI)
         self.disable_interrupts();
 
-        int == interrupt::Interrupt::VBlank
+        true
     }
 
     pub fn set(&mut self, reg: CpuRegister, val: u8) {
@@ -235,18 +331,34 @@ impl Cpu {
         self.jumped = true;
     }
 
-    pub fn rjump(&mut self, offset: i8) {
+    pub fn rjump(&mut self, offset: i16) {
         self.pc = (i32::from(self.pc) + i32::from(offset)) as u16;
         self.jumped = true;
     }
 
-    pub fn ret(&mut self, mem: &Memory) {
-        let newpc = mem.get16(self.sp);
+    pub fn ret<B: Bus>(&mut self, mem: &mut B) {
+        let newpc = self.pop(mem);
 
-        self.sp = self.sp.wrapping_add(2);
+        // Setting pc takes an extra cycle
+        mem.tick();
         self.jump(newpc);
     }
 
+    /// Push onto the stack, high byte first
+    pub fn push<B: Bus>(&mut self, mem: &mut B, val: u16) {
+        self.sp = self.sp.wrapping_sub(1);
+        mem.write(self.sp, ((val & 0xFF00) >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        mem.write(self.sp, (val & 0x00FF) as u8);
+    }
+
+    pub fn pop<B: Bus>(&mut self, mem: &mut B) -> u16 {
+        let val = mem.read16(self.sp);
+        self.sp = self.sp.wrapping_add(2);
+
+        val
+    }
+
     pub fn enable_interrupts(&mut self) {
         self.interrupts = true;
     }
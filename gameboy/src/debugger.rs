@@ -0,0 +1,551 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::cpu::Cpu;
+pub use crate::interrupt::Interrupt;
+use crate::memory::MemAccess;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub fn value(self, cpu: &Cpu) -> u16 {
+        let pair = |high: u8, low: u8| (u16::from(high) << 8) | u16::from(low);
+
+        match self {
+            Register::A => u16::from(cpu.a),
+            Register::F => u16::from(cpu.f),
+            Register::B => u16::from(cpu.b),
+            Register::C => u16::from(cpu.c),
+            Register::D => u16::from(cpu.d),
+            Register::E => u16::from(cpu.e),
+            Register::H => u16::from(cpu.h),
+            Register::L => u16::from(cpu.l),
+            Register::AF => pair(cpu.a, cpu.f),
+            Register::BC => pair(cpu.b, cpu.c),
+            Register::DE => pair(cpu.d, cpu.e),
+            Register::HL => pair(cpu.h, cpu.l),
+            Register::SP => cpu.sp,
+            Register::PC => cpu.pc,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Register, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" => Ok(Register::A),
+            "f" => Ok(Register::F),
+            "b" => Ok(Register::B),
+            "c" => Ok(Register::C),
+            "d" => Ok(Register::D),
+            "e" => Ok(Register::E),
+            "h" => Ok(Register::H),
+            "l" => Ok(Register::L),
+            "af" => Ok(Register::AF),
+            "bc" => Ok(Register::BC),
+            "de" => Ok(Register::DE),
+            "hl" => Ok(Register::HL),
+            "sp" => Ok(Register::SP),
+            "pc" => Ok(Register::PC),
+            _ => Err(format!("Unknown register {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    // Only break while this ROM bank is mapped at addr, None for any bank
+    pub bank: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    // Stop execution
+    Break,
+    // Keep running, logging the access for `take_watch_hits`
+    Record,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+    pub action: WatchAction,
+}
+
+/// An access which matched a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    // Start of the instruction which made the access
+    pub pc: u16,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    pub write: bool,
+    // Total cycles when the instruction started
+    pub cycles: u64,
+}
+
+// Oldest recorded hits are dropped past this
+const MAX_WATCH_HITS: usize = 4096;
+
+impl Watchpoint {
+    fn matches(&self, access: &MemAccess) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !access.write,
+            WatchKind::Write => access.write,
+            WatchKind::Access => true,
+        };
+
+        kind && access.addr >= self.start && access.addr <= self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Condition {
+    reg: Register,
+    value: u16,
+    // Only break when the condition becomes true
+    was_true: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint { pc: u16, bank: u8 },
+    Watchpoint(WatchHit),
+    Condition { reg: Register, value: u16 },
+    Interrupt(Interrupt),
+    Step,
+    Pause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Run,
+    // Instructions left to execute before stopping
+    Step(u32),
+    // Stop when pc reaches addr, used to step over calls
+    RunTo(u16),
+    // Stop once sp has risen above its value when the function was entered
+    Finish(u16),
+}
+
+/// Breakpoints, watchpoints & step control, checked by `GameBoy::cycle`
+/// around each instruction
+#[derive(Debug)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    conditions: Vec<Condition>,
+    interrupts: Vec<Interrupt>,
+    watch_hits: VecDeque<WatchHit>,
+
+    mode: RunMode,
+    pause: bool,
+    // pc of the last stop before an instruction, so resuming doesn't
+    // immediately hit the same breakpoint
+    resume_pc: Option<u16>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            interrupts: Vec::new(),
+            watch_hits: VecDeque::new(),
+            mode: RunMode::Run,
+            pause: false,
+            resume_pc: None,
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16, bank: Option<u8>) {
+        let bp = Breakpoint { addr, bank };
+        if !self.breakpoints.contains(&bp) {
+            self.breakpoints.push(bp);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|bp| bp.addr != addr);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Watch `start..=end`, swapping them if they're the wrong way round
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind, action: WatchAction) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.watchpoints.push(Watchpoint {
+            start,
+            end,
+            kind,
+            action,
+        });
+    }
+
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.watchpoints
+            .retain(|wp| wp.start != start || wp.end != end);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Accesses matched by `WatchAction::Record` watchpoints since the last
+    /// call, oldest first
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.watch_hits.drain(..).collect()
+    }
+
+    /// Break when `reg` becomes equal to `value`
+    pub fn add_condition(&mut self, reg: Register, value: u16) {
+        self.conditions.push(Condition {
+            reg,
+            value,
+            was_true: false,
+        });
+    }
+
+    pub fn clear_conditions(&mut self) {
+        self.conditions.clear();
+    }
+
+    /// Break after the CPU services `int`
+    pub fn set_break_on_interrupt(&mut self, int: Interrupt, enabled: bool) {
+        self.interrupts.retain(|&i| i != int);
+        if enabled {
+            self.interrupts.push(int);
+        }
+    }
+
+    /// Stop before the next instruction
+    pub fn pause(&mut self) {
+        self.pause = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.mode = RunMode::Run;
+    }
+
+    /// Execute `count` instructions then stop
+    pub fn step(&mut self, count: u32) {
+        self.mode = RunMode::Step(count);
+    }
+
+    /// Run until pc reaches `addr`
+    pub fn run_to(&mut self, addr: u16) {
+        self.mode = RunMode::RunTo(addr);
+    }
+
+    /// Run until the current function returns, given the current sp
+    pub fn finish(&mut self, sp: u16) {
+        self.mode = RunMode::Finish(sp);
+    }
+
+    pub(crate) fn has_watchpoints(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    /// Checked before executing the instruction at pc
+    pub(crate) fn before_step(&mut self, cpu: &Cpu, bank: u8) -> Option<StopReason> {
+        let pc = cpu.pc;
+        let resumed = self.resume_pc.take() == Some(pc);
+
+        let mut stop = None;
+
+        for cond in self.conditions.iter_mut() {
+            let is_true = cond.reg.value(cpu) == cond.value;
+            if is_true && !cond.was_true {
+                stop = Some(StopReason::Condition {
+                    reg: cond.reg,
+                    value: cond.value,
+                });
+            }
+            cond.was_true = is_true;
+        }
+
+        if !resumed {
+            let hit = self
+                .breakpoints
+                .iter()
+                .any(|bp| bp.addr == pc && bp.bank.unwrap_or(bank) == bank);
+            if hit {
+                stop = Some(StopReason::Breakpoint { pc, bank });
+            }
+        }
+
+        match self.mode {
+            RunMode::Run => {}
+            RunMode::Step(0) => stop = stop.or(Some(StopReason::Step)),
+            RunMode::Step(n) => self.mode = RunMode::Step(n - 1),
+            RunMode::RunTo(addr) if addr == pc => stop = stop.or(Some(StopReason::Step)),
+            RunMode::Finish(sp) if cpu.sp > sp => stop = stop.or(Some(StopReason::Step)),
+            _ => {}
+        }
+
+        if std::mem::replace(&mut self.pause, false) {
+            stop = stop.or(Some(StopReason::Pause));
+        }
+
+        if stop.is_some() {
+            self.resume_pc = Some(pc);
+        }
+        self.stopped(stop)
+    }
+
+    /// Checked after the instruction starting at `pc` & any interrupt it
+    /// triggered
+    pub(crate) fn after_step(
+        &mut self,
+        pc: u16,
+        cycles: u64,
+        accesses: &[MemAccess],
+        interrupt: Option<Interrupt>,
+    ) -> Option<StopReason> {
+        let mut stop = None;
+
+        for access in accesses {
+            // Overlapping watchpoints can both break & record the same access
+            let (mut brk, mut record) = (false, false);
+            for wp in self.watchpoints.iter().filter(|wp| wp.matches(access)) {
+                match wp.action {
+                    WatchAction::Break => brk = true,
+                    WatchAction::Record => record = true,
+                }
+            }
+
+            let hit = WatchHit {
+                pc,
+                addr: access.addr,
+                old: access.old,
+                new: access.value,
+                write: access.write,
+                cycles,
+            };
+
+            if brk {
+                stop = stop.or(Some(StopReason::Watchpoint(hit)));
+            }
+            if record {
+                if self.watch_hits.len() == MAX_WATCH_HITS {
+                    self.watch_hits.pop_front();
+                }
+                self.watch_hits.push_back(hit);
+            }
+        }
+
+        if let Some(int) = interrupt {
+            if self.interrupts.contains(&int) {
+                stop = stop.or(Some(StopReason::Interrupt(int)));
+            }
+        }
+
+        self.stopped(stop)
+    }
+
+    fn stopped(&mut self, stop: Option<StopReason>) -> Option<StopReason> {
+        if stop.is_some() {
+            self.mode = RunMode::Run;
+        }
+
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::Cpu;
+    use crate::debugger::{Debugger, Register, StopReason, WatchAction, WatchHit, WatchKind};
+    use crate::memory::MemAccess;
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = Cpu::new();
+
+        debugger.add_breakpoint(0x0150, None);
+        debugger.add_breakpoint(0x4000, Some(2));
+
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+
+        cpu.pc = 0x0150;
+        assert_eq!(
+            debugger.before_step(&cpu, 0),
+            Some(StopReason::Breakpoint {
+                pc: 0x0150,
+                bank: 0
+            })
+        );
+        // Resuming executes the instruction at the breakpoint
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+
+        cpu.pc = 0x4000;
+        assert_eq!(debugger.before_step(&cpu, 1), None);
+        assert_eq!(
+            debugger.before_step(&cpu, 2),
+            Some(StopReason::Breakpoint {
+                pc: 0x4000,
+                bank: 2
+            })
+        );
+    }
+
+    #[test]
+    fn stepping_and_conditions() {
+        let mut debugger = Debugger::new();
+        let mut cpu = Cpu::new();
+
+        debugger.step(2);
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+        assert_eq!(debugger.before_step(&cpu, 0), Some(StopReason::Step));
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+
+        debugger.add_condition(Register::BC, 0x1234);
+        cpu.b = 0x12;
+        cpu.c = 0x34;
+        assert_eq!(
+            debugger.before_step(&cpu, 0),
+            Some(StopReason::Condition {
+                reg: Register::BC,
+                value: 0x1234
+            })
+        );
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0xC000, 0xC0FF, WatchKind::Write, WatchAction::Break);
+        debugger.add_watchpoint(0xFF80, 0xFF80, WatchKind::Access, WatchAction::Record);
+
+        let read = MemAccess {
+            addr: 0xC010,
+            old: 1,
+            value: 1,
+            write: false,
+        };
+        let write = MemAccess {
+            value: 2,
+            write: true,
+            ..read
+        };
+        let hram = MemAccess {
+            addr: 0xFF80,
+            ..read
+        };
+
+        assert_eq!(debugger.after_step(0x0150, 100, &[read, hram], None), None);
+        assert_eq!(
+            debugger.after_step(0x0151, 108, &[read, write], None),
+            Some(StopReason::Watchpoint(WatchHit {
+                pc: 0x0151,
+                addr: 0xC010,
+                old: 1,
+                new: 2,
+                write: true,
+                cycles: 108,
+            }))
+        );
+
+        let hits = debugger.take_watch_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            (hits[0].pc, hits[0].addr, hits[0].write),
+            (0x0150, 0xFF80, false)
+        );
+        assert!(debugger.take_watch_hits().is_empty());
+
+        // A write matching both breaks & is recorded
+        debugger.add_watchpoint(0xC000, 0xFFFF, WatchKind::Write, WatchAction::Record);
+        assert!(matches!(
+            debugger.after_step(0x0152, 116, &[write], None),
+            Some(StopReason::Watchpoint(_))
+        ));
+        assert_eq!(debugger.take_watch_hits().len(), 1);
+    }
+
+    #[test]
+    fn backwards_watchpoint() {
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0xC0FF, 0xC000, WatchKind::Write, WatchAction::Break);
+        let wp = debugger.watchpoints()[0];
+        assert_eq!((wp.start, wp.end), (0xC000, 0xC0FF));
+
+        debugger.remove_watchpoint(0xC0FF, 0xC000);
+        assert!(debugger.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn watchpoint_then_breakpoint() {
+        let mut debugger = Debugger::new();
+        let mut cpu = Cpu::new();
+        debugger.add_watchpoint(0xC000, 0xC000, WatchKind::Write, WatchAction::Break);
+        debugger.add_breakpoint(0x0151, None);
+
+        let write = MemAccess {
+            addr: 0xC000,
+            old: 0,
+            value: 1,
+            write: true,
+        };
+
+        cpu.pc = 0x0150;
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+        assert!(matches!(
+            debugger.after_step(0x0150, 0, &[write], None),
+            Some(StopReason::Watchpoint(_))
+        ));
+
+        // The watchpoint stopping doesn't skip the breakpoint at the next pc
+        cpu.pc = 0x0151;
+        assert_eq!(
+            debugger.before_step(&cpu, 0),
+            Some(StopReason::Breakpoint {
+                pc: 0x0151,
+                bank: 0
+            })
+        );
+        assert_eq!(debugger.before_step(&cpu, 0), None);
+    }
+}
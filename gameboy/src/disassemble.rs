@@ -1,16 +1,102 @@
+use std::collections::HashMap;
+
 use crate::instruction::Instruction;
 use crate::memory::Memory;
 
 use serde::Serialize;
 
+const BANK_SIZE: usize = 0x4000;
+
 #[derive(Debug, Serialize)]
 pub struct Disassembly {
     pub addr: u16,
+    pub bank: u8,
     pub data_len: u8,
     pub data: Vec<u8>,
     pub desc: String,
+    pub label: Option<String>,
+}
+
+/// Names for (bank, address) pairs, as written by RGBDS (`rgblink -n`) &
+/// no$gmb in `.sym` files
+#[derive(Debug, Default)]
+pub struct Symbols {
+    names: HashMap<(u8, u16), String>,
+    // First name seen for an address in any bank, for references where the
+    // bank isn't known
+    any_bank: HashMap<u16, String>,
 }
 
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Parse `bb:aaaa Name` lines, ignoring `;` comments and anything else
+    /// which doesn't look like a symbol
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("");
+            let mut parts = line.split_whitespace();
+
+            let (location, name) = match (parts.next(), parts.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => continue,
+            };
+
+            let mut location = location.split(':');
+            let bank = location.next().and_then(|b| u8::from_str_radix(b, 16).ok());
+            let addr = location
+                .next()
+                .and_then(|a| u16::from_str_radix(a, 16).ok());
+
+            if let (Some(bank), Some(addr)) = (bank, addr) {
+                symbols.insert(bank, addr, name);
+            }
+        }
+
+        symbols
+    }
+
+    pub fn insert(&mut self, bank: u8, addr: u16, name: &str) {
+        self.names.insert((bank, addr), name.to_string());
+        self.any_bank
+            .entry(addr)
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The name defined at exactly this bank & address
+    pub fn get(&self, bank: u8, addr: u16) -> Option<&str> {
+        self.names.get(&(bank, addr)).map(|n| n.as_str())
+    }
+
+    /// Name for `addr` as referenced from code in `bank`. Only the switchable
+    /// ROM area depends on the bank, from bank 0 it could be any of them.
+    pub fn lookup(&self, bank: u8, addr: u16) -> Option<&str> {
+        let exact = match addr {
+            0x4000..=0x7FFF => self.get(bank, addr),
+            _ => self.get(0, addr),
+        };
+
+        match exact {
+            Some(name) => Some(name),
+            None if bank == 0 || addr >= 0x8000 => self.any_bank.get(&addr).map(|n| n.as_str()),
+            None => None,
+        }
+    }
+}
+
+/// Disassemble whatever is currently mapped between `start` & `end`
 pub fn disassemble(start: u16, end: u16, mem: &Memory) -> Vec<Disassembly> {
     let mut output = Vec::new();
 
@@ -22,9 +108,11 @@ pub fn disassemble(start: u16, end: u16, mem: &Memory) -> Vec<Disassembly> {
 
         output.push(Disassembly {
             addr: addr,
+            bank: mem.rom_bank(addr),
             data_len: len as u8,
             data: mem.clone_bytes(addr, len),
-            desc: format!("{:?}", instr),
+            desc: instr.format(Some(addr), &|_| None),
+            label: None,
         });
 
         addr += len;
@@ -32,3 +120,130 @@ pub fn disassemble(start: u16, end: u16, mem: &Memory) -> Vec<Disassembly> {
 
     output
 }
+
+/// Disassemble `start` to `end` of ROM bank `bank`, straight from the ROM
+/// image rather than through the current mapping. Addresses are as the CPU
+/// sees them, 0x0000-0x3FFF for bank 0 & 0x4000-0x7FFF for the others.
+///
+/// Instructions never run over a label, any bytes before it are shown as
+/// data instead so a table in the middle of code doesn't throw the rest of
+/// the listing out of step.
+pub fn disassemble_bank(
+    rom: &[u8],
+    bank: u8,
+    start: u16,
+    end: u16,
+    symbols: &Symbols,
+) -> Vec<Disassembly> {
+    let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let bank_end = base + (BANK_SIZE as u16 - 1);
+    let end = end.min(bank_end);
+
+    let offset = |addr: u16| usize::from(bank) * BANK_SIZE + usize::from(addr - base);
+    let symbol = |addr: u16| symbols.lookup(bank, addr).map(|n| n.to_string());
+
+    let mut output = Vec::new();
+
+    if start < base || start > end {
+        return output;
+    }
+
+    let mut addr = start;
+
+    loop {
+        let pos = offset(addr);
+        if pos >= rom.len() {
+            break;
+        }
+
+        // Don't read past the end of the bank, the next one isn't mapped after it
+        let available = (usize::from(bank_end - addr) + 1).min(rom.len() - pos);
+        let bytes = &rom[pos..pos + available.min(3)];
+
+        let instr = Instruction::decode(bytes);
+        let (mut len, mut desc) = match (bytes[0], instr) {
+            // The byte after STOP is skipped
+            (0x10, _) => (2, "STOP".to_string()),
+            (_, Instruction::ILLEGAL) => (1, data_directive(&bytes[..1])),
+            (_, instr) => (
+                Instruction::mem_size(&instr),
+                instr.format(Some(addr), &symbol),
+            ),
+        };
+
+        let overrun = (1..len)
+            .find(|&i| usize::from(i) >= available || symbols.get(bank, addr + i).is_some());
+        if let Some(split) = overrun {
+            len = split;
+            desc = data_directive(&bytes[..usize::from(len)]);
+        }
+
+        output.push(Disassembly {
+            addr,
+            bank,
+            data_len: len as u8,
+            data: bytes[..usize::from(len)].to_vec(),
+            desc,
+            label: symbols.get(bank, addr).map(|n| n.to_string()),
+        });
+
+        match addr.checked_add(len) {
+            Some(next) if next <= end => addr = next,
+            _ => break,
+        }
+    }
+
+    output
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    format!("DB {}", bytes.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_symbols() {
+        let symbols = Symbols::parse(
+            "; File generated by rgblink\n\
+             00:0150 Start\n\
+             01:4000 Bank1Code ; trailing comment\n\
+             02:4000 Bank2Code\n\
+             00:C000 wPlayerX\n\
+             garbage\n",
+        );
+
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.get(0, 0x0150), Some("Start"));
+        assert_eq!(symbols.lookup(2, 0x4000), Some("Bank2Code"));
+        assert_eq!(symbols.lookup(3, 0x4000), None);
+        // From bank 0 the switchable bank is unknown
+        assert_eq!(symbols.lookup(0, 0x4000), Some("Bank1Code"));
+        assert_eq!(symbols.lookup(5, 0xC000), Some("wPlayerX"));
+    }
+
+    #[test]
+    fn bank_disassembly() {
+        let mut rom = vec![0; 0x8000];
+        // Bank 1: call a label, then a table the label points into
+        rom[0x4000..0x4007].copy_from_slice(&[0xCD, 0x05, 0x40, 0x2A, 0x21, 0xAA, 0xBB]);
+
+        let mut symbols = Symbols::new();
+        symbols.insert(1, 0x4000, "Entry");
+        symbols.insert(1, 0x4005, "Table");
+
+        let listing = disassemble_bank(&rom, 1, 0x4000, 0x4006, &symbols);
+        let desc: Vec<&str> = listing.iter().map(|d| d.desc.as_str()).collect();
+
+        assert_eq!(
+            desc,
+            vec!["CALL Table", "LD A,[HL+]", "DB $21", "XOR A,D", "CP A,E"]
+        );
+        assert_eq!(listing[0].label.as_deref(), Some("Entry"));
+        assert_eq!(listing[3].label.as_deref(), Some("Table"));
+        assert!(listing.iter().all(|d| d.bank == 1));
+    }
+}
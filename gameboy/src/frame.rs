@@ -0,0 +1,214 @@
+//! Completed frames for frontends, in the pixel format they want. The Gpu
+//! draws into its own buffer line by line, & each finished frame is
+//! converted into a `Frame`, so what's shown is never partly drawn.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::gpu::{GB_HSIZE, GB_VSIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Four bytes per pixel, R, G, B & A
+    Rgba8888,
+    /// 0xAARRGGBB words, as minifb & most window systems take them
+    Argb32,
+    /// 5 bits red, 6 green & 5 blue
+    Rgb565,
+    /// The DMG's 2 bit shades after palettes are applied, 0 being lightest.
+    /// Always the Game Boy's own screen, without any SGB border.
+    Shades,
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PixelFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "rgba8888" => Ok(PixelFormat::Rgba8888),
+            "argb32" => Ok(PixelFormat::Argb32),
+            "rgb565" => Ok(PixelFormat::Rgb565),
+            "shades" => Ok(PixelFormat::Shades),
+            _ => Err(format!("Unknown pixel format {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PixelFormat::Rgba8888 => "rgba8888",
+            PixelFormat::Argb32 => "argb32",
+            PixelFormat::Rgb565 => "rgb565",
+            PixelFormat::Shades => "shades",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pixels {
+    Rgba8888(Vec<u8>),
+    Argb32(Vec<u32>),
+    Rgb565(Vec<u16>),
+    Shades(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    pixels: Pixels,
+}
+
+impl Frame {
+    /// A blank Game Boy sized frame
+    pub fn new(format: PixelFormat) -> Frame {
+        let size = GB_HSIZE * GB_VSIZE;
+        let pixels = match format {
+            PixelFormat::Rgba8888 => Pixels::Rgba8888(vec![255; size * 4]),
+            PixelFormat::Argb32 => Pixels::Argb32(vec![0xFFFF_FFFF; size]),
+            PixelFormat::Rgb565 => Pixels::Rgb565(vec![0xFFFF; size]),
+            PixelFormat::Shades => Pixels::Shades(vec![0; size]),
+        };
+
+        Frame {
+            width: GB_HSIZE,
+            height: GB_VSIZE,
+            pixels,
+        }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        match self.pixels {
+            Pixels::Rgba8888(_) => PixelFormat::Rgba8888,
+            Pixels::Argb32(_) => PixelFormat::Argb32,
+            Pixels::Rgb565(_) => PixelFormat::Rgb565,
+            Pixels::Shades(_) => PixelFormat::Shades,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &Pixels {
+        &self.pixels
+    }
+
+    pub fn rgba8888(&self) -> Option<&[u8]> {
+        match &self.pixels {
+            Pixels::Rgba8888(pixels) => Some(pixels),
+            _ => None,
+        }
+    }
+
+    pub fn argb32(&self) -> Option<&[u32]> {
+        match &self.pixels {
+            Pixels::Argb32(pixels) => Some(pixels),
+            _ => None,
+        }
+    }
+
+    pub fn rgb565(&self) -> Option<&[u16]> {
+        match &self.pixels {
+            Pixels::Rgb565(pixels) => Some(pixels),
+            _ => None,
+        }
+    }
+
+    pub fn shades(&self) -> Option<&[u8]> {
+        match &self.pixels {
+            Pixels::Shades(pixels) => Some(pixels),
+            _ => None,
+        }
+    }
+
+    /// Start of the pixel data, for handing to JavaScript & the like
+    pub fn as_ptr(&self) -> *const u8 {
+        match &self.pixels {
+            Pixels::Rgba8888(pixels) | Pixels::Shades(pixels) => pixels.as_ptr(),
+            Pixels::Argb32(pixels) => pixels.as_ptr() as *const u8,
+            Pixels::Rgb565(pixels) => pixels.as_ptr() as *const u8,
+        }
+    }
+
+    /// Size of the pixel data in bytes
+    pub fn byte_len(&self) -> usize {
+        match &self.pixels {
+            Pixels::Rgba8888(pixels) | Pixels::Shades(pixels) => pixels.len(),
+            Pixels::Argb32(pixels) => pixels.len() * 4,
+            Pixels::Rgb565(pixels) => pixels.len() * 2,
+        }
+    }
+
+    /// Convert a finished `width` x `height` RGBA screen, or take the
+    /// Game Boy screen's `shades`
+    pub(crate) fn capture(&mut self, rgba: &[u8], width: usize, height: usize, shades: &[u8]) {
+        let (width, height) = match self.pixels {
+            Pixels::Shades(_) => (GB_HSIZE, GB_VSIZE),
+            _ => (width, height),
+        };
+        self.width = width;
+        self.height = height;
+
+        let rgb = rgba.chunks_exact(4).map(|p| (p[0], p[1], p[2]));
+        match &mut self.pixels {
+            Pixels::Rgba8888(pixels) => {
+                pixels.clear();
+                pixels.extend_from_slice(rgba);
+            }
+            Pixels::Argb32(pixels) => {
+                pixels.clear();
+                pixels.extend(rgb.map(|(r, g, b)| {
+                    0xFF00_0000 | (u32::from(r) << 16) | (u32::from(g) << 8) | u32::from(b)
+                }));
+            }
+            Pixels::Rgb565(pixels) => {
+                pixels.clear();
+                pixels.extend(rgb.map(|(r, g, b)| {
+                    (u16::from(r >> 3) << 11) | (u16::from(g >> 2) << 5) | u16::from(b >> 3)
+                }));
+            }
+            Pixels::Shades(pixels) => {
+                pixels.clear();
+                pixels.extend_from_slice(shades);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let rgba = [0x12, 0x34, 0x56, 0xFF, 0xFF, 0x80, 0x08, 0xFF];
+        let shades = vec![2; GB_HSIZE * GB_VSIZE];
+
+        let mut frame = Frame::new(PixelFormat::Argb32);
+        frame.capture(&rgba, 2, 1, &shades);
+        assert_eq!(frame.argb32(), Some(&[0xFF12_3456, 0xFFFF_8008][..]));
+        assert_eq!((frame.width(), frame.height()), (2, 1));
+        assert_eq!(frame.byte_len(), 8);
+        assert_eq!(frame.rgba8888(), None);
+
+        let mut frame = Frame::new(PixelFormat::Rgb565);
+        frame.capture(&rgba, 2, 1, &shades);
+        assert_eq!(frame.rgb565(), Some(&[0x11AA, 0xFC01][..]));
+
+        let mut frame = Frame::new(PixelFormat::Rgba8888);
+        frame.capture(&rgba, 2, 1, &shades);
+        assert_eq!(frame.rgba8888(), Some(&rgba[..]));
+
+        let mut frame = Frame::new("shades".parse().unwrap());
+        frame.capture(&rgba, 2, 1, &shades);
+        assert_eq!(frame.shades(), Some(&shades[..]));
+        assert_eq!((frame.width(), frame.height()), (GB_HSIZE, GB_VSIZE));
+    }
+}
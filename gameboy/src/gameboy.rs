@@ -1,22 +1,87 @@
 pub use crate::cpu::Cpu;
-use crate::gpu::{Gpu, GpuDebugTrace, GpuMode};
-use crate::input::Input;
+use crate::debugger::{Debugger, StopReason};
+use crate::frame::{Frame, PixelFormat};
+use crate::gpu::{Gpu, GpuDebugTrace, GB_HSIZE, GB_VSIZE};
+use crate::input::{Button, Input};
+use crate::instruction::Instruction;
 use crate::interrupt;
 use crate::memory::Memory;
+use crate::model::Model;
+use crate::movie::{self, Held, InputChange, Movie, MovieStart};
+use crate::palette::Palettes;
+use crate::rewind::Rewind;
 use crate::rom::Cartridge;
+use crate::sgb::{Sgb, SGB_HSIZE, SGB_VSIZE};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::trace::Tracer;
+use std::io;
+
+const INTERRUPT_CYCLES: u8 = 20;
+
+/// Why one of the `run_*` methods returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStop {
+    FrameComplete,
+    CyclesElapsed,
+    /// The `run_until` predicate returned true
+    Condition,
+    Debugger(StopReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    /// Cycles executed, which may overshoot a target by part of an
+    /// instruction
+    pub cycles: u64,
+    /// Whether at least one frame completed
+    pub frame: bool,
+    pub stop: RunStop,
+}
 
 pub struct GameBoy {
     title: String,
+    model: Model,
     pub cpu: Cpu,
-    pub gpu: Gpu,
     pub mem: Memory,
+    sgb: Option<Sgb>,
+    debugger: Debugger,
+    tracer: Option<Tracer>,
+    // Why the tracer was dropped, until the frontend picks it up
+    tracer_error: Option<io::Error>,
+    rewind: Option<Rewind>,
+    recording: Option<Movie>,
+    // Movie being played & its next event
+    playback: Option<(Movie, usize)>,
+    // The last completed frame, while the Gpu draws the next
+    frame: Frame,
 
     pub steps: u64,
     pub cycles: u64,
+    /// Frames completed, including blank ones while the LCD is off
+    pub frames: u64,
 }
 
 impl GameBoy {
-    pub fn new(rom_contents: Vec<u8>) -> GameBoy {
+    pub fn new(rom_contents: Vec<u8>, model: Model) -> GameBoy {
+        GameBoy::build(rom_contents, model, None).expect("Only a boot ROM can be rejected")
+    }
+
+    /// Start from power on, running `boot_rom` (DMG, MGB, SGB or CGB image)
+    /// until it unmaps itself by writing to 0xFF50. Fails if the boot ROM is
+    /// the wrong size.
+    pub fn with_boot_rom(
+        rom_contents: Vec<u8>,
+        model: Model,
+        boot_rom: Vec<u8>,
+    ) -> Result<GameBoy, String> {
+        GameBoy::build(rom_contents, model, Some(boot_rom))
+    }
+
+    fn build(
+        rom_contents: Vec<u8>,
+        model: Model,
+        boot_rom: Option<Vec<u8>>,
+    ) -> Result<GameBoy, String> {
         let cartridge: Cartridge = Cartridge::load_rom(rom_contents);
 
         println!("ROM Type: {:?}", cartridge.mbc_type);
@@ -28,74 +93,564 @@ impl GameBoy {
             println!("RAM Size unsupported {}", cartridge.ram_size);
         }
 
-        GameBoy {
-            title: cartridge.game_title.clone(),
-            cpu: Cpu::new(),
-            gpu: Gpu::new(),
-            mem: Memory::new(cartridge),
+        let cpu = if boot_rom.is_some() {
+            Cpu::power_on()
+        } else {
+            Cpu::post_boot(model, cartridge.header_checksum)
+        };
+
+        let sgb = if model.is_sgb() && cartridge.sgb_support {
+            Some(Sgb::new())
+        } else {
+            None
+        };
+
+        let title = cartridge.game_title.clone();
+        let mut mem = Memory::new(model, cartridge, boot_rom)?;
+        if sgb.is_some() {
+            mem.input().enable_sgb();
+        }
+
+        let mut gb = GameBoy {
+            title,
+            model,
+            cpu,
+            mem,
+            sgb,
+            debugger: Debugger::new(),
+            tracer: None,
+            tracer_error: None,
+            rewind: None,
+            recording: None,
+            playback: None,
+            frame: Frame::new(PixelFormat::Rgba8888),
             steps: 0,
             cycles: 0,
-        }
+            frames: 0,
+        };
+        gb.capture_frame();
+        Ok(gb)
     }
 
     pub fn title(&self) -> &str {
         &self.title
     }
 
-    pub fn cycle(&mut self, debug: bool, debug_pc_panic: Option<u16>) -> bool {
-        if debug {
-            print_cpu_trace(&self.cpu, self.cycles, self.mem.get(0xFF40) & (1 << 7));
-            /*println!(
-                "State after {} total steps {} | Gpu PWR {} mode: {:?} elapsed {} ly {} stat {:X} lcdc {:X}",
-                self.steps,
-                self.cpu.print_state(),
-                self.gpu.debug_lcd_pwr,
-                self.gpu.mode,
-                self.gpu.mode_elapsed,
-                self.gpu.line,
-                self.mem.get(0xFF41),
-                self.mem.get(0xFF40),
-            );
-            println!(
-                "Cpu Flags: Z{} N{} H{} C{}",
-                self.cpu.z_flag(),
-                self.cpu.n_flag(),
-                self.cpu.h_flag(),
-                self.cpu.c_flag()
-            );*/
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Execute one instruction, servicing any pending interrupt afterwards.
+    /// Returns true on completing a frame (entering VBlank, or every 70224
+    /// cycles while the LCD is off), or why the debugger stopped. On a
+    /// breakpoint, condition, step or pause the instruction at pc has not yet
+    /// run; watchpoints & interrupts stop once it has.
+    pub fn cycle(&mut self, debug: bool) -> Result<bool, StopReason> {
+        let bank = self.mem.rom_bank(self.cpu.pc);
+        if let Some(reason) = self.debugger.before_step(&self.cpu, bank) {
+            return Err(reason);
         }
 
-        let cycles: u8 = self.cpu.cycle(&mut self.mem, debug);
-        self.cycles += cycles as u64;
+        if self.recording.is_some() || self.playback.is_some() {
+            self.update_movie();
+        }
 
-        if let Some(pc) = debug_pc_panic {
-            if self.cpu.pc == pc {
-                panic!("Reached panic-at-pc");
+        if let Some(tracer) = self.tracer.as_mut() {
+            if let Err(e) = tracer.trace(&self.cpu, &self.mem, self.cycles) {
+                self.tracer_error = Some(e);
+                self.tracer = None;
             }
         }
 
-        let old_mode = self.gpu.mode;
+        self.mem.set_access_log(self.debugger.has_watchpoints());
+        self.mem.clear_accesses();
 
-        self.gpu.cycle(&mut self.mem, cycles, self.cpu.halted);
+        let start_pc = self.cpu.pc;
+        let start_cycles = self.cycles;
 
-        let redraw_screen = old_mode != GpuMode::VBlank && self.gpu.mode == GpuMode::VBlank;
+        let cycles: u8 = self.cpu.cycle(&mut self.mem, debug);
+        self.cycles += cycles as u64;
 
-        self.mem.tick_timer(cycles);
+        // With M-cycle timing most of the instruction has already been ticked
+        // by its memory accesses
+        let ticked = self.mem.take_ticked();
+        self.mem.advance(cycles.saturating_sub(ticked));
 
+        let mut serviced = None;
         let int = interrupt::fetch_interrupt(&mut self.mem);
         if let Some(active) = int {
-            if self.cpu.interrupt(&mut self.mem, active) {}
+            if self.cpu.interrupt(&mut self.mem, active) {
+                interrupt::reset_interrupt(active, &mut self.mem);
+                let ticked = self.mem.take_ticked();
+                self.mem.advance(INTERRUPT_CYCLES.saturating_sub(ticked));
+                self.cycles += u64::from(INTERRUPT_CYCLES);
+                serviced = Some(active);
+            }
         }
 
         self.steps += 1;
 
-        redraw_screen
+        // A pending frame is reported by the next cycle once resumed
+        if let Some(reason) =
+            self.debugger
+                .after_step(start_pc, start_cycles, self.mem.accesses(), serviced)
+        {
+            return Err(reason);
+        }
+
+        let redraw_screen = self.mem.take_frame_ready();
+        if redraw_screen {
+            self.frames += 1;
+
+            let frames = self.frames;
+            if self
+                .playback
+                .as_ref()
+                .is_some_and(|(movie, _)| frames >= movie.end_frame)
+            {
+                self.playback = None;
+            }
+        }
+
+        if let Some(sgb) = self.sgb.as_mut() {
+            for command in self.mem.input().take_sgb_commands() {
+                sgb.command(&command, &self.mem, &self.mem.gpu().screen_shades);
+            }
+
+            if redraw_screen {
+                sgb.render(&self.mem.gpu().screen_shades);
+            }
+        }
+
+        if redraw_screen {
+            self.capture_frame();
+
+            let interval = self.rewind.as_ref().map(Rewind::interval);
+            if interval.is_some_and(|interval| self.frames.is_multiple_of(interval)) {
+                self.push_snapshot();
+            }
+        }
+
+        Ok(redraw_screen)
+    }
+
+    /// The whole machine as bytes, to restore with `load_state`. Only for use
+    /// with the same ROM & model; states from other versions of the emulator
+    /// are rejected by their header.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.header();
+        state.u64(self.steps);
+        state.u64(self.cycles);
+        state.u64(self.frames);
+        self.cpu.save_state(&mut state);
+        self.mem.save_state(&mut state);
+        if let Some(sgb) = self.sgb.as_ref() {
+            sgb.save_state(&mut state);
+        }
+
+        state.into_bytes()
+    }
+
+    /// Restore a `save_state`. If it can't be read the machine is left as
+    /// it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        if let Err(e) = self.read_state(state) {
+            self.read_state(&backup)
+                .expect("Failed to restore the state before loading");
+            return Err(e);
+        }
+
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.render(&self.mem.gpu().screen_shades);
+        }
+        self.capture_frame();
+
+        Ok(())
+    }
+
+    fn read_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(state);
+        state.header()?;
+        self.steps = state.u64()?;
+        self.cycles = state.u64()?;
+        self.frames = state.u64()?;
+        self.cpu.load_state(&mut state)?;
+        self.mem.load_state(&mut state)?;
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.load_state(&mut state)?;
+        }
+
+        state.finish()
+    }
+
+    /// Keep up to `capacity` snapshots for `rewind`, taken every `interval`
+    /// frames. A capacity of 0 turns rewinding off, an interval of 0 is taken
+    /// as every frame.
+    pub fn enable_rewind(&mut self, capacity: usize, interval: u64) {
+        self.rewind = if capacity > 0 {
+            Some(Rewind::new(capacity, interval))
+        } else {
+            None
+        };
+    }
+
+    /// Snapshot the machine for `rewind` now, rather than waiting for the
+    /// next interval. Does nothing unless rewinding is enabled.
+    pub fn push_snapshot(&mut self) {
+        if self.rewind.is_none() {
+            return;
+        }
+
+        let snapshot = self.save_state();
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.push(self.frames, snapshot);
+        }
+    }
+
+    /// Go back at least `frames` frames, to the newest snapshot from before
+    /// then or the oldest one left. Snapshots after it are discarded. Returns
+    /// how many frames it went back.
+    pub fn rewind(&mut self, frames: u64) -> u64 {
+        let target = self.frames.saturating_sub(frames);
+
+        let mut snapshot = None;
+        if let Some(rewind) = self.rewind.as_mut() {
+            while let Some((frame, state)) = rewind.pop() {
+                snapshot = Some(state);
+                if frame <= target {
+                    break;
+                }
+            }
+        }
+
+        let start = self.frames;
+        if let Some(snapshot) = snapshot {
+            self.load_state(&snapshot)
+                .expect("Rewind snapshots are always valid");
+            self.rewind_movie();
+        }
+        start.saturating_sub(self.frames)
+    }
+
+    /// Frames of history available to `rewind`
+    pub fn rewind_frames(&self) -> u64 {
+        self.rewind
+            .as_ref()
+            .map_or(0, |rewind| rewind.len() as u64 * rewind.interval())
+    }
+
+    /// Record every input change from now on into a movie, starting from
+    /// power on if nothing has run yet or from a snapshot otherwise. Changes
+    /// apply from the start of the frame they're made in, as frontends make
+    /// them between frames. Stops any movie being played, and forgets the
+    /// history for `rewind` from before the start.
+    pub fn start_recording(&mut self) {
+        let start = if self.steps == 0 {
+            MovieStart::PowerOn {
+                boot_rom: self.mem.boot_rom().map(movie::hash),
+            }
+        } else {
+            MovieStart::State(self.save_state())
+        };
+
+        self.playback = None;
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+
+        self.recording = Some(Movie::new(self.rom(), self.model, start, self.frames));
+        self.mem.input().record_changes(true);
+        self.sync_recording();
+    }
+
+    /// Finish the movie being recorded, if there is one
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        let mut movie = self.recording.take()?;
+        // Changes since the last instruction would only apply after the end
+        self.mem.input().record_changes(false);
+        movie.end_frame = self.frames;
+
+        Some(movie)
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Play `movie` back from its start. Power on movies need a GameBoy
+    /// which hasn't run yet, others load their snapshot. Other input should
+    /// be held off while `playing_movie`.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        if movie.rom_hash != movie::hash(self.rom()) {
+            return Err("Movie was recorded with a different ROM".to_string());
+        }
+        if movie.model != self.model {
+            return Err(format!("Movie was recorded on {}", movie.model));
+        }
+
+        match &movie.start {
+            MovieStart::PowerOn { boot_rom } => {
+                if self.steps != 0 {
+                    return Err("Movie starts from power on, but this has already run".to_string());
+                }
+                if *boot_rom != self.mem.boot_rom().map(movie::hash) {
+                    return Err("Movie was recorded with a different boot ROM".to_string());
+                }
+            }
+            MovieStart::State(state) => self.load_state(state)?,
+        }
+
+        self.recording = None;
+        self.mem.input().record_changes(false);
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+
+        self.set_held(movie.held_at(movie.start_frame));
+        if self.frames < movie.end_frame {
+            let next = movie
+                .events
+                .iter()
+                .take_while(|e| e.frame < self.frames)
+                .count();
+            self.playback = Some((movie, next));
+        }
+
+        Ok(())
+    }
+
+    /// True until the movie being played reaches its end
+    pub fn playing_movie(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn stop_movie(&mut self) {
+        self.playback = None;
+    }
+
+    /// Log the input changes made since the last instruction, or apply the
+    /// movie's changes due by this frame
+    fn update_movie(&mut self) {
+        let frame = self.frames;
+
+        if let Some(movie) = self.recording.as_mut() {
+            for (player, button, pressed) in self.mem.input().take_changes() {
+                movie.events.push(InputChange {
+                    frame,
+                    player,
+                    button,
+                    pressed,
+                });
+            }
+        }
+
+        if let Some((movie, next)) = self.playback.as_mut() {
+            while let Some(event) = movie.events.get(*next).filter(|e| e.frame <= frame) {
+                self.mem.input().set_player_input(
+                    usize::from(event.player),
+                    event.button,
+                    event.pressed,
+                );
+                *next += 1;
+            }
+        }
+    }
+
+    /// Follow a jump back in time, recording over the movie from here or
+    /// playing it on from here
+    fn rewind_movie(&mut self) {
+        let frame = self.frames;
+
+        if let Some(movie) = self.recording.as_mut() {
+            movie.truncate(frame);
+            self.sync_recording();
+        }
+
+        let held = self.playback.as_mut().map(|(movie, next)| {
+            *next = movie.events.iter().take_while(|e| e.frame < frame).count();
+            movie.held_at(frame)
+        });
+        if let Some(held) = held {
+            self.set_held(held);
+        }
+    }
+
+    /// Record the buttons held now which the movie doesn't have down, or the
+    /// other way around, e.g. on starting or after rewinding over part of it
+    fn sync_recording(&mut self) {
+        let frame = self.frames;
+        let movie = match self.recording.as_mut() {
+            Some(movie) => movie,
+            None => return,
+        };
+
+        let held = movie.held_at(u64::MAX);
+        let input = self.mem.input();
+        input.take_changes();
+
+        for (player, buttons) in held.iter().enumerate() {
+            for &button in Button::ALL.iter() {
+                let pressed = input.is_pressed(player, button);
+                if pressed != (buttons & (1 << button.index()) != 0) {
+                    movie.events.push(InputChange {
+                        frame,
+                        player: player as u8,
+                        button,
+                        pressed,
+                    });
+                }
+            }
+        }
+    }
+
+    fn set_held(&mut self, held: Held) {
+        let input = self.mem.input();
+        for (player, buttons) in held.iter().enumerate() {
+            for &button in Button::ALL.iter() {
+                input.set_player_input(player, button, buttons & (1 << button.index()) != 0);
+            }
+        }
+    }
+
+    /// Run until the current frame completes
+    pub fn run_frame(&mut self) -> RunSummary {
+        self.run(true, None, |_| false)
+    }
+
+    /// Run for at least `cycles` cycles, however many frames that takes
+    pub fn run_for_cycles(&mut self, cycles: u64) -> RunSummary {
+        self.run(false, Some(cycles), |_| false)
+    }
+
+    /// Run until `predicate` is true, checked after each instruction
+    pub fn run_until<F: FnMut(&GameBoy) -> bool>(&mut self, predicate: F) -> RunSummary {
+        self.run(false, None, predicate)
+    }
+
+    fn run<F: FnMut(&GameBoy) -> bool>(
+        &mut self,
+        stop_on_frame: bool,
+        cycles: Option<u64>,
+        mut predicate: F,
+    ) -> RunSummary {
+        let start = self.cycles;
+        let mut frame = false;
+
+        let stop = loop {
+            match self.cycle(false) {
+                Ok(drawn) => frame |= drawn,
+                Err(reason) => break RunStop::Debugger(reason),
+            }
+
+            if stop_on_frame && frame {
+                break RunStop::FrameComplete;
+            }
+            if cycles.is_some_and(|cycles| self.cycles - start >= cycles) {
+                break RunStop::CyclesElapsed;
+            }
+            if predicate(self) {
+                break RunStop::Condition;
+            }
+        };
+
+        RunSummary {
+            cycles: self.cycles - start,
+            frame,
+            stop,
+        }
+    }
+
+    /// Log every instruction executed from now on, or stop logging with None.
+    /// Returns the previous tracer.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// The I/O error that stopped the tracer, if writing a trace failed since
+    /// this was last called. The tracer is dropped when that happens.
+    pub fn take_tracer_error(&mut self) -> Option<io::Error> {
+        self.tracer_error.take()
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Step one instruction, running calls to completion
+    pub fn step_over(&mut self) {
+        let pc = self.cpu.pc;
+        let instr = Instruction::read(&self.mem, pc);
+
+        match instr {
+            Instruction::CALL { .. }
+            | Instruction::CALLNZ { .. }
+            | Instruction::CALLZ { .. }
+            | Instruction::CALLNC { .. }
+            | Instruction::CALLC { .. }
+            | Instruction::RST { .. } => {
+                self.debugger
+                    .run_to(pc.wrapping_add(Instruction::mem_size(&instr)));
+            }
+            _ => self.debugger.step(1),
+        }
+    }
+
+    /// Run until the current function returns
+    pub fn step_out(&mut self) {
+        self.debugger.finish(self.cpu.sp);
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        self.mem.gpu()
+    }
+
+    pub fn gpu_mut(&mut self) -> &mut Gpu {
+        self.mem.gpu_mut()
+    }
+
+    /// Colours for DMG shades. The SGB's screen has its own.
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.gpu_mut().set_palettes(palettes);
+        self.capture_frame();
+    }
+
+    pub fn palettes(&self) -> Palettes {
+        self.gpu().palettes()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        self.mem.rom()
+    }
+
+    /// Advance the Gpu & timer on each memory access within an instruction,
+    /// rather than all at once after it. Slower, but needed by timing
+    /// sensitive software.
+    pub fn set_mcycle_timing(&mut self, enabled: bool) {
+        self.mem.set_per_access_timing(enabled);
     }
 
     pub fn input(&mut self) -> &mut Input {
         self.mem.input()
     }
 
+    /// VRAM & OAM read as 0xFF while the Gpu is using them. Disable the
+    /// locking so debugging tools can see their contents via `read_region`.
+    pub fn set_access_locking(&mut self, enabled: bool) {
+        self.mem.set_access_locking(enabled);
+    }
+
+    /// Bytes written to the serial port, as test ROMs report their results
+    pub fn serial_output(&self) -> &[u8] {
+        self.mem.serial_buffer()
+    }
+
+    /// Print serial output to stdout a line at a time, on by default
+    pub fn set_serial_echo(&mut self, enabled: bool) {
+        self.mem.set_serial_echo(enabled);
+    }
+
     pub fn read_region(&self, start: u16, end: u16) -> Vec<u8> {
         assert!(end >= start);
         let mut result = Vec::with_capacity(usize::from(end - start + 1));
@@ -108,23 +663,275 @@ impl GameBoy {
     }
 
     pub fn gpu_trace(&self) -> GpuDebugTrace {
-        self.gpu.debug_last_frame.clone()
+        self.mem.gpu().debug_last_frame.clone()
+    }
+
+    /// The last completed frame, including the border when running as a
+    /// Super Game Boy. Unlike `buffer_vec` it's never partly drawn.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Pixel format for `frame`, RGBA8888 to begin with
+    pub fn set_frame_format(&mut self, format: PixelFormat) {
+        self.frame = Frame::new(format);
+        self.capture_frame();
+    }
+
+    fn capture_frame(&mut self) {
+        let shades = &self.mem.gpu().screen_shades;
+        match self.sgb.as_ref() {
+            Some(sgb) => self
+                .frame
+                .capture(&sgb.screen_rgba, SGB_HSIZE, SGB_VSIZE, shades),
+            None => self
+                .frame
+                .capture(&self.mem.gpu().screen_rgba, GB_HSIZE, GB_VSIZE, shades),
+        }
     }
 
     pub fn buffer(&self) -> *const u8 {
-        self.gpu.screen_rgba.as_ptr()
+        self.mem.gpu().screen_rgba.as_ptr()
     }
 
     pub fn buffer_vec(&self) -> &Vec<u8> {
-        &self.gpu.screen_rgba
+        &self.mem.gpu().screen_rgba
+    }
+
+    /// True when running as a Super Game Boy with an SGB enhanced cartridge
+    pub fn sgb_enabled(&self) -> bool {
+        self.sgb.is_some()
+    }
+
+    /// SGB_HSIZE x SGB_VSIZE RGBA output including the border, updated each
+    /// VBlank. None unless `sgb_enabled`.
+    pub fn sgb_buffer_vec(&self) -> Option<&Vec<u8>> {
+        self.sgb.as_ref().map(|sgb| &sgb.screen_rgba)
     }
 }
 
-fn print_cpu_trace(cpu: &Cpu, cycles: u64, gpu: u8) {
-    let z = if cpu.z_flag() { 'Z' } else { '-' };
-    let n = if cpu.n_flag() { 'N' } else { '-' };
-    let c = if cpu.c_flag() { 'C' } else { '-' };
-    let h = if cpu.h_flag() { 'H' } else { '-' };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palette;
+    use crate::trace::TraceFormat;
+
+    fn rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    #[test]
+    fn run_methods() {
+        // jr @
+        let mut gb = GameBoy::new(rom(&[0x18, 0xFE]), Model::Dmg);
+
+        let summary = gb.run_for_cycles(1000);
+        assert_eq!(summary.stop, RunStop::CyclesElapsed);
+        assert_eq!((summary.cycles, summary.frame), (1008, false));
+
+        let summary = gb.run_frame();
+        assert_eq!(summary.stop, RunStop::FrameComplete);
+        assert!(summary.frame);
+        assert_eq!(gb.frames, 1);
+
+        let summary = gb.run_until(|gb| gb.cycles >= 200_000);
+        assert_eq!(summary.stop, RunStop::Condition);
+        assert_eq!(gb.frames, 2);
+
+        gb.debugger().add_breakpoint(0x100, None);
+        assert!(matches!(gb.run_frame().stop, RunStop::Debugger(_)));
+    }
+
+    #[test]
+    fn save_and_load() {
+        // ld hl,$c000 / inc [hl] / jr -3
+        let mut gb = GameBoy::new(rom(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD]), Model::Dmg);
+        gb.run_frame();
+        let saved = gb.save_state();
+
+        gb.run_frame();
+        let later = gb.save_state();
+        let counter = gb.read_region(0xC000, 0xC000);
 
-    println!("A:{:02X} F:{}{}{}{} BC:{:02X}{:02X} DE:{:02x}{:02x} HL:{:02x}{:02x} SP:{:04x} PC:{:04x} (cy: {}) gpu: {}", cpu.a, z, n, h, c, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc, cycles, gpu);
+        assert_eq!(gb.load_state(&saved), Ok(()));
+        assert_eq!(gb.frames, 1);
+        assert_eq!(gb.save_state(), saved);
+
+        gb.run_frame();
+        assert_eq!(gb.save_state(), later);
+        assert_eq!(gb.read_region(0xC000, 0xC000), counter);
+
+        // Nothing changes when a state can't be loaded
+        assert_eq!(
+            gb.load_state(&saved[..saved.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(gb.load_state(b"GBMV"), Err(StateError::NotAState));
+        let mut longer = saved.clone();
+        longer.push(0);
+        assert_eq!(gb.load_state(&longer), Err(StateError::TrailingData(1)));
+        assert_eq!(gb.save_state(), later);
+    }
+
+    #[test]
+    fn rewind() {
+        let mut gb = GameBoy::new(rom(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD]), Model::Dmg);
+        assert_eq!(gb.rewind(10), 0);
+
+        gb.enable_rewind(3, 2);
+        for _ in 0..10 {
+            gb.run_frame();
+        }
+        assert_eq!(gb.rewind_frames(), 6);
+
+        // Snapshots at frames 6, 8 & 10
+        assert_eq!(gb.rewind(3), 4);
+        assert_eq!(gb.frames, 6);
+        assert_eq!(gb.rewind(1), 0);
+
+        gb.run_frame();
+        gb.run_frame();
+        let state = gb.save_state();
+        gb.run_frame();
+        assert_eq!(gb.rewind(1), 1);
+        assert_eq!(gb.save_state(), state);
+    }
+
+    #[test]
+    fn movie_replay() {
+        // ldh a,[$00] / add b / ld b,a / jr -5, summing the joypad reads
+        let code = rom(&[0xF0, 0x00, 0x80, 0x47, 0x18, 0xFB]);
+        let record = |gb: &mut GameBoy| {
+            gb.start_recording();
+            for frame in 0..12 {
+                match frame {
+                    2 => gb.input().set_input(Button::Right, true),
+                    5 => gb.input().set_input(Button::Right, false),
+                    7 => gb.input().set_player_input(0, Button::Down, true),
+                    _ => {}
+                }
+                gb.run_frame();
+            }
+            gb.stop_recording().unwrap()
+        };
+
+        // From power on
+        let mut gb = GameBoy::new(code.clone(), Model::Dmg);
+        let movie = record(&mut gb);
+        assert_eq!(movie.frames(), 12);
+        assert_eq!(movie.events.len(), 3);
+
+        let mut replay = GameBoy::new(code.clone(), Model::Dmg);
+        replay
+            .play_movie(Movie::from_bytes(&movie.to_bytes()).unwrap())
+            .unwrap();
+        while replay.playing_movie() {
+            replay.run_frame();
+        }
+        assert_eq!(replay.frames, 12);
+        assert_eq!(replay.save_state(), gb.save_state());
+
+        // From a snapshot, with a button already held
+        let movie = record(&mut gb);
+        assert!(matches!(movie.start, MovieStart::State(_)));
+        assert_eq!(movie.events[0].frame, 12);
+        let end = gb.save_state();
+
+        replay.run_frame();
+        replay.play_movie(movie).unwrap();
+        for _ in 0..12 {
+            replay.run_frame();
+        }
+        assert!(!replay.playing_movie());
+        assert_eq!(replay.save_state(), end);
+
+        let mut other = GameBoy::new(rom(&[0x18, 0xFE]), Model::Dmg);
+        assert!(other.play_movie(record(&mut gb)).is_err());
+    }
+
+    #[test]
+    fn rewind_while_recording() {
+        let code = rom(&[0xF0, 0x00, 0x80, 0x47, 0x18, 0xFB]);
+        let mut gb = GameBoy::new(code.clone(), Model::Dmg);
+        gb.enable_rewind(10, 2);
+        gb.start_recording();
+
+        for frame in 0..10 {
+            gb.input().set_input(Button::Left, frame % 3 == 0);
+            gb.run_frame();
+        }
+        gb.rewind(5);
+        assert_eq!(gb.frames, 4);
+
+        // Recording over the rewound frames with different input
+        for _ in 0..6 {
+            gb.input().set_input(Button::Up, true);
+            gb.run_frame();
+        }
+        let movie = gb.stop_recording().unwrap();
+        assert!(movie
+            .events
+            .iter()
+            .all(|e| e.frame <= 4 || e.button != Button::Left));
+
+        let mut replay = GameBoy::new(code, Model::Dmg);
+        replay.play_movie(movie).unwrap();
+        while replay.playing_movie() {
+            replay.run_frame();
+        }
+        assert_eq!(replay.save_state(), gb.save_state());
+    }
+
+    #[test]
+    fn blank_frames_with_lcd_off() {
+        // xor a / ldh [$40],a / jr @
+        let mut gb = GameBoy::new(rom(&[0xAF, 0xE0, 0x40, 0x18, 0xFE]), Model::Dmg);
+
+        let first = gb.run_frame();
+        let second = gb.run_frame();
+
+        assert_eq!(second.stop, RunStop::FrameComplete);
+        assert!((70224..70224 + 12).contains(&first.cycles));
+        assert!((70224..70224 + 12).contains(&second.cycles));
+        assert!(gb.buffer_vec().iter().all(|&b| b == 255));
+    }
+
+    #[test]
+    fn palettes_recolour_screen() {
+        let mut gb = GameBoy::new(rom(&[0xAF, 0xE0, 0x40, 0x18, 0xFE]), Model::Dmg);
+        gb.run_frame();
+
+        gb.set_palettes(Palettes::uniform(Palette::DMG));
+        assert_eq!(&gb.buffer_vec()[..4], &[0x9B, 0xBC, 0x0F, 0xFF]);
+
+        // Blank frames use the new colours too
+        gb.run_frame();
+        assert_eq!(&gb.buffer_vec()[..4], &[0x9B, 0xBC, 0x0F, 0xFF]);
+    }
+
+    #[test]
+    fn tracer_error_is_kept() {
+        struct Broken;
+        impl io::Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::WriteZero.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut gb = GameBoy::new(rom(&[0x18, 0xFE]), Model::Dmg);
+        gb.set_tracer(Some(Tracer::new(Box::new(Broken), TraceFormat::Doctor)));
+        gb.run_for_cycles(100);
+
+        assert!(gb.set_tracer(None).is_none());
+        assert_eq!(
+            gb.take_tracer_error().unwrap().kind(),
+            io::ErrorKind::WriteZero
+        );
+        assert!(gb.take_tracer_error().is_none());
+    }
 }
@@ -1,9 +1,14 @@
-use crate::interrupt;
-use crate::memory::Memory;
+use crate::interrupt::Interrupt;
+use crate::memory::GpuBus;
+use crate::palette::{Palettes, BG_PALETTE, OBJ0_PALETTE, OBJ1_PALETTE};
+use crate::state::{StateError, StateReader, StateWriter};
 
 pub const GB_HSIZE: usize = 160;
 pub const GB_VSIZE: usize = 144;
 
+/// Cycles in one frame, including VBlank
+pub const FRAME_CYCLES: u32 = 70224;
+
 const LCD_ON_BIT: u8 = 1 << 7;
 const WINDOW_TILEMAP_BIT: u8 = 1 << 6;
 const WINDOW_DISP_BIT: u8 = 1 << 5;
@@ -21,6 +26,89 @@ pub enum GpuMode {
     VBlank,
 }
 
+impl GpuMode {
+    /// Mode number as shown in STAT
+    pub fn number(self) -> u8 {
+        match self {
+            GpuMode::HBlank => 0,
+            GpuMode::VBlank => 1,
+            GpuMode::OAMRead => 2,
+            GpuMode::VRAMRead => 3,
+        }
+    }
+
+    pub fn from_number(number: u8) -> GpuMode {
+        match number & 0b11 {
+            0 => GpuMode::HBlank,
+            1 => GpuMode::VBlank,
+            2 => GpuMode::OAMRead,
+            _ => GpuMode::VRAMRead,
+        }
+    }
+}
+
+/// What drew a pixel
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Layer {
+    Background,
+    Window,
+    Obj0,
+    Obj1,
+}
+
+impl Layer {
+    fn number(self) -> u8 {
+        match self {
+            Layer::Background => 0,
+            Layer::Window => 1,
+            Layer::Obj0 => 2,
+            Layer::Obj1 => 3,
+        }
+    }
+
+    fn from_number(number: u8) -> Layer {
+        match number & 0b11 {
+            0 => Layer::Background,
+            1 => Layer::Window,
+            2 => Layer::Obj0,
+            _ => Layer::Obj1,
+        }
+    }
+
+    /// Which of the `Palettes` colours it
+    fn palette_number(self) -> u8 {
+        match self {
+            Layer::Background | Layer::Window => BG_PALETTE,
+            Layer::Obj0 => OBJ0_PALETTE,
+            Layer::Obj1 => OBJ1_PALETTE,
+        }
+    }
+}
+
+/// A pixel as the Gpu drew it, before any colours
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct RawPixel {
+    /// 2 bit colour index from the tile
+    pub index: u8,
+    pub layer: Layer,
+    /// Value of BGP, OBP0 or OBP1 mapping the index to a shade. 0 where the
+    /// screen or background is blank.
+    pub palette: u8,
+}
+
+impl RawPixel {
+    const BLANK: RawPixel = RawPixel {
+        index: 0,
+        layer: Layer::Background,
+        palette: 0,
+    };
+
+    /// 2 bit DMG shade
+    pub fn shade(self) -> u8 {
+        apply_palette(self.index, self.palette)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GpuDebugTrace {
     sprites: u16,
@@ -43,9 +131,15 @@ pub struct Gpu {
     pub mode_elapsed: u32,
     pub line: u8,
     pub screen_rgba: Vec<u8>,
+    // 2 bit DMG shade of each pixel, after palettes are applied
+    pub screen_shades: Vec<u8>,
+    screen_raw: Vec<RawPixel>,
+    palettes: Palettes,
     debug_current_frame: GpuDebugTrace,
     pub debug_last_frame: GpuDebugTrace,
     pub debug_lcd_pwr: bool,
+    // Time since the last blank frame while the LCD is off
+    lcd_off_elapsed: u32,
 }
 
 impl Gpu {
@@ -55,37 +149,111 @@ impl Gpu {
             mode_elapsed: 0,
             line: 0,
             screen_rgba: vec![255; GB_VSIZE * GB_HSIZE * 4],
+            screen_shades: vec![0; GB_VSIZE * GB_HSIZE],
+            screen_raw: vec![RawPixel::BLANK; GB_VSIZE * GB_HSIZE],
+            palettes: Palettes::default(),
             debug_current_frame: GpuDebugTrace::new(),
             debug_last_frame: GpuDebugTrace::new(),
             debug_lcd_pwr: false,
+            lcd_off_elapsed: 0,
         }
     }
 
-    pub fn cycle(&mut self, mem: &mut Memory, elapsed: u8, halted: bool) {
+    /// The screen is saved as raw pixels, & recoloured on load
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.mode.number());
+        state.u32(self.mode_elapsed);
+        state.u8(self.line);
+        let indices: Vec<u8> = self
+            .screen_raw
+            .iter()
+            .map(|pixel| pixel.index | (pixel.layer.number() << 2))
+            .collect();
+        let palettes: Vec<u8> = self.screen_raw.iter().map(|pixel| pixel.palette).collect();
+        state.bytes(&indices);
+        state.bytes(&palettes);
+        state.bool(self.debug_lcd_pwr);
+        state.u32(self.lcd_off_elapsed);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.mode = GpuMode::from_number(state.u8()?);
+        self.mode_elapsed = state.u32()?;
+        self.line = state.u8()?;
+        let indices = state.bytes()?;
+        let palettes = state.bytes()?;
+        for block in [indices, palettes].iter() {
+            if block.len() != self.screen_raw.len() {
+                return Err(StateError::SizeMismatch {
+                    expected: self.screen_raw.len(),
+                    found: block.len(),
+                });
+            }
+        }
+        for ((pixel, &index), &palette) in self.screen_raw.iter_mut().zip(indices).zip(palettes) {
+            *pixel = RawPixel {
+                index: index & 0b11,
+                layer: Layer::from_number(index >> 2),
+                palette,
+            };
+        }
+        self.debug_lcd_pwr = state.bool()?;
+        self.lcd_off_elapsed = state.u32()?;
+
+        self.colourise(0..GB_VSIZE * GB_HSIZE);
+
+        Ok(())
+    }
+
+    /// Colour index, layer & palette of each pixel, GB_HSIZE x GB_VSIZE
+    pub fn raw_screen(&self) -> &[RawPixel] {
+        &self.screen_raw
+    }
+
+    pub fn palettes(&self) -> Palettes {
+        self.palettes
+    }
+
+    /// Change the colours of the DMG's shades, recolouring what's on screen
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.palettes = palettes;
+        self.colourise(0..GB_VSIZE * GB_HSIZE);
+    }
+
+    /// Returns true when a frame has completed, on entering VBlank or every
+    /// FRAME_CYCLES with the LCD off, when the screen is blank
+    pub fn cycle(&mut self, mem: &mut GpuBus, elapsed: u8) -> bool {
         // TODO SLOW currently load this byte twice
         let lcdc: u8 = mem.get(0xFF40);
 
         if (lcdc & LCD_ON_BIT) == 0 {
-            if halted {
-                println!("Gpu disabled & cpu halted - uh oh");
-                panic!("nope");
-            }
-
             self.debug_lcd_pwr = false;
             self.mode = GpuMode::OAMRead;
             self.line = 0;
-            mem.set(0xFF44, self.line);
+            mem.set_ly(self.line);
 
             let lcdstat: u8 = mem.get(0xFF41);
 
             let newlcdstat: u8 = lcdstat & 0xFC;
 
-            mem.set(0xFF41, newlcdstat);
+            mem.set_stat(newlcdstat);
+            mem.set_gpu_mode(None);
 
-            return;
+            self.lcd_off_elapsed += u32::from(elapsed);
+            if self.lcd_off_elapsed < FRAME_CYCLES {
+                return false;
+            }
+
+            self.lcd_off_elapsed -= FRAME_CYCLES;
+            for pixel in self.screen_raw.iter_mut() {
+                *pixel = RawPixel::BLANK;
+            }
+            self.colourise(0..GB_VSIZE * GB_HSIZE);
+            return true;
         }
 
         self.debug_lcd_pwr = true;
+        self.lcd_off_elapsed = 0;
 
         self.mode_elapsed += u32::from(elapsed);
         let mut vblank = false;
@@ -142,34 +310,30 @@ impl Gpu {
 
         //println!("GPU State: {:?} {:?} {:?}", self.mode, self.line, self.mode_elapsed);
 
-        mem.set(0xFF44, self.line);
+        mem.set_ly(self.line);
 
         let lcdstat: u8 = mem.get(0xFF41);
 
-        let mode: u8 = match self.mode {
-            GpuMode::HBlank => 0,
-            GpuMode::VBlank => 1,
-            GpuMode::OAMRead => 2,
-            GpuMode::VRAMRead => 3,
-        };
+        let newlcdstat: u8 = (lcdstat & 0xFC) | self.mode.number();
 
-        let newlcdstat: u8 = (lcdstat & 0xFC) | mode;
-
-        mem.set(0xFF41, newlcdstat);
+        mem.set_stat(newlcdstat);
+        mem.set_gpu_mode(Some(self.mode));
 
         if vblank {
-            interrupt::set_interrupt(interrupt::Interrupt::VBlank, mem);
+            mem.set_interrupt(Interrupt::VBlank);
         }
 
         if self.lcd_status_interrupt(mem, newlcdstat, newmode, newline) {
             println!("lcd_status_interrupt");
-            interrupt::set_interrupt(interrupt::Interrupt::LcdStat, mem);
+            mem.set_interrupt(Interrupt::LcdStat);
         }
+
+        vblank
     }
 
     fn lcd_status_interrupt(
         &self,
-        mem: &Memory,
+        mem: &GpuBus,
         lcdstat: u8,
         newmode: bool,
         newline: bool,
@@ -191,19 +355,29 @@ impl Gpu {
         false
     }
 
-    fn draw_line(&mut self, mem: &Memory) {
+    fn draw_line(&mut self, mem: &GpuBus) {
         let lcdc: u8 = mem.get(0xFF40);
         let tiledataselect = (lcdc & TILEDATA_BIT) != 0;
         let tiles = tiles_start(tiledataselect);
 
         let bg_win_colours: u8 = mem.get(0xFF47);
+        let start = self.line as usize * GB_HSIZE;
+
+        // Left blank if the background's off
+        for pixel in &mut self.screen_raw[start..start + GB_HSIZE] {
+            *pixel = RawPixel::BLANK;
+        }
 
         if lcdc & BG_DISP_BIT != 0 {
             let tilemap = select_tilemap((lcdc & BG_TILEMAP_BIT) != 0);
             draw_background(
                 self.line,
                 mem,
-                bg_win_colours,
+                RawPixel {
+                    index: 0,
+                    layer: Layer::Background,
+                    palette: bg_win_colours,
+                },
                 tiles,
                 tiledataselect,
                 tilemap,
@@ -211,7 +385,7 @@ impl Gpu {
                 mem.get(0xFF42),
                 0,
                 0,
-                &mut self.screen_rgba,
+                &mut self.screen_raw,
             );
         }
 
@@ -223,7 +397,11 @@ impl Gpu {
             draw_background(
                 self.line,
                 mem,
-                bg_win_colours,
+                RawPixel {
+                    index: 0,
+                    layer: Layer::Window,
+                    palette: bg_win_colours,
+                },
                 tiles,
                 false,
                 tilemap,
@@ -231,7 +409,7 @@ impl Gpu {
                 0,
                 0,
                 0,
-                &mut self.screen_rgba,
+                &mut self.screen_raw,
             );
         }
 
@@ -242,20 +420,35 @@ impl Gpu {
                 mem,
                 sprite_height,
                 0x8000,
-                bg_win_colours,
-                &mut self.screen_rgba,
+                &mut self.screen_raw,
                 &mut self.debug_current_frame,
             );
         } else {
             ////println!("Sprites disabled {:X} {}", lcdc, lcdc & SPRITE_DISP_BIT);
         }
+
+        self.colourise(start..start + GB_HSIZE);
+    }
+
+    /// Fill in `screen_shades` & `screen_rgba` for the pixels at `range`
+    fn colourise(&mut self, range: std::ops::Range<usize>) {
+        for i in range {
+            let pixel = self.screen_raw[i];
+            self.screen_shades[i] = pixel.shade();
+
+            let colour = self
+                .palettes
+                .colour(pixel.layer.palette_number(), self.screen_shades[i]);
+            set_pixel(&mut self.screen_rgba, i * 4, colour);
+        }
     }
 }
 
+/// Draws `pixel`'s layer with its palette, taking each index from the tiles
 fn draw_background(
     line: u8,
-    mem: &Memory,
-    bgp: u8,
+    mem: &GpuBus,
+    pixel: RawPixel,
     tiledata: u16,
     tiledataselect: bool,
     tilemap: u16,
@@ -263,7 +456,7 @@ fn draw_background(
     scroll_y: u8,
     offset_x: u8,
     offset_y: u8,
-    rgba: &mut [u8],
+    raw: &mut [RawPixel],
 ) {
     if line < offset_y {
         return;
@@ -303,24 +496,31 @@ fn draw_background(
         // TODO draw all eight pixels at once.
         let tilerow = get_tile_row_data(mem, tiledata, tilenum, ty);
         let colour = get_tile_colour(tilerow, tx);
-        let pixel = apply_palette(colour, bgp);
 
-        let rgba_start = ((line as usize) * GB_HSIZE + i as usize) * 4;
-        set_pixel(rgba, rgba_start, pixel);
+        raw[(line as usize) * GB_HSIZE + i] = RawPixel {
+            index: colour,
+            ..pixel
+        };
     }
 }
 
 fn draw_sprites(
     line: u8,
-    mem: &Memory,
+    mem: &GpuBus,
     sprite_height: u8,
     tiledata: u16,
-    bgp: u8,
-    rgba: &mut [u8],
+    raw: &mut [RawPixel],
     debug: &mut GpuDebugTrace,
 ) {
     let palettes = (mem.get(0xFF48), mem.get(0xFF49));
-    let bgcolouroverdraw = apply_palette(0, bgp);
+
+    // Background & window colour indices, before any sprites cover them
+    let start = line as usize * GB_HSIZE;
+    let bg_indices: Vec<u8> = raw[start..]
+        .iter()
+        .take(GB_HSIZE + 1)
+        .map(|pixel| pixel.index)
+        .collect();
 
     let mut sprites_drawn = 0;
     // for each sprite
@@ -353,11 +553,11 @@ fn draw_sprites(
                 continue;
             }
             drawn = true;
-            let rgba_start = (line as usize * GB_HSIZE + x as usize) * 4;
+            let pixel_index = line as usize * GB_HSIZE + x as usize;
 
-            // Is priority bit set or is the bg value zero?
-            if !s.priority && rgba[rgba_start] != bgcolouroverdraw {
-                ////println!("Not drawing pixel due to priority / bg colour {}", rgba[rgba_start]);
+            // Is priority bit set or is the bg colour zero?
+            if !s.priority && bg_indices[x as usize] != 0 {
+                ////println!("Not drawing pixel due to priority / bg colour {}", bg_indices[x as usize]);
                 continue;
             }
             // draw pixel
@@ -372,12 +572,15 @@ fn draw_sprites(
             };
 
             let colour = get_tile_colour(tilerow, tx as u8);
-            let pixel = apply_palette(colour, s.palette);
 
             // Is this pixel transparent?
             if colour != 0 {
-                ////println!("Drawn pixel {:X} {}", rgba_start, pixel);
-                set_pixel(rgba, rgba_start, pixel);
+                ////println!("Drawn pixel {:X} {}", pixel_index, colour);
+                raw[pixel_index] = RawPixel {
+                    index: colour,
+                    layer: s.layer,
+                    palette: s.palette,
+                };
             } else {
                 ////println!("Skipped pixel {}", colour);
             }
@@ -399,12 +602,14 @@ struct Sprite {
     yflip: bool,
     xflip: bool,
     palette: u8,
+    // Obj0 or Obj1
+    layer: Layer,
 }
 
 const SPRITE_MEM_START: u16 = 0xFE00;
 const SPRITE_MEM_SIZE: u16 = 4;
 
-fn load_sprite(mem: &Memory, num: u16, palettes: (u8, u8)) -> Sprite {
+fn load_sprite(mem: &GpuBus, num: u16, palettes: (u8, u8)) -> Sprite {
     let addr: u16 = SPRITE_MEM_START + SPRITE_MEM_SIZE * num;
 
     let options = mem.get(addr + 3);
@@ -421,6 +626,11 @@ fn load_sprite(mem: &Memory, num: u16, palettes: (u8, u8)) -> Sprite {
         } else {
             palettes.0
         },
+        layer: if options & 0b1000 != 0 {
+            Layer::Obj1
+        } else {
+            Layer::Obj0
+        },
     }
 }
 
@@ -435,7 +645,7 @@ fn sprite_on_disp(sx: i16) -> bool {
     sx > -8 && sx <= GB_HSIZE as i16
 }
 
-fn get_tile_row_data(mem: &Memory, tiledata: u16, tilenum: i32, ty: u16) -> (u8, u8) {
+fn get_tile_row_data(mem: &GpuBus, tiledata: u16, tilenum: i32, ty: u16) -> (u8, u8) {
     const TILE_SIZE: i32 = 16;
     let signedtiledata: i32 = u32::from(tiledata) as i32;
     let tilestart = (signedtiledata + tilenum * TILE_SIZE) as u16;
@@ -459,30 +669,21 @@ fn get_tile_colour(tilerow: (u8, u8), tx: u8) -> u8 {
     ((byte1 & bit) >> offset) | (((byte2 & bit) >> offset) << 1)
 }
 
-fn apply_palette(colour: u8, pal: u8) -> u8 {
+// returns 2 bit shade
+pub fn apply_palette(colour: u8, pal: u8) -> u8 {
     match colour {
-        3 => get_colour((pal & 0b1100_0000) >> 6),
-        2 => get_colour((pal & 0b0011_0000) >> 4),
-        1 => get_colour((pal & 0b0000_1100) >> 2),
-        0 => get_colour(pal & 0b0000_0011),
+        3 => (pal & 0b1100_0000) >> 6,
+        2 => (pal & 0b0011_0000) >> 4,
+        1 => (pal & 0b0000_1100) >> 2,
+        0 => pal & 0b0000_0011,
         _ => panic!("Invalid colour {}", colour),
     }
 }
 
-fn get_colour(colour: u8) -> u8 {
-    match colour {
-        0 => 0xFF,
-        1 => 0xC0,
-        2 => 0x60,
-        3 => 0x00,
-        _ => panic!("Invalid colour {}", colour),
-    }
-}
-
-fn set_pixel(rgba: &mut [u8], start: usize, colour: u8) {
-    rgba[start] = colour;
-    rgba[start + 1] = colour;
-    rgba[start + 2] = colour;
+fn set_pixel(rgba: &mut [u8], start: usize, [r, g, b]: [u8; 3]) {
+    rgba[start] = r;
+    rgba[start + 1] = g;
+    rgba[start + 2] = b;
     rgba[start + 3] = 255;
 }
 
@@ -513,6 +714,10 @@ fn get_sprite_size(lcdc: u8) -> u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::Memory;
+    use crate::model::Model;
+    use crate::rom::Cartridge;
+
     #[test]
     fn test_sprite_in_row() {
         assert!(sprite_in_row(0, -8, 16));
@@ -522,4 +727,64 @@ mod tests {
 
         assert!(sprite_in_row(0, 0, 16));
     }
+
+    #[test]
+    fn raw_pixels() {
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+        mem.set(
+            0xFF40,
+            LCD_ON_BIT | TILEDATA_BIT | SPRITE_DISP_BIT | BG_DISP_BIT,
+        );
+        // Tile 1's top row is all colour 1, for the first background tile &
+        // a sprite overlapping it
+        mem.set(0x8010, 0xFF);
+        mem.set(0x9800, 1);
+        mem.set(0xFE00, 16);
+        mem.set(0xFE01, 8 + 4);
+        mem.set(0xFE02, 1);
+        // Every background colour is the lightest shade
+        mem.set(0xFF47, 0);
+        mem.set(0xFF48, 0b1110_0100);
+
+        let draw_line = |mem: &mut Memory| {
+            let (gpu, bus) = mem.split_gpu();
+            gpu.draw_line(&bus);
+        };
+
+        draw_line(&mut mem);
+        let raw = mem.gpu().raw_screen();
+        assert_eq!(
+            raw[0],
+            RawPixel {
+                index: 1,
+                layer: Layer::Background,
+                palette: 0
+            }
+        );
+        assert_eq!(
+            raw[4],
+            RawPixel {
+                index: 1,
+                layer: Layer::Obj0,
+                palette: 0b1110_0100
+            }
+        );
+        assert_eq!(raw[12].layer, Layer::Background);
+        assert_eq!(mem.gpu().screen_shades[..5], [0, 0, 0, 0, 1]);
+
+        // Behind the background, the sprite only shows over colour 0 even
+        // though colour 1 has the same shade
+        mem.set(0xFE03, 0b100_0000);
+        draw_line(&mut mem);
+        let raw = mem.gpu().raw_screen();
+        assert_eq!(raw[4].layer, Layer::Background);
+        assert_eq!(raw[8].layer, Layer::Obj0);
+
+        // Nothing but sprites with the background off
+        mem.set(0xFF40, LCD_ON_BIT | TILEDATA_BIT | SPRITE_DISP_BIT);
+        draw_line(&mut mem);
+        assert_eq!(mem.gpu().raw_screen()[0], RawPixel::BLANK);
+        assert_eq!(mem.gpu().raw_screen()[4].layer, Layer::Obj0);
+    }
 }
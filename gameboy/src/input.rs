@@ -1,5 +1,10 @@
+use std::str::FromStr;
+
 use crate::math;
+use crate::sgb::{command_id, PacketReader, CMD_MLT_REQ};
+use crate::state::{StateError, StateReader, StateWriter};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Button {
     A,
     B,
@@ -11,60 +16,179 @@ pub enum Button {
     Down,
 }
 
+impl Button {
+    /// In the order of their bits in the joypad register, buttons first
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+    ];
+
+    /// Position in `ALL`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Button, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" => Ok(Button::A),
+            "b" => Ok(Button::B),
+            "select" => Ok(Button::Select),
+            "start" => Ok(Button::Start),
+            "right" => Ok(Button::Right),
+            "left" => Ok(Button::Left),
+            "up" => Ok(Button::Up),
+            "down" => Ok(Button::Down),
+            _ => Err(format!("Unknown button {}", s)),
+        }
+    }
+}
+
+/// Up to four joypads with an SGB multiplayer adapter
+pub const MAX_PLAYERS: usize = 4;
+
 pub struct Input {
-    buttons: u8,
-    joypad: u8,
+    buttons: [u8; MAX_PLAYERS],
+    joypad: [u8; MAX_PLAYERS],
     high4: u8,
+
+    // Only present when running as a Super Game Boy
+    sgb: Option<PacketReader>,
+    sgb_commands: Vec<Vec<u8>>,
+    players: u8,
+    player: u8,
+    p15_pulsed: bool,
+
+    // Player, button & state of each change while recording a movie
+    changes: Option<Vec<(u8, Button, bool)>>,
 }
 
 impl Input {
     pub fn new() -> Input {
         Input {
-            buttons: 0,
-            joypad: 0,
+            buttons: [0; MAX_PLAYERS],
+            joypad: [0; MAX_PLAYERS],
             high4: 0xF0,
+
+            sgb: None,
+            sgb_commands: Vec::new(),
+            players: 1,
+            player: 0,
+            p15_pulsed: false,
+
+            changes: None,
         }
     }
 
+    /// Buttons held aren't included, they belong to whoever is playing
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.high4);
+        if let Some(reader) = self.sgb.as_ref() {
+            reader.save_state(state);
+        }
+        state.u8(self.players);
+        state.u8(self.player);
+        state.bool(self.p15_pulsed);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.high4 = state.u8()?;
+        if let Some(reader) = self.sgb.as_mut() {
+            reader.load_state(state)?;
+        }
+        self.players = state.u8()?;
+        self.player = state.u8()?;
+        if !matches!(self.players, 1 | 2 | 4) || self.player >= self.players {
+            return Err(StateError::Invalid("joypad player"));
+        }
+        self.p15_pulsed = state.bool()?;
+        self.sgb_commands.clear();
+
+        Ok(())
+    }
+
+    /// Decode SGB command packets sent over the joypad register
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(PacketReader::new());
+    }
+
     pub fn set_input(&mut self, key: Button, key_down: bool) {
+        self.set_player_input(0, key, key_down);
+    }
+
+    /// Set input of joypad `player` (0 - 3). Only player 0 is read unless
+    /// an SGB game has requested multiplayer mode.
+    pub fn set_player_input(&mut self, player: usize, key: Button, key_down: bool) {
+        if self.is_pressed(player, key) == key_down {
+            return;
+        }
+
         let func = if key_down { math::set } else { math::reset };
+        let (lines, bit) = self.lines(player, key);
+        *lines = func(*lines, bit);
 
-        match key {
-            Button::A => {
-                self.buttons = func(self.buttons, 0);
-            }
-            Button::B => {
-                self.buttons = func(self.buttons, 1);
-            }
-            Button::Select => {
-                self.buttons = func(self.buttons, 2);
-            }
-            Button::Start => {
-                self.buttons = func(self.buttons, 3);
-            }
-            Button::Right => {
-                self.joypad = func(self.joypad, 0);
-            }
-            Button::Left => {
-                self.joypad = func(self.joypad, 1);
-            }
-            Button::Up => {
-                self.joypad = func(self.joypad, 2);
-            }
-            Button::Down => {
-                self.joypad = func(self.joypad, 3);
-            }
+        if let Some(changes) = self.changes.as_mut() {
+            changes.push((player as u8, key, key_down));
         }
 
         // TODO interrupt
     }
 
+    pub fn is_pressed(&self, player: usize, key: Button) -> bool {
+        let index = key.index();
+        let lines = if index < 4 {
+            self.buttons[player]
+        } else {
+            self.joypad[player]
+        };
+
+        lines & (1 << (index % 4)) != 0
+    }
+
+    /// Register holding `key` for `player`, & its bit
+    fn lines(&mut self, player: usize, key: Button) -> (&mut u8, u8) {
+        let index = key.index();
+        if index < 4 {
+            (&mut self.buttons[player], index as u8)
+        } else {
+            (&mut self.joypad[player], index as u8 - 4)
+        }
+    }
+
+    /// Keep every change made by `set_input` for `take_changes`, or stop
+    pub fn record_changes(&mut self, enabled: bool) {
+        self.changes = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Player, button & whether it was pressed for each change since the
+    /// last call
+    pub fn take_changes(&mut self) -> Vec<(u8, Button, bool)> {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
     pub fn value(&self) -> u8 {
+        let player = self.player as usize;
         let mut result = self.high4;
-        if (self.high4 & 0x20) != 0 {
-            result |= !self.joypad;
+
+        if self.sgb.is_some() && (self.high4 & 0x30) == 0x30 {
+            // With neither line selected the SGB reports the current joypad
+            result |= 0x0F - self.player;
+        } else if (self.high4 & 0x20) != 0 {
+            result |= !self.joypad[player];
         } else if (self.high4 & 0x10) != 0 {
-            result |= !self.buttons;
+            result |= !self.buttons[player];
         }
 
         result
@@ -72,5 +196,57 @@ impl Input {
 
     pub fn update(&mut self, val: u8) {
         self.high4 = val & 0xF0;
+
+        if let Some(reader) = self.sgb.as_mut() {
+            if let Some(command) = reader.write(val) {
+                if command_id(&command) == CMD_MLT_REQ {
+                    self.players = match command[1] & 0b11 {
+                        1 => 2,
+                        3 => 4,
+                        _ => 1,
+                    };
+                    self.player = 0;
+                } else {
+                    self.sgb_commands.push(command);
+                }
+            }
+
+            // Releasing P15 moves on to the next joypad
+            if val & 0x20 == 0 {
+                self.p15_pulsed = true;
+            } else if val & 0x30 == 0x30 && self.p15_pulsed {
+                self.p15_pulsed = false;
+                self.player = (self.player + 1) % self.players;
+            }
+        }
+    }
+
+    /// SGB commands received since the last call
+    pub fn take_sgb_commands(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.sgb_commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(players: u8, player: u8) -> Result<(), StateError> {
+        let mut state = StateWriter::new();
+        state.u8(0xF0);
+        state.u8(players);
+        state.u8(player);
+        state.bool(false);
+        let state = state.into_bytes();
+
+        Input::new().load_state(&mut StateReader::new(&state))
+    }
+
+    #[test]
+    fn invalid_players() {
+        assert_eq!(load(4, 3), Ok(()));
+        assert_eq!(load(3, 0), Err(StateError::Invalid("joypad player")));
+        assert_eq!(load(2, 2), Err(StateError::Invalid("joypad player")));
+        assert_eq!(load(0, 0), Err(StateError::Invalid("joypad player")));
     }
 }
@@ -1,6 +1,9 @@
-use crate::cpu::{Cpu, Cpu16Register, CpuRegister};
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+pub use crate::cpu::{Cpu16Register, CpuRegister};
 use crate::math;
-use crate::memory::Memory;
 use crate::opcode::{read_extended_opcode, read_opcode};
 
 #[derive(Debug)]
@@ -268,17 +271,27 @@ pub enum Instruction {
 }
 
 impl Instruction {
-    pub fn read(mem: &Memory, addr: u16) -> Instruction {
-        let opcode: u8 = mem.get(addr);
+    pub fn read<B: Bus>(mem: &B, addr: u16) -> Instruction {
+        Instruction::decode(&[
+            mem.peek(addr),
+            mem.peek(addr.wrapping_add(1)),
+            mem.peek(addr.wrapping_add(2)),
+        ])
+    }
+
+    /// Decode from raw bytes, e.g. straight out of the ROM independent of
+    /// what is currently mapped. Bytes past the end of `bytes` read as 0.
+    pub fn decode(bytes: &[u8]) -> Instruction {
+        let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
 
-        if opcode == 0xCB {
-            return read_extended_opcode(mem.get(addr + 1), addr + 2, mem);
+        if byte(0) == 0xCB {
+            return read_extended_opcode(byte(1));
         }
 
-        read_opcode(opcode, addr + 1, mem)
+        read_opcode(byte(0), byte(1), u16::from(byte(1)) | (u16::from(byte(2)) << 8))
     }
 
-    pub fn disassemble(mem: &Memory, start_addr: u16, num_instrs: usize) -> Vec<Instruction> {
+    pub fn disassemble<B: Bus>(mem: &B, start_addr: u16, num_instrs: usize) -> Vec<Instruction> {
         let mut instrs = Vec::with_capacity(num_instrs);
         let mut addr = start_addr;
 
@@ -408,7 +421,159 @@ impl Instruction {
         }
     }
 
-    pub fn execute(&self, cpu: &mut Cpu, mem: &mut Memory) -> u8 {
+    /// Absolute target of a jump, call or restart
+    pub fn target(&self, pc: u16) -> Option<u16> {
+        let relative = |offset: i8| Some(pc.wrapping_add(2).wrapping_add(offset as u16));
+
+        match *self {
+            Instruction::JP { addr }
+            | Instruction::JPNZ { addr }
+            | Instruction::JPZ { addr }
+            | Instruction::JPNC { addr }
+            | Instruction::JPC { addr }
+            | Instruction::CALL { addr }
+            | Instruction::CALLNZ { addr }
+            | Instruction::CALLNC { addr }
+            | Instruction::CALLZ { addr }
+            | Instruction::CALLC { addr }
+            | Instruction::RST { addr } => Some(addr),
+            Instruction::JR { offset }
+            | Instruction::JRNZ { offset }
+            | Instruction::JRZ { offset }
+            | Instruction::JRNC { offset }
+            | Instruction::JRC { offset } => relative(offset),
+            _ => None,
+        }
+    }
+
+    /// Assembler (RGBDS) syntax. With the address of the instruction relative
+    /// jumps show their target, otherwise an offset from `@`. Jump targets &
+    /// memory operands use `symbol` to name addresses where it can.
+    pub fn format(&self, pc: Option<u16>, symbol: &dyn Fn(u16) -> Option<String>) -> String {
+        let addr = |a: u16| symbol(a).unwrap_or_else(|| format!("${:04X}", a));
+        let high = |a: u8| addr(0xFF00 + u16::from(a));
+        let rel = |offset: i8| match pc {
+            Some(pc) => addr(pc.wrapping_add(2).wrapping_add(offset as u16)),
+            None => format!("@{:+}", i16::from(offset) + 2),
+        };
+
+        match *self {
+            Instruction::Noop => "NOP".to_string(),
+            Instruction::LDI16 { val, reg } => format!("LD {:?},${:04X}", reg, val),
+            Instruction::LDI8 { val, reg } => format!("LD {:?},${:02X}", reg, val),
+            Instruction::LDR8 { src, dst } => format!("LD {:?},{:?}", dst, src),
+            Instruction::LDA8 { src_addr, dst } => format!("LD {:?},[{:?}]", dst, src_addr),
+            Instruction::LDHA { addr } => format!("LDH A,[{}]", high(addr)),
+            Instruction::LDHCA => "LDH A,[C]".to_string(),
+            Instruction::LDD => "LD A,[HL-]".to_string(),
+            Instruction::LDI => "LD A,[HL+]".to_string(),
+            Instruction::LDAA { addr: a } => format!("LD A,[{}]", addr(a)),
+            Instruction::LDHLI { offset } => format!("LD HL,SP{:+}", offset),
+            Instruction::LDSPA { addr: a } => format!("LD [{}],SP", addr(a)),
+            Instruction::LDSPHL => "LD SP,HL".to_string(),
+            Instruction::JP { addr: a } => format!("JP {}", addr(a)),
+            Instruction::JPNZ { addr: a } => format!("JP NZ,{}", addr(a)),
+            Instruction::JPZ { addr: a } => format!("JP Z,{}", addr(a)),
+            Instruction::JPNC { addr: a } => format!("JP NC,{}", addr(a)),
+            Instruction::JPC { addr: a } => format!("JP C,{}", addr(a)),
+            Instruction::JPA => "JP HL".to_string(),
+            Instruction::JR { offset } => format!("JR {}", rel(offset)),
+            Instruction::JRNZ { offset } => format!("JR NZ,{}", rel(offset)),
+            Instruction::JRZ { offset } => format!("JR Z,{}", rel(offset)),
+            Instruction::JRNC { offset } => format!("JR NC,{}", rel(offset)),
+            Instruction::JRC { offset } => format!("JR C,{}", rel(offset)),
+            Instruction::CALL { addr: a } => format!("CALL {}", addr(a)),
+            Instruction::CALLNZ { addr: a } => format!("CALL NZ,{}", addr(a)),
+            Instruction::CALLNC { addr: a } => format!("CALL NC,{}", addr(a)),
+            Instruction::CALLZ { addr: a } => format!("CALL Z,{}", addr(a)),
+            Instruction::CALLC { addr: a } => format!("CALL C,{}", addr(a)),
+            Instruction::RET => "RET".to_string(),
+            Instruction::RETNZ => "RET NZ".to_string(),
+            Instruction::RETZ => "RET Z".to_string(),
+            Instruction::RETNC => "RET NC".to_string(),
+            Instruction::RETC => "RET C".to_string(),
+            Instruction::RETI => "RETI".to_string(),
+            Instruction::RST { addr } => format!("RST ${:02X}", addr),
+            Instruction::STA8 { dst_addr, src } => format!("LD [{:?}],{:?}", dst_addr, src),
+            Instruction::STI8 { dst_addr, val } => format!("LD [{:?}],${:02X}", dst_addr, val),
+            Instruction::STHA { addr } => format!("LDH [{}],A", high(addr)),
+            Instruction::STHCA => "LDH [C],A".to_string(),
+            Instruction::STAA { addr: a } => format!("LD [{}],A", addr(a)),
+            Instruction::STD => "LD [HL-],A".to_string(),
+            Instruction::STI => "LD [HL+],A".to_string(),
+            Instruction::SUBR { reg } => format!("SUB A,{:?}", reg),
+            Instruction::SUBA { reg_addr } => format!("SUB A,[{:?}]", reg_addr),
+            Instruction::SUBI { val } => format!("SUB A,${:02X}", val),
+            Instruction::SBCR { reg } => format!("SBC A,{:?}", reg),
+            Instruction::SBCA { reg_addr } => format!("SBC A,[{:?}]", reg_addr),
+            Instruction::SBCI { val } => format!("SBC A,${:02X}", val),
+            Instruction::ADDR { reg } => format!("ADD A,{:?}", reg),
+            Instruction::ADDA => "ADD A,[HL]".to_string(),
+            Instruction::ADDI { val } => format!("ADD A,${:02X}", val),
+            Instruction::ADD16 { src } => format!("ADD HL,{:?}", src),
+            Instruction::ADDSP { val } => format!("ADD SP,{}", val),
+            Instruction::ADC { reg } => format!("ADC A,{:?}", reg),
+            Instruction::ADCA => "ADC A,[HL]".to_string(),
+            Instruction::ADCI { val } => format!("ADC A,${:02X}", val),
+            Instruction::XORR { reg } => format!("XOR A,{:?}", reg),
+            Instruction::XORA => "XOR A,[HL]".to_string(),
+            Instruction::XORI { val } => format!("XOR A,${:02X}", val),
+            Instruction::INC { reg } => format!("INC {:?}", reg),
+            Instruction::INCA => "INC [HL]".to_string(),
+            Instruction::DEC { reg } => format!("DEC {:?}", reg),
+            Instruction::DECA => "DEC [HL]".to_string(),
+            Instruction::INC16 { reg } => format!("INC {:?}", reg),
+            Instruction::DEC16 { reg } => format!("DEC {:?}", reg),
+            Instruction::CPL => "CPL".to_string(),
+            Instruction::CCF => "CCF".to_string(),
+            Instruction::DI => "DI".to_string(),
+            Instruction::EI => "EI".to_string(),
+            Instruction::CMPR { reg } => format!("CP A,{:?}", reg),
+            Instruction::CMPA => "CP A,[HL]".to_string(),
+            Instruction::CMPI { val } => format!("CP A,${:02X}", val),
+            Instruction::ORR { reg } => format!("OR A,{:?}", reg),
+            Instruction::ORA => "OR A,[HL]".to_string(),
+            Instruction::ORI { val } => format!("OR A,${:02X}", val),
+            Instruction::ANDR { reg } => format!("AND A,{:?}", reg),
+            Instruction::ANDA => "AND A,[HL]".to_string(),
+            Instruction::ANDI { val } => format!("AND A,${:02X}", val),
+            Instruction::PUSH { reg } => format!("PUSH {:?}", reg),
+            Instruction::POP { reg } => format!("POP {:?}", reg),
+            Instruction::SWAP { reg } => format!("SWAP {:?}", reg),
+            Instruction::SWAPA => "SWAP [HL]".to_string(),
+            Instruction::BIT { n, reg } => format!("BIT {},{:?}", n, reg),
+            Instruction::BITA { n } => format!("BIT {},[HL]", n),
+            Instruction::SET { n, reg } => format!("SET {},{:?}", n, reg),
+            Instruction::SETA { n } => format!("SET {},[HL]", n),
+            Instruction::RESET { n, reg } => format!("RES {},{:?}", n, reg),
+            Instruction::RESETA { n } => format!("RES {},[HL]", n),
+            Instruction::SLA { reg } => format!("SLA {:?}", reg),
+            Instruction::SLAA => "SLA [HL]".to_string(),
+            Instruction::SRL { reg } => format!("SRL {:?}", reg),
+            Instruction::SRLA => "SRL [HL]".to_string(),
+            Instruction::SRA { reg } => format!("SRA {:?}", reg),
+            Instruction::SRAA => "SRA [HL]".to_string(),
+            Instruction::RLCA => "RLCA".to_string(),
+            Instruction::RLA => "RLA".to_string(),
+            Instruction::RRCA => "RRCA".to_string(),
+            Instruction::RRA => "RRA".to_string(),
+            Instruction::RLC { reg } => format!("RLC {:?}", reg),
+            Instruction::RLCHL => "RLC [HL]".to_string(),
+            Instruction::RL { reg } => format!("RL {:?}", reg),
+            Instruction::RLHL => "RL [HL]".to_string(),
+            Instruction::RRC { reg } => format!("RRC {:?}", reg),
+            Instruction::RRCHL => "RRC [HL]".to_string(),
+            Instruction::RR { reg } => format!("RR {:?}", reg),
+            Instruction::RRHL => "RR [HL]".to_string(),
+            Instruction::SCF => "SCF".to_string(),
+            Instruction::DAA => "DAA".to_string(),
+            Instruction::HALT => "HALT".to_string(),
+            Instruction::ILLEGAL => "ILLEGAL".to_string(),
+            Instruction::UNIMPLEMENTED { opcode } => format!("DB ${:02X}", opcode),
+        }
+    }
+
+    pub fn execute<B: Bus>(&self, cpu: &mut Cpu, mem: &mut B) -> u8 {
         let cycles: u8;
         // Execute based on opcode
         match *self {
@@ -430,32 +595,32 @@ impl Instruction {
             }
             Instruction::LDA8 { src_addr, dst } => {
                 let addr: u16 = cpu.get16(src_addr);
-                cpu.set(dst, mem.get(addr));
+                cpu.set(dst, mem.read(addr));
                 cycles = 8;
             }
             Instruction::LDHA { addr } => {
-                cpu.set(CpuRegister::A, mem.get(0xFF00 + u16::from(addr)));
+                cpu.set(CpuRegister::A, mem.read(0xFF00 + u16::from(addr)));
                 cycles = 12;
             }
             Instruction::LDHCA => {
                 let addr: u16 = u16::from(cpu.get(CpuRegister::C));
-                cpu.set(CpuRegister::A, mem.get(0xFF00 + addr));
+                cpu.set(CpuRegister::A, mem.read(0xFF00 + addr));
                 cycles = 8;
             }
             Instruction::LDD => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                cpu.set(CpuRegister::A, mem.get(addr));
+                cpu.set(CpuRegister::A, mem.read(addr));
                 cpu.set16(Cpu16Register::HL, addr - 1);
                 cycles = 8;
             }
             Instruction::LDI => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                cpu.set(CpuRegister::A, mem.get(addr));
+                cpu.set(CpuRegister::A, mem.read(addr));
                 cpu.set16(Cpu16Register::HL, addr + 1);
                 cycles = 8;
             }
             Instruction::LDAA { addr } => {
-                cpu.set(CpuRegister::A, mem.get(addr));
+                cpu.set(CpuRegister::A, mem.read(addr));
                 cycles = 16;
             }
             Instruction::LDHLI { offset } => {
@@ -469,24 +634,28 @@ impl Instruction {
                 cpu.set16(Cpu16Register::HL, result as u16);
                 
                 cpu.set_flags(false, false, h, c);
+                mem.tick();
                 cycles = 12;
             }
             Instruction::LDSPA { addr } => {
-                mem.set16(addr, cpu.get16(Cpu16Register::SP));
-                //cpu.set16(Cpu16Register::SP, mem.get16(addr));
+                mem.write16(addr, cpu.get16(Cpu16Register::SP));
+                //cpu.set16(Cpu16Register::SP, mem.read16(addr));
                 cycles = 20;
             }
             Instruction::LDSPHL => {
                 let val: u16 = cpu.get16(Cpu16Register::HL);
                 cpu.set16(Cpu16Register::SP, val);
+                mem.tick();
                 cycles = 8;
             }
             Instruction::JP { addr } => {
+                mem.tick();
                 cpu.jump(addr);
                 cycles = 16;
             }
             Instruction::JPNZ { addr } => {
                 if !cpu.z_flag() {
+                    mem.tick();
                     cpu.jump(addr);
                     cycles = 16;
                 } else {
@@ -495,6 +664,7 @@ impl Instruction {
             }
             Instruction::JPZ { addr } => {
                 if cpu.z_flag() {
+                    mem.tick();
                     cpu.jump(addr);
                     cycles = 16;
                 } else {
@@ -503,6 +673,7 @@ impl Instruction {
             }
             Instruction::JPNC { addr } => {
                 if !cpu.c_flag() {
+                    mem.tick();
                     cpu.jump(addr);
                     cycles = 16;
                 } else {
@@ -511,6 +682,7 @@ impl Instruction {
             }
             Instruction::JPC { addr } => {
                 if cpu.c_flag() {
+                    mem.tick();
                     cpu.jump(addr);
                     cycles = 16;
                 } else {
@@ -523,12 +695,14 @@ impl Instruction {
                 cycles = 4;
             }
             Instruction::JR { offset } => {
-                cpu.rjump(offset + 2);
+                mem.tick();
+                cpu.rjump(i16::from(offset) + 2);
                 cycles = 12;
             }
             Instruction::JRNZ { offset } => {
                 if !cpu.z_flag() {
-                    cpu.rjump(offset + 2);
+                    mem.tick();
+                    cpu.rjump(i16::from(offset) + 2);
                     cycles = 12;
                 } else {
                     cycles = 8;
@@ -536,7 +710,8 @@ impl Instruction {
             }
             Instruction::JRZ { offset } => {
                 if cpu.z_flag() {
-                    cpu.rjump(offset + 2);
+                    mem.tick();
+                    cpu.rjump(i16::from(offset) + 2);
                     cycles = 12;
                 } else {
                     cycles = 8;
@@ -544,7 +719,8 @@ impl Instruction {
             }
             Instruction::JRNC { offset } => {
                 if !cpu.c_flag() {
-                    cpu.rjump(offset + 2);
+                    mem.tick();
+                    cpu.rjump(i16::from(offset) + 2);
                     cycles = 12;
                 } else {
                     cycles = 8;
@@ -552,22 +728,23 @@ impl Instruction {
             }
             Instruction::JRC { offset } => {
                 if cpu.c_flag() {
-                    cpu.rjump(offset + 2);
+                    mem.tick();
+                    cpu.rjump(i16::from(offset) + 2);
                     cycles = 12;
                 } else {
                     cycles = 8;
                 }
             }
             Instruction::CALL { addr } => {
-                cpu.sp -= 2;
-                mem.set16(cpu.sp, cpu.pc + Instruction::mem_size(self));
+                mem.tick();
+                cpu.push(mem, cpu.pc + Instruction::mem_size(self));
                 cpu.jump(addr);
                 cycles = 24;
             }
             Instruction::CALLNZ { addr } => {
                 if !cpu.z_flag() {
-                    cpu.sp -= 2;
-                    mem.set16(cpu.sp, cpu.pc + Instruction::mem_size(self));
+                    mem.tick();
+                    cpu.push(mem, cpu.pc + Instruction::mem_size(self));
                     cpu.jump(addr);
                     cycles = 24;
                 } else {
@@ -576,8 +753,8 @@ impl Instruction {
             }
             Instruction::CALLNC { addr } => {
                 if !cpu.c_flag() {
-                    cpu.sp -= 2;
-                    mem.set16(cpu.sp, cpu.pc + Instruction::mem_size(self));
+                    mem.tick();
+                    cpu.push(mem, cpu.pc + Instruction::mem_size(self));
                     cpu.jump(addr);
                     cycles = 24;
                 } else {
@@ -586,8 +763,8 @@ impl Instruction {
             }
             Instruction::CALLZ { addr } => {
                 if cpu.z_flag() {
-                    cpu.sp -= 2;
-                    mem.set16(cpu.sp, cpu.pc + Instruction::mem_size(self));
+                    mem.tick();
+                    cpu.push(mem, cpu.pc + Instruction::mem_size(self));
                     cpu.jump(addr);
                     cycles = 24;
                 } else {
@@ -596,8 +773,8 @@ impl Instruction {
             }
             Instruction::CALLC { addr } => {
                 if cpu.c_flag() {
-                    cpu.sp -= 2;
-                    mem.set16(cpu.sp, cpu.pc + Instruction::mem_size(self));
+                    mem.tick();
+                    cpu.push(mem, cpu.pc + Instruction::mem_size(self));
                     cpu.jump(addr);
                     cycles = 24;
                 } else {
@@ -609,6 +786,8 @@ impl Instruction {
                 cycles = 16;
             }
             Instruction::RETNZ => {
+                // Checking the condition takes an extra cycle
+                mem.tick();
                 if !cpu.z_flag() {
                     cpu.ret(mem);
                     cycles = 20;
@@ -617,6 +796,8 @@ impl Instruction {
                 }
             }
             Instruction::RETZ => {
+                // Checking the condition takes an extra cycle
+                mem.tick();
                 if cpu.z_flag() {
                     cpu.ret(mem);
                     cycles = 20;
@@ -625,6 +806,8 @@ impl Instruction {
                 }
             }
             Instruction::RETNC => {
+                // Checking the condition takes an extra cycle
+                mem.tick();
                 if !cpu.c_flag() {
                     cpu.ret(mem);
                     cycles = 20;
@@ -633,6 +816,8 @@ impl Instruction {
                 }
             }
             Instruction::RETC => {
+                // Checking the condition takes an extra cycle
+                mem.tick();
                 if cpu.c_flag() {
                     cpu.ret(mem);
                     cycles = 20;
@@ -647,42 +832,42 @@ impl Instruction {
             }
             Instruction::RST { addr } => {
                 // Store next pc on stack & jump to addr
-                cpu.sp = cpu.sp.wrapping_sub(2);
-                mem.set16(cpu.sp, cpu.pc + Instruction::mem_size(self));
+                mem.tick();
+                cpu.push(mem, cpu.pc + Instruction::mem_size(self));
                 cpu.jump(addr);
                 cycles = 16;
             }
             Instruction::STA8 { dst_addr, src } => {
                 let dst: u16 = cpu.get16(dst_addr);
-                mem.set(dst, cpu.get(src));
+                mem.write(dst, cpu.get(src));
                 cycles = 8;
             }
             Instruction::STHA { addr } => {
-                mem.set((0xFF00 + u16::from(addr)) as u16, cpu.get(CpuRegister::A));
+                mem.write((0xFF00 + u16::from(addr)) as u16, cpu.get(CpuRegister::A));
                 cycles = 12;
             }
             Instruction::STHCA => {
                 let addr: u16 = u16::from(cpu.get(CpuRegister::C));
-                mem.set(0xFF00 + addr, cpu.get(CpuRegister::A));
+                mem.write(0xFF00 + addr, cpu.get(CpuRegister::A));
                 cycles = 8;
             }
             Instruction::STI8 { dst_addr, val } => {
-                mem.set(cpu.get16(dst_addr), val);
-                cycles = 8;
+                mem.write(cpu.get16(dst_addr), val);
+                cycles = 12;
             }
             Instruction::STAA { addr } => {
-                mem.set(addr, cpu.get(CpuRegister::A));
+                mem.write(addr, cpu.get(CpuRegister::A));
                 cycles = 16;
             }
             Instruction::STD => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                mem.set(addr, cpu.get(CpuRegister::A));
+                mem.write(addr, cpu.get(CpuRegister::A));
                 cpu.set16(Cpu16Register::HL, addr - 1);
                 cycles = 8;
             }
             Instruction::STI => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                mem.set(addr, cpu.get(CpuRegister::A));
+                mem.write(addr, cpu.get(CpuRegister::A));
                 cpu.set16(Cpu16Register::HL, addr + 1);
                 cycles = 8;
             }
@@ -692,7 +877,7 @@ impl Instruction {
                 cycles = 4;
             }
             Instruction::SUBA { reg_addr } => {
-                let val: u8 = mem.get(cpu.get16(reg_addr));
+                let val: u8 = mem.read(cpu.get16(reg_addr));
                 math::subtract(cpu, val);
                 cycles = 8;
             }
@@ -706,7 +891,7 @@ impl Instruction {
                 cycles = 4;
             }
             Instruction::SBCA { reg_addr } => {
-                let val: u8 = mem.get(cpu.get16(reg_addr));
+                let val: u8 = mem.read(cpu.get16(reg_addr));
                 math::sbc(cpu, val);
                 cycles = 8;
             }
@@ -720,7 +905,7 @@ impl Instruction {
                 cycles = 4;
             }
             Instruction::ADDA => {
-                let val = mem.get(cpu.get16(Cpu16Register::HL));
+                let val = mem.read(cpu.get16(Cpu16Register::HL));
                 math::add(cpu, val);
                 cycles = 8;
             }
@@ -731,7 +916,8 @@ impl Instruction {
             Instruction::ADD16 { src } => {
                 let val: u16 = cpu.get16(src);
                 math::add16(cpu, Cpu16Register::HL, val);
-                cycles = 16;
+                mem.tick();
+                cycles = 8;
             }
             Instruction::ADDSP { val } => {
                 
@@ -745,6 +931,8 @@ impl Instruction {
                 cpu.set16(Cpu16Register::SP, result as u16);
                 
                 cpu.set_flags(false, false, h, c);
+                mem.tick();
+                mem.tick();
                 cycles = 16;
             }
             Instruction::ADC { reg } => {
@@ -753,7 +941,7 @@ impl Instruction {
                 cycles = 4;
             }
             Instruction::ADCA => {
-                let val: u8 = mem.get(cpu.get16(Cpu16Register::HL));
+                let val: u8 = mem.read(cpu.get16(Cpu16Register::HL));
                 math::adc(cpu, val);
                 cycles = 8;
             }
@@ -768,7 +956,7 @@ impl Instruction {
             }
             Instruction::XORA => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                math::xor(cpu, mem.get(addr));
+                math::xor(cpu, mem.read(addr));
                 cycles = 8;
             }
             Instruction::XORI { val } => {
@@ -784,8 +972,8 @@ impl Instruction {
             }
             Instruction::INCA => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let val = math::increment(cpu, mem.get(addr));
-                mem.set(addr, val);
+                let val = math::increment(cpu, mem.read(addr));
+                mem.write(addr, val);
                 cycles = 12;
             }
             Instruction::DEC { reg } => {
@@ -796,16 +984,18 @@ impl Instruction {
             }
             Instruction::DECA => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let val = math::decrement(cpu, mem.get(addr));
-                mem.set(addr, val);
+                let val = math::decrement(cpu, mem.read(addr));
+                mem.write(addr, val);
                 cycles = 12;
             }
             Instruction::INC16 { reg } => {
                 math::increment16(cpu, reg);
+                mem.tick();
                 cycles = 8;
             }
             Instruction::DEC16 { reg } => {
                 math::decrement16(cpu, reg);
+                mem.tick();
                 cycles = 8;
             }
             Instruction::CPL => {
@@ -835,7 +1025,7 @@ impl Instruction {
             }
             Instruction::CMPA => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                math::compare(cpu, mem.get(addr));
+                math::compare(cpu, mem.read(addr));
                 cycles = 8;
             }
             Instruction::ORR { reg } => {
@@ -845,7 +1035,7 @@ impl Instruction {
             }
             Instruction::ORA => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                math::or(cpu, mem.get(addr));
+                math::or(cpu, mem.read(addr));
                 cycles = 8;
             }
             Instruction::ORI { val } => {
@@ -858,7 +1048,7 @@ impl Instruction {
                 cycles = 4;
             }
             Instruction::ANDA => {
-                let val: u8 = mem.get(cpu.get16(Cpu16Register::HL));
+                let val: u8 = mem.read(cpu.get16(Cpu16Register::HL));
                 math::and(cpu, val);
                 cycles = 8;
             }
@@ -867,14 +1057,14 @@ impl Instruction {
                 cycles = 8;
             }
             Instruction::PUSH { reg } => {
-                cpu.sp = cpu.sp.wrapping_sub(2);
-                mem.set16(cpu.sp, cpu.get16(reg));
+                let val = cpu.get16(reg);
+                mem.tick();
+                cpu.push(mem, val);
                 cycles = 16;
             }
             Instruction::POP { reg } => {
-                let sp = cpu.sp;
-                cpu.set16(reg, mem.get16(sp));
-                cpu.sp = cpu.sp.wrapping_add(2);
+                let val = cpu.pop(mem);
+                cpu.set16(reg, val);
                 cycles = 12;
             }
             Instruction::SWAP { reg } => {
@@ -885,8 +1075,8 @@ impl Instruction {
             }
             Instruction::SWAPA => {
                 let addr: u16 = cpu.get16(Cpu16Register::HL);
-                let val = mem.get(addr);
-                mem.set(addr, math::swap_nibble(cpu, val));
+                let val = mem.read(addr);
+                mem.write(addr, math::swap_nibble(cpu, val));
                 cycles = 16;
             }
             Instruction::BIT { n, reg } => {
@@ -896,8 +1086,8 @@ impl Instruction {
             }
             Instruction::BITA { n } => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                math::bit(cpu, mem.get(addr), n);
-                cycles = 16;
+                math::bit(cpu, mem.read(addr), n);
+                cycles = 12;
             }
             Instruction::SET { n, reg } => {
                 let newval = math::set(cpu.get(reg), n);
@@ -906,8 +1096,8 @@ impl Instruction {
             }
             Instruction::SETA { n } => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::set(mem.get(addr), n);
-                mem.set(addr, newval);
+                let newval = math::set(mem.read(addr), n);
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::RESET { n, reg } => {
@@ -917,8 +1107,8 @@ impl Instruction {
             }
             Instruction::RESETA { n } => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::reset(mem.get(addr), n);
-                mem.set(addr, newval);
+                let newval = math::reset(mem.read(addr), n);
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::SLA { reg } => {
@@ -929,8 +1119,8 @@ impl Instruction {
             }
             Instruction::SLAA => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::sla(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::sla(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::SRL { reg } => {
@@ -941,8 +1131,8 @@ impl Instruction {
             }
             Instruction::SRLA => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::srl(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::srl(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::SRA { reg } => {
@@ -953,8 +1143,8 @@ impl Instruction {
             }
             Instruction::SRAA => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::sra(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::sra(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::RLCA => {
@@ -988,8 +1178,8 @@ impl Instruction {
             }
             Instruction::RLCHL => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::rlc(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::rlc(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::RRC { reg } => {
@@ -999,8 +1189,8 @@ impl Instruction {
             }
             Instruction::RRCHL => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::rrc(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::rrc(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::RL { reg } => {
@@ -1010,8 +1200,8 @@ impl Instruction {
             }
             Instruction::RLHL => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::rl(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::rl(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::RR { reg } => {
@@ -1021,8 +1211,8 @@ impl Instruction {
             }
             Instruction::RRHL => {
                 let addr = cpu.get16(Cpu16Register::HL);
-                let newval = math::rr(cpu, mem.get(addr));
-                mem.set(addr, newval);
+                let newval = math::rr(cpu, mem.read(addr));
+                mem.write(addr, newval);
                 cycles = 16;
             }
             Instruction::SCF => {
@@ -1043,19 +1233,27 @@ impl Instruction {
     }
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(None, &|_| None))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cpu::{Cpu, Cpu16Register, CpuRegister};
     use crate::instruction::Instruction;
     use crate::memory::Memory;
+    use crate::model::Model;
     use crate::rom::Cartridge;
 
     #[test]
     fn daa_instruction() {
         let mut cpu = Cpu::new();
-        let mut mem = Memory::new(Cartridge::load_rom(vec![0; 32 * 1024]));
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
 
-        let test_cases = vec![
+        let test_cases = [
             (0b0001_0001, 0b0000_0000, 0b0001_0001),
             (0b0000_1001, 0b0000_0001, 0b0001_0000),
             (0b0001_1000, 0b0001_1000, 0b0011_0110),
@@ -1082,7 +1280,8 @@ mod tests {
     #[test]
     fn subi_instruction() {
         let mut cpu = Cpu::new();
-        let mut mem = Memory::new(Cartridge::load_rom(vec![0; 32 * 1024]));
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
 
         cpu.a = 0xD8;
         cpu.f = 0xC0;
@@ -1098,7 +1297,8 @@ mod tests {
     #[test]
     fn add16_instruction() {
         let mut cpu = Cpu::new();
-        let mut mem = Memory::new(Cartridge::load_rom(vec![0; 32 * 1024]));
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
 
         cpu.a = 0x0F;
         cpu.b = 0x00;
@@ -1122,4 +1322,63 @@ mod tests {
         assert_eq!(cpu.e, 0x01);
         assert_eq!(cpu.f, 0x00);
     }
+
+    #[test]
+    fn per_access_timing() {
+        let mut cpu = Cpu::new();
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+        mem.set_per_access_timing(true);
+
+        // Internal delay, then the two stack writes
+        assert_eq!(Instruction::CALL { addr: 0x0200 }.execute(&mut cpu, &mut mem), 24);
+        assert_eq!(mem.take_ticked(), 12);
+
+        cpu.f = 0x00;
+        assert_eq!(Instruction::RETZ.execute(&mut cpu, &mut mem), 8);
+        assert_eq!(mem.take_ticked(), 4);
+
+        assert_eq!(Instruction::RETNZ.execute(&mut cpu, &mut mem), 20);
+        assert_eq!(mem.take_ticked(), 16);
+        assert_eq!(cpu.pc, 0x0103);
+    }
+
+    #[test]
+    fn jr_extreme_offsets() {
+        let mut cpu = Cpu::new();
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+
+        for &(offset, target) in &[(0x7E, 0x0180), (0x7F, 0x0181), (-0x80, 0x0082)] {
+            cpu.pc = 0x0100;
+            Instruction::JR { offset }.execute(&mut cpu, &mut mem);
+            assert_eq!(cpu.pc, target);
+
+            cpu.pc = 0x0100;
+            cpu.f = 0x80;
+            Instruction::JRZ { offset }.execute(&mut cpu, &mut mem);
+            assert_eq!(cpu.pc, target);
+        }
+    }
+
+    #[test]
+    fn assembler_syntax() {
+        let named = |addr: u16| match addr {
+            0x0150 => Some("Start".to_string()),
+            0xFF40 => Some("rLCDC".to_string()),
+            _ => None,
+        };
+        let format = |bytes: &[u8], pc| Instruction::decode(bytes).format(pc, &named);
+
+        assert_eq!(format(&[0x2A], None), "LD A,[HL+]");
+        assert_eq!(format(&[0x32], None), "LD [HL-],A");
+        assert_eq!(format(&[0xE0, 0x40], None), "LDH [rLCDC],A");
+        assert_eq!(format(&[0xF0, 0x44], None), "LDH A,[$FF44]");
+        assert_eq!(format(&[0xC3, 0x50, 0x01], None), "JP Start");
+        assert_eq!(format(&[0xF8, 0xFE], None), "LD HL,SP-2");
+        assert_eq!(format(&[0x18, 0xFE], None), "JR @+0");
+        assert_eq!(format(&[0x20, 0x0E], Some(0x0140)), "JR NZ,Start");
+        assert_eq!(format(&[0xCB, 0x7E], None), "BIT 7,[HL]");
+        assert_eq!(Instruction::decode(&[0x01, 0x34, 0x12]).to_string(), "LD BC,$1234");
+    }
 }
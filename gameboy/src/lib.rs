@@ -1,5 +1,8 @@
+pub mod bus;
 mod cpu;
+pub mod debugger;
 pub mod disassemble;
+pub mod frame;
 pub mod gameboy;
 pub mod gpu;
 pub mod input;
@@ -7,6 +10,13 @@ pub mod instruction;
 mod interrupt;
 mod math;
 mod memory;
+pub mod model;
+pub mod movie;
 mod opcode;
+pub mod palette;
+pub mod rewind;
 mod rom;
+pub mod sgb;
+pub mod state;
 mod timer;
+pub mod trace;
@@ -1,10 +1,27 @@
+use crate::bus::Bus;
+use crate::gpu::{Gpu, GpuMode};
 use crate::input::Input;
-use crate::interrupt::{set_interrupt, Interrupt};
+use crate::interrupt::{self, set_interrupt, Interrupt};
+use crate::model::Model;
 use crate::rom::Cartridge;
+use crate::state::{StateError, StateReader, StateWriter};
 use crate::timer::Timer;
 
+/// A read or write, logged for the debugger's watchpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: u16,
+    // Value before the access, the same as `value` for reads
+    pub old: u8,
+    pub value: u8,
+    pub write: bool,
+}
+
 pub struct Memory {
+    model: Model,
     cartridge: Cartridge,
+    // Mapped over the start of the cartridge until 0xFF50 is written
+    boot_rom: Option<Vec<u8>>,
     vram: Vec<u8>,
     ram: Vec<u8>,
     sprite: Vec<u8>,
@@ -17,8 +34,26 @@ pub struct Memory {
     // Input & Timer are always interfaced via the MMU
     input: Input,
     timer: Timer,
+    // Owned here so CPU accesses can advance it
+    gpu: Gpu,
+    frame_ready: bool,
+
+    // Advance the rest of the system on each CPU access rather than once
+    // per instruction
+    per_access_timing: bool,
+    // Cycles advanced by CPU accesses since the last `take_ticked`
+    ticked: u8,
+
+    log_accesses: bool,
+    accesses: Vec<MemAccess>,
 
     serial_buf: Vec<u8>,
+    serial_echo: bool,
+
+    // Mode the Gpu was in after its last cycle, None while the LCD is off
+    gpu_mode: Option<GpuMode>,
+    // VRAM & OAM are inaccessible to the CPU & DMA while the Gpu is using them
+    access_locking: bool,
 }
 
 const VRAM_SIZE: usize = 8 * 1024;
@@ -29,11 +64,81 @@ const HIGHRAM_SIZE: usize = 128;
 
 const MAX_SERIAL_BUF_LEN: usize = 50000;
 
+pub const BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+// IO register values left behind by the DMG boot ROM (0xFF00 - 0xFF4B).
+// See `post_boot_io` for the differences on other models.
+#[rustfmt::skip]
+const DMG_IO_DEFAULTS: [u8; IO_SIZE] = [
+    // P1    SB    SC          DIV   TIMA  TMA   TAC
+    0xCF, 0x00, 0x7E, 0xFF, 0xAB, 0x00, 0x00, 0xF8,
+    //                                         IF
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE1,
+    // NR10 NR11  NR12  NR13  NR14        NR21  NR22
+    0x80, 0xBF, 0xF3, 0xFF, 0xBF, 0xFF, 0x3F, 0x00,
+    // NR23 NR24  NR30  NR31  NR32  NR33  NR34
+    0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41 NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x77, 0xF3, 0xF1, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC STAT  SCY   SCX   LY    LYC   DMA   BGP
+    0x91, 0x85, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFC,
+    // OBP0 OBP1  WY    WX
+    0xFF, 0xFF, 0x00, 0x00,
+];
+
+fn post_boot_io(model: Model) -> [u8; IO_SIZE] {
+    let mut io = DMG_IO_DEFAULTS;
+
+    // Where the value after boot is unknown or varies the DMG value is kept
+    match model {
+        Model::Dmg0 => {
+            io[0x04] = 0x18;
+            io[0x41] = 0x81;
+        }
+        Model::Dmg | Model::Mgb => {}
+        Model::Sgb => {
+            io[0x00] = 0xC7;
+            io[0x26] = 0xF0;
+        }
+        Model::Cgb | Model::Agb => {
+            io[0x00] = 0xC7;
+            io[0x02] = 0x7F;
+        }
+    }
+
+    io
+}
+
 impl Memory {
-    pub fn new(cartridge: Cartridge) -> Memory {
+    /// Fails if `boot_rom` isn't the size of a DMG or CGB boot ROM
+    pub fn new(
+        model: Model,
+        cartridge: Cartridge,
+        boot_rom: Option<Vec<u8>>,
+    ) -> Result<Memory, String> {
+        if let Some(boot_rom) = boot_rom.as_ref() {
+            if boot_rom.len() != BOOT_ROM_SIZE && boot_rom.len() != CGB_BOOT_ROM_SIZE {
+                return Err(format!(
+                    "Unsupported boot ROM size {}, expected {} or {} bytes",
+                    boot_rom.len(),
+                    BOOT_ROM_SIZE,
+                    CGB_BOOT_ROM_SIZE
+                ));
+            }
+        }
+
+        let post_boot = boot_rom.is_none();
+
         // Move rom contents into Memory
         let mut mem = Memory {
+            model,
             cartridge,
+            boot_rom,
             vram: vec![0; VRAM_SIZE],
             ram: vec![0; RAM_SIZE],
             sprite: vec![0; SPRITE_SIZE],
@@ -43,19 +148,45 @@ impl Memory {
             unused: 0,
             input: Input::new(),
             timer: Timer::new(),
+            gpu: Gpu::new(),
+            frame_ready: false,
+
+            per_access_timing: false,
+            ticked: 0,
+
+            log_accesses: false,
+            accesses: Vec::new(),
 
             serial_buf: Vec::new(),
+            serial_echo: true,
+
+            gpu_mode: None,
+            access_locking: true,
         };
 
-        mem.set(0xFF40, 0x91);
-        mem.set(0xFF47, 0xFC);
-        mem.set(0xFF48, 0xFF);
-        mem.set(0xFF49, 0xFF);
+        // Without a boot ROM to run, start from the state it would have left behind
+        if post_boot {
+            let io = post_boot_io(model);
+            mem.io.copy_from_slice(&io);
+            mem.timer = Timer::with_divider(io[0x04]);
+            for addr in 0xFF05..=0xFF07 {
+                mem.timer.write(addr, io[(addr - 0xFF00) as usize]);
+            }
+        }
 
-        mem
+        Ok(mem)
     }
 
     pub fn get(&self, addr: u16) -> u8 {
+        if self.locked(addr) {
+            return 0xFF;
+        }
+
+        self.get_unlocked(addr)
+    }
+
+    /// Read ignoring the VRAM / OAM locking, for use by the Gpu itself
+    pub fn get_unlocked(&self, addr: u16) -> u8 {
         match addr {
             0xFF00 => self.input.value(),
             0xFF4D => 0xFF,
@@ -79,7 +210,155 @@ impl Memory {
         self.set(addr + 1, high as u8);
     }
 
+    /// Everything the CPU can see, & the parts of the machine behind it.
+    /// Settings such as timing & access logging aren't included.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        // The boot ROM's contents go too, as there's nothing to restore them
+        // from once unmapped
+        state.bool(self.boot_rom.is_some());
+        if let Some(boot_rom) = self.boot_rom.as_ref() {
+            state.bytes(boot_rom);
+        }
+        state.bytes(&self.vram);
+        state.bytes(&self.ram);
+        state.bytes(&self.sprite);
+        state.bytes(&self.io);
+        state.bytes(&self.highram);
+        state.u8(self.unused);
+        state.bool(self.frame_ready);
+        state.u8(self.gpu_mode.map_or(0xFF, GpuMode::number));
+
+        self.cartridge.save_state(state);
+        self.input.save_state(state);
+        self.timer.save_state(state);
+        self.gpu.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.boot_rom = if state.bool()? {
+            let boot_rom = state.bytes()?;
+            if boot_rom.len() != BOOT_ROM_SIZE && boot_rom.len() != CGB_BOOT_ROM_SIZE {
+                return Err(StateError::Invalid("boot ROM size"));
+            }
+            Some(boot_rom.to_vec())
+        } else {
+            None
+        };
+        state.bytes_into(&mut self.vram)?;
+        state.bytes_into(&mut self.ram)?;
+        state.bytes_into(&mut self.sprite)?;
+        state.bytes_into(&mut self.io)?;
+        state.bytes_into(&mut self.highram)?;
+        self.unused = state.u8()?;
+        self.frame_ready = state.bool()?;
+        self.gpu_mode = match state.u8()? {
+            0xFF => None,
+            mode => Some(GpuMode::from_number(mode)),
+        };
+
+        self.cartridge.load_state(state)?;
+        self.input.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.gpu.load_state(state)?;
+
+        self.ticked = 0;
+        self.accesses.clear();
+
+        Ok(())
+    }
+
+    /// Record CPU reads & writes until the next `clear_accesses`
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.log_accesses = enabled;
+    }
+
+    pub fn accesses(&self) -> &[MemAccess] {
+        &self.accesses
+    }
+
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+
+    /// The whole cartridge ROM, regardless of which bank is mapped
+    pub fn rom(&self) -> &[u8] {
+        &self.cartridge.rom_contents
+    }
+
+    /// ROM bank mapped at `addr`, 0 outside of the switchable bank
+    pub fn rom_bank(&self, addr: u16) -> u8 {
+        match addr {
+            0x4000..=0x7FFF => self.cartridge.rom_bank(),
+            _ => 0,
+        }
+    }
+
+    /// An M-cycle of CPU time, used for instruction fetch and internal delays
+    pub fn mcycle(&mut self) {
+        if self.per_access_timing {
+            self.advance(4);
+            self.ticked += 4;
+        }
+    }
+
+    /// Cycles already advanced by CPU accesses since the last call
+    pub fn take_ticked(&mut self) -> u8 {
+        std::mem::replace(&mut self.ticked, 0)
+    }
+
+    pub fn set_per_access_timing(&mut self, enabled: bool) {
+        self.per_access_timing = enabled;
+    }
+
+    /// Advance the Gpu & timer
+    pub fn advance(&mut self, cycles: u8) {
+        let (gpu, mut bus) = self.split_gpu();
+        if gpu.cycle(&mut bus, cycles) {
+            self.frame_ready = true;
+        }
+
+        if self.timer.tick(cycles) {
+            set_interrupt(Interrupt::Timer, self);
+        }
+    }
+
+    /// True if the Gpu has completed a frame since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn gpu_mut(&mut self) -> &mut Gpu {
+        &mut self.gpu
+    }
+
+    /// The Gpu along with the memory it draws from, borrowed separately
+    pub(crate) fn split_gpu(&mut self) -> (&mut Gpu, GpuBus<'_>) {
+        let bus = GpuBus {
+            vram: &self.vram,
+            sprite: &self.sprite,
+            io: &mut self.io,
+            interrupt_enable: self.highram[HIGHRAM_SIZE - 1],
+            gpu_mode: &mut self.gpu_mode,
+        };
+
+        (&mut self.gpu, bus)
+    }
+
     fn mmu(&self, addr: u16) -> &u8 {
+        if let Some(boot_rom) = self.boot_rom.as_ref() {
+            match addr {
+                0x0000..=0x00FF => return &boot_rom[addr as usize],
+                0x0200..=0x08FF if boot_rom.len() == CGB_BOOT_ROM_SIZE => {
+                    return &boot_rom[addr as usize];
+                }
+                _ => {}
+            }
+        }
+
         match addr {
             0x0000..=0x7FFF => self.cartridge.mbc(addr),
             0x8000..=0x9FFF => &self.vram[(addr - 0x8000) as usize],
@@ -94,7 +373,37 @@ impl Memory {
         }
     }
 
+    fn locked(&self, addr: u16) -> bool {
+        if !self.access_locking {
+            return false;
+        }
+
+        matches!(
+            (addr, self.gpu_mode),
+            (0x8000..=0x9FFF, Some(GpuMode::VRAMRead))
+                | (0xFE00..=0xFE9F, Some(GpuMode::OAMRead))
+                | (0xFE00..=0xFE9F, Some(GpuMode::VRAMRead))
+        )
+    }
+
+    pub fn set_gpu_mode(&mut self, mode: Option<GpuMode>) {
+        self.gpu_mode = mode;
+    }
+
+    /// Update STAT from the Gpu side, bypassing the CPU write quirks
+    pub fn set_stat(&mut self, val: u8) {
+        self.io[0x41] = val;
+    }
+
+    pub fn set_access_locking(&mut self, enabled: bool) {
+        self.access_locking = enabled;
+    }
+
     pub fn set(&mut self, addr: u16, val: u8) {
+        if self.locked(addr) {
+            return;
+        }
+
         match addr {
             0x0000..=0x7FFF => self.cartridge.mbc_write(addr, val),
             0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize] = val,
@@ -107,7 +416,7 @@ impl Memory {
             0xFF01 => {
                 self.serial_buf.push(val);
 
-                if val as char == '\n' {
+                if self.serial_echo && val as char == '\n' {
                     println!("{}", String::from_utf8_lossy(&self.serial_buf));
                 }
 
@@ -116,35 +425,59 @@ impl Memory {
                 }
             }
             0xFF02..=0xFF03 => {}, // unimplemented
-            0xFF04..=0xFF06 => self.timer.write(addr, val),
+            0xFF04..=0xFF07 => {
+                if self.timer.write(addr, val) {
+                    set_interrupt(Interrupt::Timer, self);
+                }
+            }
+            0xFF41 => {
+                self.io[0x41] = val;
+
+                let blanking = matches!(self.gpu_mode, Some(GpuMode::HBlank) | Some(GpuMode::VBlank));
+                if self.model.has_stat_write_bug() && blanking {
+                    set_interrupt(Interrupt::LcdStat, self);
+                }
+            }
             0xFF00..=0xFF45 | 0xFF47..=0xFF4B => self.io[(addr - 0xFF00) as usize] = val,
             0xFF46 => {
                 // OAM Write
+                // DMA reads are subject to VRAM locking, but it owns the OAM bus
+                // so the writes always land.
                 // TODO SLOW This could be a lot faster
                 let source: u16 = u16::from(val) << 8;
-                let target: u16 = 0xFE00;
-                for i in 0..160 {
-                    let val: u8 = self.get(source + i);
-                    self.set(target + i, val);
+                for i in 0..SPRITE_SIZE {
+                    self.sprite[i] = self.get(source + i as u16);
                 }
             },
             0xFF4C => {},
             0xFF4D => {
                 println!("Speed");
             }
+            0xFF50 => {
+                if val != 0 {
+                    self.boot_rom = None;
+                }
+            }
             0xFF4E..=0xFF7F => {},
             0xFF80..=0xFFFF => self.highram[(addr - 0xFF80) as usize] = val,
         }
     }
 
-    pub fn input(&mut self) -> &mut Input {
-        &mut self.input
+    pub fn model(&self) -> Model {
+        self.model
     }
 
-    pub fn tick_timer(&mut self, cycles: u8) {
-        if self.timer.tick(cycles) {
-            set_interrupt(Interrupt::Timer, self);
-        }
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /// Contents of the boot ROM while it's still mapped
+    pub fn boot_rom(&self) -> Option<&[u8]> {
+        self.boot_rom.as_deref()
+    }
+
+    pub fn input(&mut self) -> &mut Input {
+        &mut self.input
     }
 
     pub fn clone_bytes(&self, start: u16, len: u16) -> Vec<u8> {
@@ -162,4 +495,271 @@ impl Memory {
     pub fn serial_buffer(&self) -> &[u8] {
         &self.serial_buf
     }
+
+    /// Print each line sent over the serial port to stdout
+    pub fn set_serial_echo(&mut self, enabled: bool) {
+        self.serial_echo = enabled;
+    }
+}
+
+impl Bus for Memory {
+    /// CPU read. With per access timing enabled this first advances the rest
+    /// of the system by one M-cycle.
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        self.mcycle();
+        let value = self.get(addr);
+
+        // Logged here rather than in `get` & `set`, which DMA & debugging
+        // tools also use
+        if self.log_accesses {
+            self.accesses.push(MemAccess {
+                addr,
+                old: value,
+                value,
+                write: false,
+            });
+        }
+
+        value
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, val: u8) {
+        self.mcycle();
+
+        if self.log_accesses {
+            let old = self.get_unlocked(addr);
+            self.accesses.push(MemAccess {
+                addr,
+                old,
+                value: val,
+                write: true,
+            });
+        }
+
+        self.set(addr, val);
+    }
+
+    #[inline]
+    fn tick(&mut self) {
+        self.mcycle();
+    }
+
+    #[inline]
+    fn peek(&self, addr: u16) -> u8 {
+        self.get(addr)
+    }
+
+    /// Unlike `read`, not logged as an access to memory
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.mcycle();
+        self.get(addr)
+    }
+}
+
+/// The parts of memory the Gpu uses, which it reads without VRAM & OAM
+/// locking & writes without the CPU's side effects
+pub struct GpuBus<'a> {
+    vram: &'a [u8],
+    sprite: &'a [u8],
+    io: &'a mut [u8],
+    interrupt_enable: u8,
+    gpu_mode: &'a mut Option<GpuMode>,
+}
+
+impl GpuBus<'_> {
+    /// VRAM, OAM & the IO registers
+    pub fn get(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize],
+            0xFE00..=0xFE9F => self.sprite[(addr - 0xFE00) as usize],
+            0xFF00..=0xFF4B => self.io[(addr - 0xFF00) as usize],
+            _ => panic!("Gpu read from 0x{:04X}", addr),
+        }
+    }
+
+    pub fn set_ly(&mut self, line: u8) {
+        self.io[0x44] = line;
+    }
+
+    pub fn set_stat(&mut self, val: u8) {
+        self.io[0x41] = val;
+    }
+
+    pub fn set_gpu_mode(&mut self, mode: Option<GpuMode>) {
+        *self.gpu_mode = mode;
+    }
+
+    pub fn set_interrupt(&mut self, int: Interrupt) {
+        self.io[0x0F] = interrupt::requested(int, self.io[0x0F], self.interrupt_enable);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::Bus;
+    use crate::gpu::GpuMode;
+    use crate::memory::Memory;
+    use crate::model::Model;
+    use crate::rom::Cartridge;
+
+    #[test]
+    fn vram_oam_locking() {
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+
+        mem.set(0x8000, 0x12);
+        mem.set(0xFE00, 0x34);
+
+        mem.set_gpu_mode(Some(GpuMode::OAMRead));
+        assert_eq!(mem.get(0x8000), 0x12);
+        assert_eq!(mem.get(0xFE00), 0xFF);
+
+        mem.set_gpu_mode(Some(GpuMode::VRAMRead));
+        assert_eq!(mem.get(0x8000), 0xFF);
+        assert_eq!(mem.get(0xFE00), 0xFF);
+        mem.set(0x8000, 0x56);
+        assert_eq!(mem.get_unlocked(0x8000), 0x12);
+
+        mem.set_access_locking(false);
+        assert_eq!(mem.get(0x8000), 0x12);
+        assert_eq!(mem.get(0xFE00), 0x34);
+
+        mem.set_access_locking(true);
+        mem.set_gpu_mode(Some(GpuMode::HBlank));
+        mem.set(0x8000, 0x56);
+        assert_eq!(mem.get(0x8000), 0x56);
+    }
+
+    #[test]
+    fn boot_rom_unmapped_by_ff50() {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x0000] = 0x11;
+        rom[0x0100] = 0x22;
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[0x0000] = 0x33;
+
+        let mut mem = Memory::new(Model::Dmg, Cartridge::load_rom(rom), Some(boot_rom)).unwrap();
+
+        assert!(mem.boot_rom_mapped());
+        assert_eq!(mem.get(0x0000), 0x33);
+        assert_eq!(mem.get(0x0100), 0x22);
+        assert_eq!(mem.get(0xFF40), 0x00);
+
+        mem.set(0xFF50, 0x01);
+
+        assert!(!mem.boot_rom_mapped());
+        assert_eq!(mem.get(0x0000), 0x11);
+    }
+
+    #[test]
+    fn timer_through_bus() {
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+        mem.set(0xFF0F, 0);
+        mem.set(0xFF06, 0x10);
+        mem.set(0xFF05, 0xFE);
+        // Enabled, counting every 16 cycles
+        mem.set(0xFF07, 0b101);
+        assert_eq!(mem.get(0xFF07), 0b101);
+
+        mem.advance(16);
+        assert_eq!(mem.get(0xFF05), 0xFF);
+        assert_eq!(mem.get(0xFF0F) & 0b100, 0);
+
+        mem.advance(16);
+        assert_eq!(mem.get(0xFF05), 0x10);
+        assert_eq!(mem.get(0xFF0F) & 0b100, 0b100);
+    }
+
+    #[test]
+    fn wrong_size_boot_rom() {
+        let cartridge = Cartridge::load_rom(vec![0; 32 * 1024]);
+        assert!(Memory::new(Model::Dmg, cartridge, Some(vec![0; 0x200])).is_err());
+    }
+
+    #[test]
+    fn post_boot_io_state() {
+        let mem = Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+
+        assert_eq!(mem.get(0xFF04), 0xAB);
+        assert_eq!(mem.get(0xFF07), 0xF8);
+        assert_eq!(mem.get(0xFF0F), 0xE1);
+        assert_eq!(mem.get(0xFF26), 0xF1);
+        assert_eq!(mem.get(0xFF40), 0x91);
+        assert_eq!(mem.get(0xFF41), 0x85);
+        assert_eq!(mem.get(0xFF47), 0xFC);
+
+        let mem = Memory::new(Model::Dmg0, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+        assert_eq!(mem.get(0xFF04), 0x18);
+        assert_eq!(mem.get(0xFF41), 0x81);
+    }
+
+    #[test]
+    fn stat_write_bug() {
+        for &(model, expected) in &[(Model::Dmg, true), (Model::Cgb, false)] {
+            let mut mem =
+                Memory::new(model, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+            mem.set(0xFF0F, 0x00);
+            mem.set_gpu_mode(Some(GpuMode::HBlank));
+
+            mem.set(0xFF41, 0x40);
+
+            assert_eq!(mem.get(0xFF0F) & 0x02 != 0, expected);
+        }
+    }
+
+    #[test]
+    fn per_access_timing() {
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+
+        mem.read(0xC000);
+        assert_eq!(mem.take_ticked(), 0);
+
+        mem.set_per_access_timing(true);
+        mem.read(0xC000);
+        mem.write16(0xC000, 0x1234);
+        mem.mcycle();
+        assert_eq!(mem.take_ticked(), 16);
+        assert_eq!(mem.take_ticked(), 0);
+    }
+
+    #[test]
+    fn only_cpu_accesses_logged() {
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+        mem.set_access_log(true);
+
+        mem.set(0xC000, 0x12);
+        assert_eq!(mem.get(0xC000), 0x12);
+        assert!(mem.accesses().is_empty());
+
+        mem.write(0xC000, 0x34);
+        mem.read(0xC000);
+        let logged: Vec<_> = mem
+            .accesses()
+            .iter()
+            .map(|a| (a.addr, a.old, a.value, a.write))
+            .collect();
+        assert_eq!(logged, [(0xC000, 0x12, 0x34, true), (0xC000, 0x34, 0x34, false)]);
+    }
+
+    #[test]
+    fn dma_reads_are_locked() {
+        let mut mem =
+            Memory::new(Model::Dmg, Cartridge::load_rom(vec![0; 32 * 1024]), None).unwrap();
+
+        mem.set(0x8000, 0x12);
+        mem.set(0xC000, 0x34);
+
+        mem.set_gpu_mode(Some(GpuMode::VRAMRead));
+        mem.set(0xFF46, 0x80);
+        assert_eq!(mem.get_unlocked(0xFE00), 0xFF);
+
+        mem.set(0xFF46, 0xC0);
+        assert_eq!(mem.get_unlocked(0xFE00), 0x34);
+    }
 }
@@ -0,0 +1,68 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Console hardware revision being emulated. Drives the post-boot register
+/// state and a handful of hardware quirks that games can detect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    /// Original Game Boy, early boot ROM revision
+    Dmg0,
+    /// Original Game Boy
+    #[default]
+    Dmg,
+    /// Game Boy Pocket / Light
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Game Boy Color
+    Cgb,
+    /// Game Boy Advance
+    Agb,
+}
+
+impl Model {
+    pub fn is_sgb(self) -> bool {
+        self == Model::Sgb
+    }
+
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb || self == Model::Agb
+    }
+
+    /// Writing to STAT during HBlank / VBlank raises a spurious LCD STAT
+    /// interrupt on the monochrome hardware
+    pub fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            "agb" => Ok(Model::Agb),
+            _ => Err(format!("Unknown model {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Model::Dmg0 => "DMG0",
+            Model::Dmg => "DMG",
+            Model::Mgb => "MGB",
+            Model::Sgb => "SGB",
+            Model::Cgb => "CGB",
+            Model::Agb => "AGB",
+        };
+
+        write!(f, "{}", name)
+    }
+}
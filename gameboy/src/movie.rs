@@ -0,0 +1,471 @@
+//! Input recordings, to replay a run exactly for a bug report or tool
+//! assisted play. A movie holds a hash of the ROM it was made with, where it
+//! started from & every button change with the frame it applies on.
+//!
+//! Replays come out the same because nothing in the emulator depends on the
+//! outside world: there's no cartridge real time clock, memory powers on
+//! zeroed rather than random, and the host's clock is never read. Anything
+//! like that added later needs to go in the save state, or be seeded from
+//! the movie.
+
+use crate::input::{Button, MAX_PLAYERS};
+use crate::model::Model;
+use crate::state::{StateReader, StateWriter};
+
+const MAGIC: &[u8; 4] = b"GBMV";
+const VERSION: u8 = 1;
+
+const VBM_MAGIC: &[u8; 4] = b"VBM\x1A";
+const VBM_HEADER_SIZE: usize = 0x40;
+const ZIP_MAGIC: &[u8; 4] = b"PK\x03\x04";
+
+const HEADER_CHECKSUM_OFFSET: usize = 0x14D;
+
+/// A button pressed or released at the start of `frame`, once that many
+/// frames have completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputChange {
+    pub frame: u64,
+    pub player: u8,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieStart {
+    /// From power on, with a hash of the boot ROM if one ran
+    PowerOn { boot_rom: Option<u64> },
+    /// From a `GameBoy::save_state`
+    State(Vec<u8>),
+}
+
+/// Buttons held by each player, a bit per `Button::index`
+pub type Held = [u8; MAX_PLAYERS];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub model: Model,
+    pub start: MovieStart,
+    /// Frame count when recording started & stopped
+    pub start_frame: u64,
+    pub end_frame: u64,
+    /// In frame order
+    pub events: Vec<InputChange>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], model: Model, start: MovieStart, start_frame: u64) -> Movie {
+        Movie {
+            rom_hash: hash(rom),
+            model,
+            start,
+            start_frame,
+            end_frame: start_frame,
+            events: Vec::new(),
+        }
+    }
+
+    /// Length in frames
+    pub fn frames(&self) -> u64 {
+        self.end_frame - self.start_frame
+    }
+
+    /// Buttons held going into `frame`, before its own changes
+    pub fn held_at(&self, frame: u64) -> Held {
+        let mut held = [0; MAX_PLAYERS];
+
+        for event in self.events.iter().take_while(|e| e.frame < frame) {
+            let bit = 1 << event.button.index();
+            let buttons = &mut held[event.player as usize];
+            if event.pressed {
+                *buttons |= bit;
+            } else {
+                *buttons &= !bit;
+            }
+        }
+
+        held
+    }
+
+    /// Drop everything from `frame` on, to record over it
+    pub fn truncate(&mut self, frame: u64) {
+        self.events.retain(|e| e.frame < frame);
+        self.end_frame = frame.max(self.start_frame);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        MAGIC.iter().for_each(|&b| out.u8(b));
+        out.u8(VERSION);
+
+        out.u64(self.rom_hash);
+        out.bytes(self.model.to_string().as_bytes());
+        match &self.start {
+            MovieStart::PowerOn { boot_rom } => {
+                out.u8(0);
+                out.bool(boot_rom.is_some());
+                out.u64(boot_rom.unwrap_or(0));
+            }
+            MovieStart::State(state) => {
+                out.u8(1);
+                out.bytes(state);
+            }
+        }
+        out.u64(self.start_frame);
+        out.u64(self.end_frame);
+
+        out.u32(self.events.len() as u32);
+        for event in &self.events {
+            out.u64(event.frame);
+            out.u8(event.player);
+            out.u8(event.button.index() as u8);
+            out.bool(event.pressed);
+        }
+
+        out.into_bytes()
+    }
+
+    /// Read a movie written by `to_bytes`
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, String> {
+        if !data.starts_with(MAGIC) {
+            return Err("Not a movie file".to_string());
+        }
+
+        let mut data = StateReader::new(&data[MAGIC.len()..]);
+        let version = data.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported movie version {}", version));
+        }
+
+        let rom_hash = data.u64()?;
+        let model = String::from_utf8_lossy(data.bytes()?).parse()?;
+        let start = match data.u8()? {
+            0 => {
+                let has_boot_rom = data.bool()?;
+                let boot_rom = data.u64()?;
+                MovieStart::PowerOn {
+                    boot_rom: if has_boot_rom { Some(boot_rom) } else { None },
+                }
+            }
+            1 => MovieStart::State(data.bytes()?.to_vec()),
+            kind => return Err(format!("Unknown movie start {}", kind)),
+        };
+        let start_frame = data.u64()?;
+        let end_frame = data.u64()?;
+        if end_frame < start_frame {
+            return Err(format!(
+                "Movie ends at frame {} before it starts",
+                end_frame
+            ));
+        }
+
+        let count = data.u32()?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let frame = data.u64()?;
+            let player = data.u8()?;
+            let button = data.u8()?;
+            let pressed = data.bool()?;
+
+            if usize::from(player) >= MAX_PLAYERS {
+                return Err(format!("Invalid player {}", player));
+            }
+            let button = *Button::ALL
+                .get(usize::from(button))
+                .ok_or_else(|| format!("Invalid button {}", button))?;
+
+            events.push(InputChange {
+                frame,
+                player,
+                button,
+                pressed,
+            });
+        }
+
+        data.finish()?;
+
+        Ok(Movie {
+            rom_hash,
+            model,
+            start,
+            start_frame,
+            end_frame,
+            events,
+        })
+    }
+
+    /// Any of the formats read by `from_bytes`, `import_vbm` or
+    /// `import_bk2_log`, told apart by their contents. `rom` & `model` are
+    /// only used by the imports.
+    pub fn parse(data: &[u8], rom: &[u8], model: Model) -> Result<Movie, String> {
+        if data.starts_with(MAGIC) {
+            Movie::from_bytes(data)
+        } else if data.starts_with(VBM_MAGIC) {
+            Movie::import_vbm(data, rom, model)
+        } else if data.starts_with(ZIP_MAGIC) {
+            Err("BK2 movies are zip archives, extract the Input Log.txt inside".to_string())
+        } else {
+            match std::str::from_utf8(data) {
+                Ok(text) if text.contains("LogKey:") => Movie::import_bk2_log(text, rom, model),
+                _ => Err("Unknown movie format".to_string()),
+            }
+        }
+    }
+
+    /// Import a VisualBoyAdvance movie recorded from power on. It'll only
+    /// stay in sync as long as the two emulators' timing agrees.
+    pub fn import_vbm(data: &[u8], rom: &[u8], model: Model) -> Result<Movie, String> {
+        if !data.starts_with(VBM_MAGIC) || data.len() < VBM_HEADER_SIZE {
+            return Err("Not a VBM movie".to_string());
+        }
+
+        let le_u32 = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&data[offset..offset + 4]);
+            u32::from_le_bytes(bytes) as usize
+        };
+
+        let frame_count = le_u32(0x0C);
+        if data[0x14] & 0b11 != 0 {
+            return Err("Only VBM movies starting from power on can be imported".to_string());
+        }
+        if data[0x16] & 0b1 != 0 {
+            return Err("VBM movie is for the Game Boy Advance".to_string());
+        }
+        if rom.get(HEADER_CHECKSUM_OFFSET) != Some(&data[0x31]) {
+            return Err("VBM movie was recorded with a different ROM".to_string());
+        }
+
+        // Two bytes a frame for each controller in use, with the buttons
+        // in the same bit order as `Button::ALL`
+        let controllers: Vec<usize> = (0..MAX_PLAYERS)
+            .filter(|c| data[0x15] & (1 << c) != 0)
+            .collect();
+        let stride = controllers.len() * 2;
+        let start = le_u32(0x3C);
+        let input = data
+            .get(start..start + frame_count * stride)
+            .ok_or("VBM movie is truncated")?;
+
+        let frames = input
+            .chunks(stride.max(1))
+            .take(frame_count)
+            .map(|frame| {
+                let mut held = [0; MAX_PLAYERS];
+                for (i, &player) in controllers.iter().enumerate() {
+                    held[player] = frame[i * 2];
+                }
+                held
+            })
+            .collect();
+
+        Ok(Movie::from_frames(rom, model, frames))
+    }
+
+    /// Import the `Input Log.txt` from a BizHawk BK2 movie recorded from
+    /// power on. There's a line per frame with a character per key named
+    /// in the `LogKey` line, `.` when it's up.
+    pub fn import_bk2_log(text: &str, rom: &[u8], model: Model) -> Result<Movie, String> {
+        let mut keys: Vec<Option<(usize, Button)>> = Vec::new();
+        let mut frames = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if let Some(log_key) = line.strip_prefix("LogKey:") {
+                keys = log_key
+                    .split('|')
+                    .map(|key| key.trim_start_matches('#'))
+                    .filter(|key| !key.is_empty())
+                    .map(bk2_key)
+                    .collect();
+            } else if line.starts_with('|') {
+                if keys.is_empty() {
+                    return Err("BK2 input log has no LogKey".to_string());
+                }
+
+                let mut held = [0; MAX_PLAYERS];
+                for (key, c) in keys.iter().zip(line.chars().filter(|&c| c != '|')) {
+                    if let Some((player, button)) = key {
+                        if c != '.' && c != ' ' {
+                            held[*player] |= 1 << button.index();
+                        }
+                    }
+                }
+                frames.push(held);
+            }
+        }
+
+        Ok(Movie::from_frames(rom, model, frames))
+    }
+
+    /// From the buttons held on each frame after power on
+    fn from_frames(rom: &[u8], model: Model, frames: Vec<Held>) -> Movie {
+        let mut movie = Movie::new(rom, model, MovieStart::PowerOn { boot_rom: None }, 0);
+        let mut previous = [0; MAX_PLAYERS];
+
+        for (frame, held) in frames.iter().enumerate() {
+            for player in 0..MAX_PLAYERS {
+                let changed = previous[player] ^ held[player];
+                for button in Button::ALL.iter() {
+                    let bit = 1 << button.index();
+                    if changed & bit != 0 {
+                        movie.events.push(InputChange {
+                            frame: frame as u64,
+                            player: player as u8,
+                            button: *button,
+                            pressed: held[player] & bit != 0,
+                        });
+                    }
+                }
+            }
+            previous = *held;
+        }
+
+        movie.end_frame = frames.len() as u64;
+        movie
+    }
+}
+
+/// `Up`, or `P2 Up` with more than one controller. None for keys which
+/// aren't joypad buttons, like `Power`.
+fn bk2_key(name: &str) -> Option<(usize, Button)> {
+    let (player, button) = match name.strip_prefix('P') {
+        Some(rest) => match rest.split_once(' ') {
+            Some((number, button)) => (number.parse::<usize>().ok()?.checked_sub(1)?, button),
+            None => (0, name),
+        },
+        None => (0, name),
+    };
+
+    if player >= MAX_PLAYERS {
+        return None;
+    }
+    Some((player, button.parse().ok()?))
+}
+
+/// 64-bit FNV-1a, to recognise the ROM & boot ROM a movie was made with
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(frame: u64, button: Button, pressed: bool) -> InputChange {
+        InputChange {
+            frame,
+            player: 0,
+            button,
+            pressed,
+        }
+    }
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[HEADER_CHECKSUM_OFFSET] = 0x5A;
+        rom
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut movie = Movie::new(&rom(), Model::Sgb, MovieStart::State(vec![1, 2, 3]), 10);
+        movie.events.push(change(12, Button::Start, true));
+        movie.events.push(change(15, Button::Start, false));
+        movie.end_frame = 20;
+
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()), Ok(movie.clone()));
+        assert_eq!(
+            Movie::parse(&movie.to_bytes(), &[], Model::Dmg),
+            Ok(movie.clone())
+        );
+        assert_eq!(movie.frames(), 10);
+        assert_eq!(movie.held_at(15), [1 << Button::Start.index(), 0, 0, 0]);
+
+        movie.truncate(13);
+        assert_eq!(movie.events, vec![change(12, Button::Start, true)]);
+        assert_eq!(movie.end_frame, 13);
+
+        assert!(Movie::from_bytes(b"nope").is_err());
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut movie = Movie::new(
+            &rom(),
+            Model::Dmg,
+            MovieStart::PowerOn { boot_rom: None },
+            0,
+        );
+        movie.events.push(change(1, Button::A, true));
+        let bytes = movie.to_bytes();
+
+        for len in MAGIC.len()..bytes.len() {
+            assert!(Movie::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(Movie::from_bytes(&longer).is_err());
+
+        // A huge event count
+        let mut corrupt = bytes;
+        let count = corrupt.len() - 15;
+        corrupt[count..count + 4].copy_from_slice(&[0xFF; 4]);
+        assert!(Movie::from_bytes(&corrupt).is_err());
+    }
+
+    #[test]
+    fn import_vbm() {
+        let mut vbm = vec![0; VBM_HEADER_SIZE];
+        vbm[..4].copy_from_slice(VBM_MAGIC);
+        vbm[0x0C] = 3;
+        vbm[0x15] = 0b1;
+        vbm[0x31] = 0x5A;
+        vbm[0x3C] = VBM_HEADER_SIZE as u8;
+        // A, A & Right, nothing
+        vbm.extend_from_slice(&[0x01, 0, 0x11, 0, 0, 0]);
+
+        let movie = Movie::parse(&vbm, &rom(), Model::Dmg).unwrap();
+        assert_eq!(movie.rom_hash, hash(&rom()));
+        assert_eq!(movie.frames(), 3);
+        assert_eq!(
+            movie.events,
+            vec![
+                change(0, Button::A, true),
+                change(1, Button::Right, true),
+                change(2, Button::A, false),
+                change(2, Button::Right, false),
+            ]
+        );
+
+        vbm[0x31] = 0;
+        assert!(Movie::import_vbm(&vbm, &rom(), Model::Dmg).is_err());
+    }
+
+    #[test]
+    fn import_bk2_log() {
+        let log = "[Input]\n\
+                   LogKey:#Up|Down|Left|Right|Start|Select|B|A|Power|\n\
+                   |.........|\n\
+                   |U......A.|\n\
+                   |.......A.|\n\
+                   [/Input]\n";
+
+        let movie = Movie::parse(log.as_bytes(), &rom(), Model::Dmg).unwrap();
+        assert_eq!(movie.frames(), 3);
+        assert_eq!(
+            movie.events,
+            vec![
+                change(1, Button::A, true),
+                change(1, Button::Up, true),
+                change(2, Button::Up, false),
+            ]
+        );
+
+        assert_eq!(bk2_key("P2 Select"), Some((1, Button::Select)));
+        assert_eq!(bk2_key("Power"), None);
+    }
+}
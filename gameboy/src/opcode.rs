@@ -1,12 +1,11 @@
 use crate::cpu::*;
 use crate::instruction::Instruction;
-use crate::memory::Memory;
 
-pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
+pub fn read_opcode(opcode: u8, imm8: u8, imm16: u16) -> Instruction {
     match opcode {
         0x00 => Instruction::Noop,
         0x01 => Instruction::LDI16 {
-            val: mem.get16(argstart),
+            val: imm16,
             reg: Cpu16Register::BC,
         },
         0x02 => Instruction::STA8 {
@@ -23,13 +22,11 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::B,
         },
         0x06 => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::B,
         },
         0x07 => Instruction::RLCA,
-        0x08 => Instruction::LDSPA {
-            addr: mem.get16(argstart),
-        },
+        0x08 => Instruction::LDSPA { addr: imm16 },
         0x09 => Instruction::ADD16 {
             src: Cpu16Register::BC,
         },
@@ -47,13 +44,13 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::C,
         },
         0x0E => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::C,
         },
         0x0F => Instruction::RRCA,
         0x10 => Instruction::HALT, // STOP
         0x11 => Instruction::LDI16 {
-            val: mem.get16(argstart),
+            val: imm16,
             reg: Cpu16Register::DE,
         },
         0x12 => Instruction::STA8 {
@@ -70,13 +67,11 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::D,
         },
         0x16 => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::D,
         },
         0x17 => Instruction::RLA,
-        0x18 => Instruction::JR {
-            offset: mem.get(argstart) as i8,
-        },
+        0x18 => Instruction::JR { offset: imm8 as i8 },
         0x19 => Instruction::ADD16 {
             src: Cpu16Register::DE,
         },
@@ -94,15 +89,13 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::E,
         },
         0x1E => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::E,
         },
         0x1F => Instruction::RRA,
-        0x20 => Instruction::JRNZ {
-            offset: mem.get(argstart) as i8,
-        },
+        0x20 => Instruction::JRNZ { offset: imm8 as i8 },
         0x21 => Instruction::LDI16 {
-            val: mem.get16(argstart),
+            val: imm16,
             reg: Cpu16Register::HL,
         },
         0x22 => Instruction::STI,
@@ -116,13 +109,11 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::H,
         },
         0x26 => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::H,
         },
         0x27 => Instruction::DAA,
-        0x28 => Instruction::JRZ {
-            offset: mem.get(argstart) as i8,
-        },
+        0x28 => Instruction::JRZ { offset: imm8 as i8 },
         0x29 => Instruction::ADD16 {
             src: Cpu16Register::HL,
         },
@@ -137,15 +128,13 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::L,
         },
         0x2E => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::L,
         },
         0x2F => Instruction::CPL,
-        0x30 => Instruction::JRNC {
-            offset: mem.get(argstart) as i8,
-        },
+        0x30 => Instruction::JRNC { offset: imm8 as i8 },
         0x31 => Instruction::LDI16 {
-            val: mem.get16(argstart),
+            val: imm16,
             reg: Cpu16Register::SP,
         },
         0x32 => Instruction::STD,
@@ -156,12 +145,10 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
         0x35 => Instruction::DECA,
         0x36 => Instruction::STI8 {
             dst_addr: Cpu16Register::HL,
-            val: mem.get(argstart),
+            val: imm8,
         },
         0x37 => Instruction::SCF,
-        0x38 => Instruction::JRC {
-            offset: mem.get(argstart) as i8,
-        },
+        0x38 => Instruction::JRC { offset: imm8 as i8 },
         0x39 => Instruction::ADD16 {
             src: Cpu16Register::SP,
         },
@@ -176,7 +163,7 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
             reg: CpuRegister::A,
         },
         0x3E => Instruction::LDI8 {
-            val: mem.get(argstart),
+            val: imm8,
             reg: CpuRegister::A,
         },
         0x3F => Instruction::CCF,
@@ -617,73 +604,43 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
         0xC1 => Instruction::POP {
             reg: Cpu16Register::BC,
         },
-        0xC2 => Instruction::JPNZ {
-            addr: mem.get16(argstart),
-        },
-        0xC3 => Instruction::JP {
-            addr: mem.get16(argstart),
-        },
-        0xC4 => Instruction::CALLNZ {
-            addr: mem.get16(argstart),
-        },
+        0xC2 => Instruction::JPNZ { addr: imm16 },
+        0xC3 => Instruction::JP { addr: imm16 },
+        0xC4 => Instruction::CALLNZ { addr: imm16 },
         0xC5 => Instruction::PUSH {
             reg: Cpu16Register::BC,
         },
-        0xC6 => Instruction::ADDI {
-            val: mem.get(argstart),
-        },
+        0xC6 => Instruction::ADDI { val: imm8 },
         0xC7 => Instruction::RST { addr: 0x0000 },
         0xC8 => Instruction::RETZ,
         0xC9 => Instruction::RET,
-        0xCA => Instruction::JPZ {
-            addr: mem.get16(argstart),
-        },
+        0xCA => Instruction::JPZ { addr: imm16 },
         // 0xCB extension instructions
-        0xCC => Instruction::CALLZ {
-            addr: mem.get16(argstart),
-        },
-        0xCD => Instruction::CALL {
-            addr: mem.get16(argstart),
-        },
-        0xCE => Instruction::ADCI {
-            val: mem.get(argstart),
-        },
+        0xCC => Instruction::CALLZ { addr: imm16 },
+        0xCD => Instruction::CALL { addr: imm16 },
+        0xCE => Instruction::ADCI { val: imm8 },
         0xCF => Instruction::RST { addr: 0x0008 },
         0xD0 => Instruction::RETNC,
         0xD1 => Instruction::POP {
             reg: Cpu16Register::DE,
         },
-        0xD2 => Instruction::JPNC {
-            addr: mem.get16(argstart),
-        },
+        0xD2 => Instruction::JPNC { addr: imm16 },
         0xD3 => Instruction::ILLEGAL,
-        0xD4 => Instruction::CALLNC {
-            addr: mem.get16(argstart),
-        },
+        0xD4 => Instruction::CALLNC { addr: imm16 },
         0xD5 => Instruction::PUSH {
             reg: Cpu16Register::DE,
         },
-        0xD6 => Instruction::SUBI {
-            val: mem.get(argstart),
-        },
+        0xD6 => Instruction::SUBI { val: imm8 },
         0xD7 => Instruction::RST { addr: 0x0010 },
         0xD8 => Instruction::RETC,
         0xD9 => Instruction::RETI,
-        0xDA => Instruction::JPC {
-            addr: mem.get16(argstart),
-        },
+        0xDA => Instruction::JPC { addr: imm16 },
         0xDB => Instruction::ILLEGAL,
-        0xDC => Instruction::CALLC {
-            addr: mem.get16(argstart),
-        },
+        0xDC => Instruction::CALLC { addr: imm16 },
         0xDD => Instruction::ILLEGAL,
-        0xDE => Instruction::SBCI {
-            val: mem.get(argstart),
-        },
+        0xDE => Instruction::SBCI { val: imm8 },
         0xDF => Instruction::RST { addr: 0x0018 },
-        0xE0 => Instruction::STHA {
-            addr: mem.get(argstart),
-        },
+        0xE0 => Instruction::STHA { addr: imm8 },
         0xE1 => Instruction::POP {
             reg: Cpu16Register::HL,
         },
@@ -693,27 +650,17 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
         0xE5 => Instruction::PUSH {
             reg: Cpu16Register::HL,
         },
-        0xE6 => Instruction::ANDI {
-            val: mem.get(argstart),
-        },
+        0xE6 => Instruction::ANDI { val: imm8 },
         0xE7 => Instruction::RST { addr: 0x0020 },
-        0xE8 => Instruction::ADDSP {
-            val: mem.get(argstart) as i8,
-        },
+        0xE8 => Instruction::ADDSP { val: imm8 as i8 },
         0xE9 => Instruction::JPA,
-        0xEA => Instruction::STAA {
-            addr: mem.get16(argstart),
-        },
+        0xEA => Instruction::STAA { addr: imm16 },
         0xEB => Instruction::ILLEGAL,
         0xEC => Instruction::ILLEGAL,
         0xED => Instruction::ILLEGAL,
-        0xEE => Instruction::XORI {
-            val: mem.get(argstart),
-        },
+        0xEE => Instruction::XORI { val: imm8 },
         0xEF => Instruction::RST { addr: 0x0028 },
-        0xF0 => Instruction::LDHA {
-            addr: mem.get(argstart),
-        },
+        0xF0 => Instruction::LDHA { addr: imm8 },
         0xF1 => Instruction::POP {
             reg: Cpu16Register::AF,
         },
@@ -723,29 +670,21 @@ pub fn read_opcode(opcode: u8, argstart: u16, mem: &Memory) -> Instruction {
         0xF5 => Instruction::PUSH {
             reg: Cpu16Register::AF,
         },
-        0xF6 => Instruction::ORI {
-            val: mem.get(argstart),
-        },
+        0xF6 => Instruction::ORI { val: imm8 },
         0xF7 => Instruction::RST { addr: 0x0030 },
-        0xF8 => Instruction::LDHLI {
-            offset: mem.get(argstart) as i8,
-        },
+        0xF8 => Instruction::LDHLI { offset: imm8 as i8 },
         0xF9 => Instruction::LDSPHL,
-        0xFA => Instruction::LDAA {
-            addr: mem.get16(argstart),
-        },
+        0xFA => Instruction::LDAA { addr: imm16 },
         0xFB => Instruction::EI,
         0xFC => Instruction::ILLEGAL,
         0xFD => Instruction::ILLEGAL,
-        0xFE => Instruction::CMPI {
-            val: mem.get(argstart),
-        },
+        0xFE => Instruction::CMPI { val: imm8 },
         0xFF => Instruction::RST { addr: 0x0038 },
         _ => panic!("Unknown opcode {:2X}", opcode),
     }
 }
 
-pub fn read_extended_opcode(opcode: u8, _argstart: u16, _mem: &Memory) -> Instruction {
+pub fn read_extended_opcode(opcode: u8) -> Instruction {
     match opcode {
         0x00 => Instruction::RLC {
             reg: CpuRegister::B,
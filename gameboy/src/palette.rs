@@ -0,0 +1,409 @@
+//! The colours the DMG's four shades are drawn in. Each of the background
+//! (& window), OBJ0 & OBJ1 palettes can have its own, as the Game Boy Color
+//! does when running DMG games.
+
+use std::fmt;
+use std::str::FromStr;
+
+const OLD_LICENSEE_OFFSET: usize = 0x14B;
+const NEW_LICENSEE_OFFSET: usize = 0x144;
+const ROM_TITLE_START: usize = 0x134;
+const ROM_TITLE_END: usize = 0x144;
+
+/// RGB of each shade, lightest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    /// Plain greys
+    pub const GREYS: Palette = Palette([
+        [0xFF, 0xFF, 0xFF],
+        [0xC0, 0xC0, 0xC0],
+        [0x60, 0x60, 0x60],
+        [0x00, 0x00, 0x00],
+    ]);
+    /// The original DMG's green LCD
+    pub const DMG: Palette = Palette([
+        [0x9B, 0xBC, 0x0F],
+        [0x8B, 0xAC, 0x0F],
+        [0x30, 0x62, 0x30],
+        [0x0F, 0x38, 0x0F],
+    ]);
+    /// The Game Boy Pocket's greyer LCD
+    pub const POCKET: Palette = Palette([
+        [0xC4, 0xCF, 0xA1],
+        [0x8B, 0x95, 0x6D],
+        [0x4D, 0x53, 0x3C],
+        [0x1F, 0x1F, 0x1F],
+    ]);
+    /// The Game Boy Light's backlit LCD
+    pub const LIGHT: Palette = Palette([
+        [0x00, 0xB5, 0x81],
+        [0x00, 0x9A, 0x71],
+        [0x00, 0x69, 0x4A],
+        [0x00, 0x4F, 0x3B],
+    ]);
+
+    pub fn colour(&self, shade: u8) -> [u8; 3] {
+        self.0[shade as usize]
+    }
+
+    fn preset(name: &str) -> Option<Palette> {
+        match name {
+            "greys" => Some(Palette::GREYS),
+            "dmg" => Some(Palette::DMG),
+            "pocket" => Some(Palette::POCKET),
+            "light" => Some(Palette::LIGHT),
+            _ => None,
+        }
+    }
+}
+
+/// A preset name, or four RGB hex colours separated by commas, lightest
+/// first: `e0f8d0,88c070,346856,081820`
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        let s = s.trim().to_ascii_lowercase();
+        if let Some(palette) = Palette::preset(&s) {
+            return Ok(palette);
+        }
+
+        let colours: Vec<&str> = s.split(',').map(str::trim).collect();
+        if colours.len() != 4 {
+            return Err(format!("Palette {} isn't a preset or four hex colours", s));
+        }
+
+        let mut palette = [[0; 3]; 4];
+        for (colour, hex) in palette.iter_mut().zip(colours) {
+            let hex = hex.trim_start_matches('#');
+            let rgb = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or_else(|| format!("Invalid colour {}", hex))?;
+            *colour = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+        }
+
+        Ok(Palette(palette))
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let colours: Vec<String> = self
+            .0
+            .iter()
+            .map(|[r, g, b]| format!("{:02x}{:02x}{:02x}", r, g, b))
+            .collect();
+        write!(f, "{}", colours.join(","))
+    }
+}
+
+/// Palette number of each layer, as kept for every pixel on screen
+pub const BG_PALETTE: u8 = 0;
+pub const OBJ0_PALETTE: u8 = 1;
+pub const OBJ1_PALETTE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palettes {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette,
+}
+
+impl Palettes {
+    /// What the Game Boy Color uses for DMG games it doesn't recognise
+    pub const CGB_DEFAULT: Palettes = Palettes {
+        bg: Palette([
+            [0xFF, 0xFF, 0xFF],
+            [0x7B, 0xFF, 0x31],
+            [0x00, 0x63, 0xC5],
+            [0x00, 0x00, 0x00],
+        ]),
+        obj0: Palette([
+            [0xFF, 0xFF, 0xFF],
+            [0xFF, 0x84, 0x84],
+            [0x94, 0x3A, 0x3A],
+            [0x00, 0x00, 0x00],
+        ]),
+        obj1: Palette([
+            [0xFF, 0xFF, 0xFF],
+            [0xFF, 0x84, 0x84],
+            [0x94, 0x3A, 0x3A],
+            [0x00, 0x00, 0x00],
+        ]),
+    };
+
+    /// The same palette for every layer
+    pub const fn uniform(palette: Palette) -> Palettes {
+        Palettes {
+            bg: palette,
+            obj0: palette,
+            obj1: palette,
+        }
+    }
+
+    /// Colours as the Game Boy Color's boot ROM picks them, from the title
+    /// checksum of Nintendo's own games. Anything else gets `CGB_DEFAULT`.
+    pub fn for_rom(rom: &[u8]) -> Palettes {
+        title_checksum(rom)
+            .and_then(|checksum| {
+                TITLE_PALETTES
+                    .iter()
+                    .find(|(sum, fourth, _)| {
+                        *sum == checksum && fourth.is_none_or(|c| c == rom[ROM_TITLE_START + 3])
+                    })
+                    .map(|(_, _, palettes)| *palettes)
+            })
+            .unwrap_or(Palettes::CGB_DEFAULT)
+    }
+
+    /// 0 for the background & window, 1 for OBJ0 & 2 for OBJ1
+    pub fn colour(&self, palette: u8, shade: u8) -> [u8; 3] {
+        match palette {
+            BG_PALETTE => self.bg.colour(shade),
+            OBJ0_PALETTE => self.obj0.colour(shade),
+            OBJ1_PALETTE => self.obj1.colour(shade),
+            _ => panic!("Invalid palette {}", palette),
+        }
+    }
+}
+
+impl Default for Palettes {
+    fn default() -> Self {
+        Palettes::uniform(Palette::GREYS)
+    }
+}
+
+/// One palette for everything, or the background, OBJ0 & OBJ1 palettes
+/// separated by `/`. `cgb` is `CGB_DEFAULT`.
+impl FromStr for Palettes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palettes, String> {
+        if s.trim().eq_ignore_ascii_case("cgb") {
+            return Ok(Palettes::CGB_DEFAULT);
+        }
+
+        let palettes = s
+            .split('/')
+            .map(str::parse)
+            .collect::<Result<Vec<Palette>, String>>()?;
+
+        match palettes[..] {
+            [palette] => Ok(Palettes::uniform(palette)),
+            [bg, obj0, obj1] => Ok(Palettes { bg, obj0, obj1 }),
+            _ => Err(format!(
+                "Expected one or three palettes, got {}",
+                palettes.len()
+            )),
+        }
+    }
+}
+
+// Palettes from the Game Boy Color boot ROM's table, white first apart from
+// the inverted one
+const RED: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0xFF, 0x84, 0x84],
+    [0x94, 0x3A, 0x3A],
+    [0x00, 0x00, 0x00],
+]);
+const GREEN: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0x7B, 0xFF, 0x31],
+    [0x00, 0x84, 0x00],
+    [0x00, 0x00, 0x00],
+]);
+const BLUE: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0x63, 0xA5, 0xFF],
+    [0x00, 0x00, 0xFF],
+    [0x00, 0x00, 0x00],
+]);
+const YELLOW: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x00],
+    [0xFF, 0x00, 0x00],
+    [0x00, 0x00, 0x00],
+]);
+const ORANGE: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0xFF, 0x73, 0x00],
+    [0x94, 0x42, 0x00],
+    [0x00, 0x00, 0x00],
+]);
+const DARK_GREEN: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0x00, 0xFF, 0x00],
+    [0x31, 0x84, 0x00],
+    [0x00, 0x4A, 0x00],
+]);
+const KHAKI: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0xAD, 0xAD, 0x84],
+    [0x42, 0x73, 0x7B],
+    [0x00, 0x00, 0x00],
+]);
+const SKY: Palette = Palette([
+    [0xFF, 0xFF, 0xFF],
+    [0x5A, 0xBD, 0xFF],
+    [0xFF, 0x00, 0x00],
+    [0x00, 0x00, 0xFF],
+]);
+const LILAC: Palette = Palette([
+    [0xA5, 0x9C, 0xFF],
+    [0xFF, 0xFF, 0x00],
+    [0x00, 0x63, 0x00],
+    [0x00, 0x00, 0x00],
+]);
+const INVERTED: Palette = Palette([
+    [0x00, 0x00, 0x00],
+    [0x00, 0x84, 0x84],
+    [0xFF, 0xDE, 0x00],
+    [0xFF, 0xFF, 0xFF],
+]);
+
+/// Palettes the Game Boy Color picks by title checksum, with the title's
+/// fourth letter to tell apart games with the same checksum. These are the
+/// boot ROM's entries for some of Nintendo's best known games, the rest get
+/// `CGB_DEFAULT`.
+const TITLE_PALETTES: &[(u8, Option<u8>, Palettes)] = &[
+    // ALLEY WAY
+    (0x88, None, Palettes::uniform(LILAC)),
+    // TETRIS
+    (0xDB, None, Palettes::uniform(YELLOW)),
+    // ZELDA
+    (
+        0x70,
+        None,
+        Palettes {
+            bg: RED,
+            obj0: DARK_GREEN,
+            obj1: BLUE,
+        },
+    ),
+    // POKEMON RED
+    (
+        0x14,
+        None,
+        Palettes {
+            bg: RED,
+            obj0: GREEN,
+            obj1: RED,
+        },
+    ),
+    // POKEMON GREEN
+    (
+        0xAA,
+        None,
+        Palettes {
+            bg: Palettes::CGB_DEFAULT.bg,
+            obj0: RED,
+            obj1: Palettes::CGB_DEFAULT.bg,
+        },
+    ),
+    // POKEMON BLUE
+    (
+        0x61,
+        Some(b'E'),
+        Palettes {
+            bg: BLUE,
+            obj0: RED,
+            obj1: BLUE,
+        },
+    ),
+    // SUPER MARIOLAND, METROID2 has the same checksum
+    (
+        0x46,
+        Some(b'E'),
+        Palettes {
+            bg: KHAKI,
+            obj0: ORANGE,
+            obj1: SKY,
+        },
+    ),
+    // SOLARSTRIKER
+    (0xA5, Some(b'A'), Palettes::uniform(INVERTED)),
+];
+
+/// The sum of the title's bytes, which the Game Boy Color only looks up for
+/// games published by Nintendo
+pub fn title_checksum(rom: &[u8]) -> Option<u8> {
+    if rom.len() <= OLD_LICENSEE_OFFSET {
+        return None;
+    }
+
+    let nintendo = match rom[OLD_LICENSEE_OFFSET] {
+        0x01 => true,
+        0x33 => &rom[NEW_LICENSEE_OFFSET..NEW_LICENSEE_OFFSET + 2] == b"01",
+        _ => false,
+    };
+    if !nintendo {
+        return None;
+    }
+
+    Some(
+        rom[ROM_TITLE_START..ROM_TITLE_END]
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_add(b)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("Pocket".parse(), Ok(Palette::POCKET));
+        let custom: Palette = "e0f8d0, 88c070,#346856,081820".parse().unwrap();
+        assert_eq!(custom.colour(1), [0x88, 0xC0, 0x70]);
+        assert_eq!(custom.to_string(), "e0f8d0,88c070,346856,081820");
+        assert!("e0f8d0,88c070,346856".parse::<Palette>().is_err());
+        assert!("e0f8d0,88c070,346856,08182g".parse::<Palette>().is_err());
+
+        assert_eq!("dmg".parse(), Ok(Palettes::uniform(Palette::DMG)));
+        let split: Palettes = "greys/dmg/light".parse().unwrap();
+        assert_eq!(split.colour(OBJ0_PALETTE, 0), Palette::DMG.colour(0));
+        assert_eq!(split.colour(OBJ1_PALETTE, 3), Palette::LIGHT.colour(3));
+        assert!("greys/dmg".parse::<Palettes>().is_err());
+    }
+
+    #[test]
+    fn checksum() {
+        let mut rom = vec![0; 0x150];
+        rom[ROM_TITLE_START..ROM_TITLE_START + 5].copy_from_slice(b"TETRA");
+        assert_eq!(title_checksum(&rom), None);
+        assert_eq!(Palettes::for_rom(&rom), Palettes::CGB_DEFAULT);
+
+        rom[OLD_LICENSEE_OFFSET] = 0x01;
+        assert_eq!(title_checksum(&rom), Some(0x80));
+
+        rom[OLD_LICENSEE_OFFSET] = 0x33;
+        rom[NEW_LICENSEE_OFFSET..NEW_LICENSEE_OFFSET + 2].copy_from_slice(b"01");
+        assert_eq!(title_checksum(&rom), Some(0x80));
+    }
+
+    #[test]
+    fn title_palettes() {
+        let rom = |title: &[u8]| {
+            let mut rom = vec![0; 0x150];
+            rom[OLD_LICENSEE_OFFSET] = 0x01;
+            rom[ROM_TITLE_START..ROM_TITLE_START + title.len()].copy_from_slice(title);
+            rom
+        };
+
+        let red = Palettes::for_rom(&rom(b"POKEMON RED"));
+        assert_eq!(red.bg.colour(1), [0xFF, 0x84, 0x84]);
+        assert_eq!(red.obj0.colour(1), [0x7B, 0xFF, 0x31]);
+
+        // Same checksum, told apart by the fourth letter
+        let mario = rom(b"SUPER MARIOLAND");
+        let metroid = rom(b"METROID2");
+        assert_eq!(title_checksum(&mario), title_checksum(&metroid));
+        assert_eq!(Palettes::for_rom(&mario).obj1.colour(1), [0x5A, 0xBD, 0xFF]);
+        assert_eq!(Palettes::for_rom(&metroid), Palettes::CGB_DEFAULT);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+/// Bounded history of machine snapshots for stepping back in time. Only the
+/// newest snapshot is kept in full, each older one is stored as the
+/// differences from the one after it, which are mostly runs of unchanged
+/// bytes.
+pub struct Rewind {
+    // Frame number & full state of the newest snapshot
+    latest: Option<(u64, Vec<u8>)>,
+    // Oldest first, each a delta against the next newer snapshot
+    history: VecDeque<(u64, Vec<u8>)>,
+    capacity: usize,
+    interval: u64,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` snapshots, taken every `interval` frames. Both
+    /// are at least 1.
+    pub fn new(capacity: usize, interval: u64) -> Rewind {
+        Rewind {
+            latest: None,
+            history: VecDeque::new(),
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    pub fn push(&mut self, frame: u64, snapshot: Vec<u8>) {
+        if let Some((latest_frame, latest)) = self.latest.take() {
+            self.history
+                .push_back((latest_frame, delta(&snapshot, &latest)));
+        }
+        self.latest = Some((frame, snapshot));
+
+        while self.len() > self.capacity {
+            self.history.pop_front();
+        }
+    }
+
+    /// Remove & return the newest snapshot with its frame number
+    pub fn pop(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (frame, snapshot) = self.latest.take()?;
+
+        if let Some((previous_frame, delta)) = self.history.pop_back() {
+            self.latest = Some((previous_frame, apply_delta(&snapshot, &delta)));
+        }
+
+        Some((frame, snapshot))
+    }
+
+    /// Frame number of the newest snapshot
+    pub fn latest_frame(&self) -> Option<u64> {
+        self.latest.as_ref().map(|(frame, _)| *frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+
+    /// Bytes of snapshot data held
+    pub fn memory_used(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |(_, s)| s.len());
+        latest + self.history.iter().map(|(_, d)| d.len()).sum::<usize>()
+    }
+}
+
+const DELTA_FULL: u8 = 0;
+const DELTA_XOR: u8 = 1;
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        val |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+/// Encode `target` as its differences from `base`: alternating runs of
+/// unchanged bytes & literal runs of XORed bytes. Stored in full if the
+/// sizes differ.
+pub fn delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    if base.len() != target.len() {
+        let mut out = vec![DELTA_FULL];
+        out.extend_from_slice(target);
+        return out;
+    }
+
+    let mut out = vec![DELTA_XOR];
+    let mut i = 0;
+
+    while i < target.len() {
+        let unchanged = base[i..]
+            .iter()
+            .zip(&target[i..])
+            .take_while(|(b, t)| b == t)
+            .count();
+        i += unchanged;
+
+        let changed = base[i..]
+            .iter()
+            .zip(&target[i..])
+            .take_while(|(b, t)| b != t)
+            .count();
+
+        write_varint(&mut out, unchanged);
+        write_varint(&mut out, changed);
+        out.extend(
+            base[i..i + changed]
+                .iter()
+                .zip(&target[i..i + changed])
+                .map(|(b, t)| b ^ t),
+        );
+        i += changed;
+    }
+
+    out
+}
+
+/// Rebuild the target of `delta` from its `base`
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    match delta[0] {
+        DELTA_FULL => delta[1..].to_vec(),
+        DELTA_XOR => {
+            let mut out = base.to_vec();
+            let mut pos = 1;
+            let mut i = 0;
+
+            while pos < delta.len() {
+                i += read_varint(delta, &mut pos);
+                let changed = read_varint(delta, &mut pos);
+
+                for (byte, xor) in out[i..i + changed]
+                    .iter_mut()
+                    .zip(&delta[pos..pos + changed])
+                {
+                    *byte ^= xor;
+                }
+                pos += changed;
+                i += changed;
+            }
+
+            out
+        }
+        kind => panic!("Unknown snapshot delta {}", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas() {
+        let base = vec![0; 1000];
+        let mut target = base.clone();
+        target[3] = 7;
+        target[500..700].iter_mut().for_each(|b| *b = 0xAA);
+
+        let encoded = delta(&base, &target);
+        assert!(encoded.len() < 220);
+        assert_eq!(apply_delta(&base, &encoded), target);
+
+        assert_eq!(apply_delta(&base, &delta(&base, &base)), base);
+        assert_eq!(apply_delta(&base, &delta(&base, &[1, 2])), vec![1, 2]);
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let snapshot = |n: u8| {
+            let mut snapshot = vec![0; 100];
+            snapshot[0] = n;
+            snapshot
+        };
+
+        let mut rewind = Rewind::new(3, 2);
+        for n in 0..5 {
+            rewind.push(u64::from(n) * 2, snapshot(n));
+        }
+
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.latest_frame(), Some(8));
+        assert!(rewind.memory_used() < 120);
+
+        assert_eq!(rewind.pop(), Some((8, snapshot(4))));
+        assert_eq!(rewind.pop(), Some((6, snapshot(3))));
+        assert_eq!(rewind.pop(), Some((4, snapshot(2))));
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+
+        let mut rewind = Rewind::new(0, 0);
+        assert_eq!(rewind.interval(), 1);
+        rewind.push(0, snapshot(0));
+        rewind.push(1, snapshot(1));
+        assert_eq!(rewind.len(), 1);
+    }
+}
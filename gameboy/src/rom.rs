@@ -1,5 +1,7 @@
 use std::str;
 
+use crate::state::{StateError, StateReader, StateWriter};
+
 const CARTRIDGE_DEFAULT_RAM_SIZE: usize = 8 * 1024;
 
 const CARTRIDGE_RAM_SIZE_01: usize = 2 * 1024;
@@ -15,6 +17,8 @@ pub struct Cartridge {
     pub mbc_type: MbcType,
     pub rom_size: u8,
     pub ram_size: u8,
+    pub header_checksum: u8,
+    pub sgb_support: bool,
     pub rom_contents: Vec<u8>,
 
     pub ram: Vec<u8>,
@@ -96,6 +100,9 @@ const ROM_TITLE_END: usize = ROM_TITLE_START + ROM_TITLE_LEN;
 const ROM_TYPE_OFFSET: usize = 0x147;
 const ROM_SIZE_OFFSET: usize = 0x148;
 const RAM_SIZE_OFFSET: usize = 0x149;
+const HEADER_CHECKSUM_OFFSET: usize = 0x14D;
+const SGB_FLAG_OFFSET: usize = 0x146;
+const OLD_LICENSEE_OFFSET: usize = 0x14B;
 
 impl Cartridge {
     pub fn load_rom(rom_contents: Vec<u8>) -> Cartridge {
@@ -104,6 +111,10 @@ impl Cartridge {
             mbc_type: MbcType::from_byte(rom_contents[ROM_TYPE_OFFSET as usize]),
             rom_size: rom_contents[ROM_SIZE_OFFSET as usize],
             ram_size: rom_contents[RAM_SIZE_OFFSET as usize],
+            header_checksum: rom_contents[HEADER_CHECKSUM_OFFSET],
+            // SGB functions also need the old licensee code to point at the new one
+            sgb_support: rom_contents[SGB_FLAG_OFFSET] == 0x03
+                && rom_contents[OLD_LICENSEE_OFFSET] == 0x33,
             rom_contents,
 
             ram: vec![0; CARTRIDGE_DEFAULT_RAM_SIZE],
@@ -132,6 +143,46 @@ impl Cartridge {
         rom
     }
 
+    /// Cartridge RAM & the banks selected
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        match self.mbc_type {
+            MbcType::None => {}
+            MbcType::Mbc1 { rom_bank, ram_bank } | MbcType::Mbc3 { rom_bank, ram_bank } => {
+                state.u8(rom_bank);
+                state.bool(ram_bank.is_some());
+                state.u8(ram_bank.unwrap_or(0));
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.ram)?;
+        match self.mbc_type {
+            MbcType::None => {}
+            MbcType::Mbc1 { ref mut rom_bank, ref mut ram_bank }
+            | MbcType::Mbc3 { ref mut rom_bank, ref mut ram_bank } => {
+                *rom_bank = state.u8()?;
+                if (*rom_bank as usize + 1) * BANK_SIZE > self.rom_contents.len() {
+                    return Err(StateError::Invalid("ROM bank"));
+                }
+                let enabled = state.bool()?;
+                let bank = state.u8()?;
+                *ram_bank = if enabled { Some(bank) } else { None };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bank currently mapped at 0x4000 - 0x7FFF
+    pub fn rom_bank(&self) -> u8 {
+        match self.mbc_type {
+            MbcType::None => 1,
+            MbcType::Mbc1 { rom_bank, .. } | MbcType::Mbc3 { rom_bank, .. } => rom_bank,
+        }
+    }
+
     pub fn mbc_write(&mut self, addr: u16, val: u8) {
         match self.mbc_type {
             MbcType::None => {
@@ -250,3 +301,31 @@ impl Cartridge {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_rom_bank() {
+        // MBC1 with 4 banks
+        let mut rom = vec![0; 4 * BANK_SIZE];
+        rom[ROM_TYPE_OFFSET] = 0x01;
+        rom[ROM_SIZE_OFFSET] = 0x01;
+        let mut cartridge = Cartridge::load_rom(rom);
+
+        let load = |cartridge: &mut Cartridge, bank| {
+            let mut state = StateWriter::new();
+            state.bytes(&vec![0; CARTRIDGE_DEFAULT_RAM_SIZE]);
+            state.u8(bank);
+            state.bool(false);
+            state.u8(0);
+            let state = state.into_bytes();
+            cartridge.load_state(&mut StateReader::new(&state))
+        };
+
+        assert_eq!(load(&mut cartridge, 3), Ok(()));
+        assert_eq!(cartridge.rom_bank(), 3);
+        assert_eq!(load(&mut cartridge, 4), Err(StateError::Invalid("ROM bank")));
+    }
+}
@@ -0,0 +1,602 @@
+use crate::gpu::{apply_palette, GB_HSIZE, GB_VSIZE};
+use crate::memory::Memory;
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub const SGB_HSIZE: usize = 256;
+pub const SGB_VSIZE: usize = 224;
+
+// Position of the Gameboy screen within the border
+const SCREEN_X: usize = (SGB_HSIZE - GB_HSIZE) / 2;
+const SCREEN_Y: usize = (SGB_VSIZE - GB_VSIZE) / 2;
+
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+
+// Attributes are set per 8x8 cell of the Gameboy screen
+const ATTR_WIDTH: usize = GB_HSIZE / 8;
+const ATTR_HEIGHT: usize = GB_VSIZE / 8;
+
+const BORDER_WIDTH: usize = SGB_HSIZE / 8;
+const BORDER_HEIGHT: usize = SGB_VSIZE / 8;
+const BORDER_TILE_SIZE: usize = 32;
+
+const VRAM_TRANSFER_SIZE: usize = 4096;
+const SYSTEM_PALETTES: usize = 512;
+
+// Power on palette, used until the game sends its own
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+pub const CMD_PAL01: u8 = 0x00;
+pub const CMD_PAL23: u8 = 0x01;
+pub const CMD_PAL03: u8 = 0x02;
+pub const CMD_PAL12: u8 = 0x03;
+pub const CMD_ATTR_BLK: u8 = 0x04;
+pub const CMD_ATTR_LIN: u8 = 0x05;
+pub const CMD_PAL_SET: u8 = 0x0A;
+pub const CMD_PAL_TRN: u8 = 0x0B;
+pub const CMD_MLT_REQ: u8 = 0x11;
+pub const CMD_CHR_TRN: u8 = 0x13;
+pub const CMD_PCT_TRN: u8 = 0x14;
+pub const CMD_MASK_EN: u8 = 0x17;
+
+pub fn command_id(command: &[u8]) -> u8 {
+    command[0] >> 3
+}
+
+/// Reassembles SGB command packets pulsed out over the P14 / P15 lines of
+/// the joypad register.
+#[derive(Debug, Default)]
+pub struct PacketReader {
+    packet: [u8; PACKET_SIZE],
+    bit: usize,
+    receiving: bool,
+    // Lines must be released (both high) between each pulse
+    released: bool,
+
+    command: Vec<u8>,
+    packets_left: u8,
+}
+
+impl PacketReader {
+    pub fn new() -> PacketReader {
+        PacketReader::default()
+    }
+
+    /// Feed a write to 0xFF00. Returns a command once all of its packets
+    /// have been received.
+    pub fn write(&mut self, val: u8) -> Option<Vec<u8>> {
+        match val & 0x30 {
+            // Reset pulse: both lines low starts a new packet
+            0x00 => {
+                self.packet = [0; PACKET_SIZE];
+                self.bit = 0;
+                self.receiving = true;
+                self.released = false;
+            }
+            0x30 => self.released = true,
+            lines => {
+                if !self.receiving || !self.released {
+                    return None;
+                }
+                self.released = false;
+
+                // P15 low is a 1 bit, P14 low a 0 bit
+                if self.bit == PACKET_BITS {
+                    // Stop bit
+                    self.receiving = false;
+                    return self.finish_packet();
+                }
+
+                if lines == 0x10 {
+                    self.packet[self.bit / 8] |= 1 << (self.bit % 8);
+                }
+                self.bit += 1;
+            }
+        }
+
+        None
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.packet);
+        state.u32(self.bit as u32);
+        state.bool(self.receiving);
+        state.bool(self.released);
+        state.bytes(&self.command);
+        state.u8(self.packets_left);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.packet)?;
+        self.bit = state.u32()? as usize;
+        self.receiving = state.bool()?;
+        self.released = state.bool()?;
+        self.command = state.bytes()?.to_vec();
+        self.packets_left = state.u8()?;
+
+        Ok(())
+    }
+
+    fn finish_packet(&mut self) -> Option<Vec<u8>> {
+        if self.packets_left == 0 {
+            // First packet of a command holds the packet count
+            let len = self.packet[0] & 0b111;
+            if len == 0 {
+                return None;
+            }
+
+            self.command.clear();
+            self.packets_left = len;
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        self.packets_left -= 1;
+
+        if self.packets_left == 0 {
+            Some(std::mem::take(&mut self.command))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    None,
+    Freeze,
+    Black,
+    Colour0,
+}
+
+/// Super Game Boy state: colourisation of the Gameboy screen and the border
+/// drawn around it.
+pub struct Sgb {
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    // Palette number of each 8x8 cell on the Gameboy screen
+    attributes: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+
+    // 256 SNES 4bpp tiles, loaded 128 at a time by CHR_TRN
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    // Border uses SNES palettes 4-7
+    border_palettes: [[u16; 16]; 4],
+
+    mask: Mask,
+    frozen_shades: Vec<u8>,
+
+    pub screen_rgba: Vec<u8>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Sgb {
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; SYSTEM_PALETTES],
+            attributes: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            border_tiles: vec![0; 256 * BORDER_TILE_SIZE],
+            border_map: vec![0; BORDER_WIDTH * BORDER_HEIGHT],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            frozen_shades: vec![0; GB_HSIZE * GB_VSIZE],
+            screen_rgba: vec![255; SGB_HSIZE * SGB_VSIZE * 4],
+        }
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb::default()
+    }
+
+    /// Everything but the screen, which `render` redraws
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let palettes = self.palettes.iter().chain(self.system_palettes.iter());
+        for colour in palettes.flatten() {
+            state.u16(*colour);
+        }
+        state.bytes(&self.attributes);
+        state.bytes(&self.border_tiles);
+        for entry in &self.border_map {
+            state.u16(*entry);
+        }
+        for colour in self.border_palettes.iter().flatten() {
+            state.u16(*colour);
+        }
+        state.u8(match self.mask {
+            Mask::None => 0,
+            Mask::Freeze => 1,
+            Mask::Black => 2,
+            Mask::Colour0 => 3,
+        });
+        state.bytes(&self.frozen_shades);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let palettes = self
+            .palettes
+            .iter_mut()
+            .chain(self.system_palettes.iter_mut());
+        for colour in palettes.flatten() {
+            *colour = state.u16()?;
+        }
+        state.bytes_into(&mut self.attributes)?;
+        state.bytes_into(&mut self.border_tiles)?;
+        for entry in self.border_map.iter_mut() {
+            *entry = state.u16()?;
+        }
+        for colour in self.border_palettes.iter_mut().flatten() {
+            *colour = state.u16()?;
+        }
+        self.mask = match state.u8()? {
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Colour0,
+            _ => Mask::None,
+        };
+        state.bytes_into(&mut self.frozen_shades)?;
+
+        Ok(())
+    }
+
+    /// Apply a command received over the joypad port. VRAM transfers take
+    /// their data from what is currently on screen.
+    pub fn command(&mut self, command: &[u8], mem: &Memory, shades: &[u8]) {
+        match command_id(command) {
+            CMD_PAL01 => self.set_palettes(command, 0, 1),
+            CMD_PAL23 => self.set_palettes(command, 2, 3),
+            CMD_PAL03 => self.set_palettes(command, 0, 3),
+            CMD_PAL12 => self.set_palettes(command, 1, 2),
+            CMD_ATTR_BLK => self.attr_blk(command),
+            CMD_ATTR_LIN => self.attr_lin(command),
+            CMD_PAL_SET => self.pal_set(command),
+            CMD_PAL_TRN => {
+                let data = vram_transfer(mem);
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (c, colour) in palette.iter_mut().enumerate() {
+                        *colour = read_u16(&data, i * 8 + c * 2);
+                    }
+                }
+            }
+            CMD_CHR_TRN => {
+                let data = vram_transfer(mem);
+                let start = if command[1] & 1 != 0 {
+                    VRAM_TRANSFER_SIZE
+                } else {
+                    0
+                };
+                self.border_tiles[start..start + VRAM_TRANSFER_SIZE].copy_from_slice(&data);
+            }
+            CMD_PCT_TRN => {
+                let data = vram_transfer(mem);
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = read_u16(&data, i * 2);
+                }
+                for (p, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (c, colour) in palette.iter_mut().enumerate() {
+                        *colour = read_u16(&data, 0x800 + p * 32 + c * 2);
+                    }
+                }
+            }
+            CMD_MASK_EN => {
+                self.mask = match command[1] & 0b11 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Colour0,
+                    _ => Mask::None,
+                };
+
+                if self.mask == Mask::Freeze {
+                    self.frozen_shades.copy_from_slice(shades);
+                }
+            }
+            id => {
+                println!("Unsupported SGB command 0x{:02X}", id);
+            }
+        }
+    }
+
+    fn set_palettes(&mut self, command: &[u8], first: usize, second: usize) {
+        // Colour 0 is shared between all palettes
+        let colour0 = read_u16(command, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = colour0;
+        }
+
+        for c in 1..4 {
+            self.palettes[first][c] = read_u16(command, 1 + c * 2);
+            self.palettes[second][c] = read_u16(command, 7 + c * 2);
+        }
+    }
+
+    fn attr_blk(&mut self, command: &[u8]) {
+        let sets = usize::from(command[1] & 0x1F);
+
+        for set in command[2..].chunks(6).take(sets) {
+            if set.len() < 6 {
+                break;
+            }
+
+            let control = set[0];
+            let palette_inside = set[1] & 0b11;
+            let palette_line = (set[1] >> 2) & 0b11;
+            let palette_outside = (set[1] >> 4) & 0b11;
+            let (x1, y1) = (usize::from(set[2] & 0x1F), usize::from(set[3] & 0x1F));
+            let (x2, y2) = (usize::from(set[4] & 0x1F), usize::from(set[5] & 0x1F));
+
+            let change_inside = control & 0b001 != 0;
+            let change_outside = control & 0b100 != 0;
+            let mut change_line = control & 0b010 != 0;
+            let mut palette_line = palette_line;
+
+            // With only one of inside / outside set the surrounding line
+            // takes on that palette too
+            if !change_line && change_inside != change_outside {
+                change_line = true;
+                palette_line = if change_inside {
+                    palette_inside
+                } else {
+                    palette_outside
+                };
+            }
+
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let inside_x = x > x1 && x < x2;
+                    let inside_y = y > y1 && y < y2;
+                    let on_x = x >= x1 && x <= x2;
+                    let on_y = y >= y1 && y <= y2;
+
+                    let cell = &mut self.attributes[y * ATTR_WIDTH + x];
+                    if inside_x && inside_y {
+                        if change_inside {
+                            *cell = palette_inside;
+                        }
+                    } else if on_x && on_y {
+                        if change_line {
+                            *cell = palette_line;
+                        }
+                    } else if change_outside {
+                        *cell = palette_outside;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, command: &[u8]) {
+        let sets = usize::from(command[1]);
+
+        for &set in command[2..].iter().take(sets) {
+            let line = usize::from(set & 0x1F);
+            let palette = (set >> 5) & 0b11;
+
+            if set & 0x80 != 0 {
+                // Horizontal line
+                if line < ATTR_HEIGHT {
+                    for x in 0..ATTR_WIDTH {
+                        self.attributes[line * ATTR_WIDTH + x] = palette;
+                    }
+                }
+            } else if line < ATTR_WIDTH {
+                for y in 0..ATTR_HEIGHT {
+                    self.attributes[y * ATTR_WIDTH + line] = palette;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, command: &[u8]) {
+        for p in 0..4 {
+            let index = usize::from(read_u16(command, 1 + p * 2) & 0x1FF);
+            self.palettes[p] = self.system_palettes[index];
+        }
+
+        // Cancel mask
+        if command[9] & 0x40 != 0 {
+            self.mask = Mask::None;
+        }
+    }
+
+    /// Render the Gameboy screen, coloured by the SGB palettes, inside the
+    /// border into `screen_rgba`
+    pub fn render(&mut self, shades: &[u8]) {
+        let shades = match self.mask {
+            Mask::Freeze => &self.frozen_shades[..],
+            _ => shades,
+        };
+
+        // Backdrop shows through transparent border pixels
+        let backdrop = self.palettes[0][0];
+        for y in 0..SGB_VSIZE {
+            for x in 0..SGB_HSIZE {
+                let colour = self.border_pixel(x, y).unwrap_or(backdrop);
+                set_pixel(&mut self.screen_rgba, (y * SGB_HSIZE + x) * 4, colour);
+            }
+        }
+
+        for y in 0..GB_VSIZE {
+            for x in 0..GB_HSIZE {
+                let colour = match self.mask {
+                    Mask::Black => 0x0000,
+                    Mask::Colour0 => self.palettes[0][0],
+                    Mask::None | Mask::Freeze => {
+                        let palette = self.attributes[(y / 8) * ATTR_WIDTH + x / 8];
+                        self.palettes[palette as usize][shades[y * GB_HSIZE + x] as usize]
+                    }
+                };
+
+                let start = ((y + SCREEN_Y) * SGB_HSIZE + x + SCREEN_X) * 4;
+                set_pixel(&mut self.screen_rgba, start, colour);
+            }
+        }
+
+        // Border is drawn over the screen
+        for y in SCREEN_Y..SCREEN_Y + GB_VSIZE {
+            for x in SCREEN_X..SCREEN_X + GB_HSIZE {
+                if let Some(colour) = self.border_pixel(x, y) {
+                    set_pixel(&mut self.screen_rgba, (y * SGB_HSIZE + x) * 4, colour);
+                }
+            }
+        }
+    }
+
+    // Colour of the border at (x, y), None where it is transparent
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = self.border_map[(y / 8) * BORDER_WIDTH + x / 8];
+
+        let tile = usize::from(entry & 0xFF);
+        let palette = usize::from((entry >> 10) & 0b111);
+        let tx = if entry & 0x4000 != 0 {
+            7 - x % 8
+        } else {
+            x % 8
+        };
+        let ty = if entry & 0x8000 != 0 {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+
+        // SNES 4bpp: bitplanes 0 & 1 interleaved by row, then planes 2 & 3
+        let start = tile * BORDER_TILE_SIZE + ty * 2;
+        let bit = 7 - tx;
+        let planes = [
+            self.border_tiles[start],
+            self.border_tiles[start + 1],
+            self.border_tiles[start + 16],
+            self.border_tiles[start + 17],
+        ];
+        let colour = planes
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, plane)| acc | (((plane >> bit) & 1) << i));
+
+        if colour == 0 || palette < 4 {
+            None
+        } else {
+            Some(self.border_palettes[palette - 4][usize::from(colour)])
+        }
+    }
+}
+
+/// Capture the 4KB the SGB reads from the screen during a VRAM transfer:
+/// the tile data of the first 256 tiles in the background map, as displayed
+/// through BGP.
+fn vram_transfer(mem: &Memory) -> Vec<u8> {
+    let lcdc = mem.get(0xFF40);
+    let bgp = mem.get(0xFF47);
+    let tilemap: u16 = if lcdc & 0b1000 != 0 { 0x9C00 } else { 0x9800 };
+    let unsigned_tiles = lcdc & 0b1_0000 != 0;
+
+    let mut data = Vec::with_capacity(VRAM_TRANSFER_SIZE);
+
+    for i in 0..256 {
+        let (row, col) = (i / ATTR_WIDTH as u16, i % ATTR_WIDTH as u16);
+        let tilenum = mem.get_unlocked(tilemap + row * 32 + col);
+
+        let tile_addr = if unsigned_tiles {
+            0x8000 + u16::from(tilenum) * 16
+        } else {
+            (0x9000 + i32::from(tilenum as i8) * 16) as u16
+        };
+
+        for row in 0..8 {
+            let low = mem.get_unlocked(tile_addr + row * 2);
+            let high = mem.get_unlocked(tile_addr + row * 2 + 1);
+
+            let (mut out_low, mut out_high) = (0, 0);
+            for bit in 0..8 {
+                let colour = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                let shade = apply_palette(colour, bgp);
+                out_low |= (shade & 1) << bit;
+                out_high |= ((shade >> 1) & 1) << bit;
+            }
+
+            data.push(out_low);
+            data.push(out_high);
+        }
+    }
+
+    data
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from(data[offset]) | (u16::from(data[offset + 1]) << 8)
+}
+
+// SNES colours are 15 bit BGR
+fn set_pixel(rgba: &mut [u8], start: usize, colour: u16) {
+    let expand = |c: u16| -> u8 {
+        let c = (c & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    };
+
+    rgba[start] = expand(colour);
+    rgba[start + 1] = expand(colour >> 5);
+    rgba[start + 2] = expand(colour >> 10);
+    rgba[start + 3] = 255;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(reader: &mut PacketReader, packet: &[u8; PACKET_SIZE]) -> Option<Vec<u8>> {
+        reader.write(0x00);
+        reader.write(0x30);
+        for bit in 0..PACKET_BITS {
+            let one = packet[bit / 8] & (1 << (bit % 8)) != 0;
+            reader.write(if one { 0x10 } else { 0x20 });
+            reader.write(0x30);
+        }
+        // Stop bit
+        let command = reader.write(0x20);
+        reader.write(0x30);
+        command
+    }
+
+    #[test]
+    fn packet_reader_single_packet() {
+        let mut reader = PacketReader::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = (CMD_MLT_REQ << 3) | 1;
+        packet[1] = 0x01;
+
+        let command = send(&mut reader, &packet).unwrap();
+
+        assert_eq!(command_id(&command), CMD_MLT_REQ);
+        assert_eq!(command[1], 0x01);
+    }
+
+    #[test]
+    fn packet_reader_multi_packet() {
+        let mut reader = PacketReader::new();
+        let mut first = [0; PACKET_SIZE];
+        first[0] = (CMD_ATTR_LIN << 3) | 2;
+        let second = [0xAA; PACKET_SIZE];
+
+        assert_eq!(send(&mut reader, &first), None);
+        let command = send(&mut reader, &second).unwrap();
+
+        assert_eq!(command.len(), 2 * PACKET_SIZE);
+        assert_eq!(command[PACKET_SIZE], 0xAA);
+    }
+
+    #[test]
+    fn attr_blk_inside_sets_line() {
+        let mut sgb = Sgb::new();
+        let mut command = vec![0; PACKET_SIZE];
+        command[0] = (CMD_ATTR_BLK << 3) | 1;
+        command[1] = 1;
+        // Change inside only, palette 2, from (1, 1) to (3, 3)
+        command[2..8].copy_from_slice(&[0b001, 0b10, 1, 1, 3, 3]);
+
+        sgb.attr_blk(&command);
+
+        assert_eq!(sgb.attributes[2 * ATTR_WIDTH + 2], 2);
+        assert_eq!(sgb.attributes[ATTR_WIDTH + 1], 2);
+        assert_eq!(sgb.attributes[0], 0);
+        assert_eq!(sgb.attributes[4 * ATTR_WIDTH + 4], 0);
+    }
+}
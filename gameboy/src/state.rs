@@ -0,0 +1,261 @@
+//! Machine state as bytes, for snapshots. Each part of the machine writes
+//! its fields in a fixed order with `save_state` and reads them back in the
+//! same order with `load_state`. Only emulated state is included, not
+//! settings like the tracer or debugger, nor the ROM itself.
+
+use std::error::Error;
+use std::fmt;
+
+/// Start of every `GameBoy::save_state`
+pub const MAGIC: &[u8; 4] = b"GBSS";
+/// Bumped whenever any part of the machine changes what it saves
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// Doesn't start with `MAGIC`
+    NotAState,
+    /// Saved by a version of the emulator with a different layout
+    Version(u16),
+    /// Ended before everything was read
+    Truncated,
+    /// A block isn't the size of what it's read into
+    SizeMismatch { expected: usize, found: usize },
+    /// Bytes left over once everything was read, e.g. from another model
+    TrailingData(usize),
+    /// A value no machine could have, naming what it was
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "Not a save state"),
+            StateError::Version(version) => write!(
+                f,
+                "Save state version {} isn't supported, expected {}",
+                version, VERSION
+            ),
+            StateError::Truncated => write!(f, "Ends early"),
+            StateError::SizeMismatch { expected, found } => {
+                write!(f, "Block of {} bytes, expected {}", found, expected)
+            }
+            StateError::TrailingData(len) => write!(f, "{} bytes left over at the end", len),
+            StateError::Invalid(what) => write!(f, "Invalid {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// For reading states inside formats with `String` errors, like movies
+impl From<StateError> for String {
+    fn from(e: StateError) -> String {
+        e.to_string()
+    }
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.data.push(val);
+    }
+
+    /// `MAGIC` & `VERSION`, for `StateReader::header` to check
+    pub fn header(&mut self) {
+        self.data.extend_from_slice(MAGIC);
+        self.u16(VERSION);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u8(val as u8);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Length prefixed
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+/// Reads back what `StateWriter` wrote, failing rather than panicking on
+/// truncated or corrupt data as states & movies come from files
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// Check for `MAGIC` & the current `VERSION`
+    pub fn header(&mut self) -> Result<(), StateError> {
+        if !self.data.starts_with(MAGIC) {
+            return Err(StateError::NotAState);
+        }
+        self.take(MAGIC.len())?;
+
+        match self.u16()? {
+            VERSION => Ok(()),
+            version => Err(StateError::Version(version)),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Into a buffer of a known size, e.g. RAM
+    pub fn bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::SizeMismatch {
+                expected: buffer.len(),
+                found: bytes.len(),
+            });
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Everything has been read
+    pub fn finish(&self) -> Result<(), StateError> {
+        match self.data.len() {
+            0 => Ok(()),
+            len => Err(StateError::TrailingData(len)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StateWriter::new();
+        writer.header();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u32(0x789A_BCDE);
+        writer.u64(u64::MAX);
+        writer.bytes(&[1, 2, 3]);
+        let state = writer.into_bytes();
+
+        let mut reader = StateReader::new(&state);
+        assert_eq!(reader.header(), Ok(()));
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.u64(), Ok(u64::MAX));
+        let mut buffer = [0; 3];
+        assert_eq!(reader.bytes_into(&mut buffer), Ok(()));
+        assert_eq!(buffer, [1, 2, 3]);
+        assert!(reader.is_empty());
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            StateReader::new(b"GBMV\x01\x00").header(),
+            Err(StateError::NotAState)
+        );
+        assert_eq!(
+            StateReader::new(b"GBSS\x63\x00").header(),
+            Err(StateError::Version(0x63))
+        );
+
+        let mut reader = StateReader::new(&[0x34, 0x12, 0x56]);
+        assert_eq!(reader.u32(), Err(StateError::Truncated));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.finish(), Err(StateError::TrailingData(1)));
+
+        let mut writer = StateWriter::new();
+        writer.bytes(&[1, 2]);
+        let state = writer.into_bytes();
+        assert_eq!(
+            StateReader::new(&state).bytes_into(&mut [0; 3]),
+            Err(StateError::SizeMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            StateReader::new(&state[..5]).bytes(),
+            Err(StateError::Truncated)
+        );
+    }
+}
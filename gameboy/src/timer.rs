@@ -1,36 +1,52 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
 #[derive(Debug)]
 pub struct Timer {
-    /* Seen cpu cycles modulo 16 "Remainder of a base clock seen" */
-    remainder_cycles: u8,
-    /* Seen baseclock values modulo 64 */
-    baseclock_count: u8,
+    /* Internal 16 bit counter incremented every cpu cycle, DIV is the upper 8 bits */
+    internal: u16,
 
-    divider: u8,
     counter: u8,
     modulo: u8,
 
     control: u8,
-    speed: u8,
-    enabled: bool,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            remainder_cycles: 0,
-            baseclock_count: 0,
-            divider: 0,
+            internal: 0,
             counter: 0,
             modulo: 0,
             control: 0,
-            speed: 0,
-            enabled: false,
         }
     }
 
+    pub fn with_divider(divider: u8) -> Timer {
+        Timer {
+            internal: u16::from(divider) << 8,
+            ..Timer::new()
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.internal);
+        state.u8(self.counter);
+        state.u8(self.modulo);
+        state.u8(self.control);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.internal = state.u16()?;
+        self.counter = state.u8()?;
+        self.modulo = state.u8()?;
+        self.control = state.u8()?;
+
+        Ok(())
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0xFF04 => self.divider,
+            0xFF04 => (self.internal >> 8) as u8,
             0xFF05 => self.counter,
             0xFF06 => self.modulo,
             0xFF07 => self.control,
@@ -38,10 +54,17 @@ impl Timer {
         }
     }
 
-    pub fn write(&mut self, addr: u16, val: u8) {
+    /// Returns true on timer interrupt, as resetting DIV can overflow the
+    /// counter
+    pub fn write(&mut self, addr: u16, val: u8) -> bool {
         match addr {
             0xFF04 => {
-                self.divider = 0;
+                // Resetting the counter can itself cause a falling edge
+                let before = self.counter_input();
+                self.internal = 0;
+                if before {
+                    return self.increment_counter();
+                }
             }
             0xFF05 => {
                 self.counter = val;
@@ -51,16 +74,47 @@ impl Timer {
             }
             0xFF07 => {
                 self.control = val;
-                self.speed = val & 0b11;
-                self.enabled = (val & 0b001) != 0;
-
-                if self.enabled {
-                    dbg!("Enabled timer");
-                    dbg!(self);
-                }
             }
             _ => panic!("read at unsupported timer address 0x{:x}", addr),
         }
+
+        false
+    }
+
+    fn enabled(&self) -> bool {
+        (self.control & 0b100) != 0
+    }
+
+    /*
+     * The counter increments on the falling edge of a bit of the internal
+     * counter, selected by the low two bits of control:
+     *   |----|----------------|-----|
+     *   | 0b | Inc. Frequency | Bit |
+     *   |----|----------------|-----|
+     *   | 00 | 4096 Hz        | 9   |
+     *   | 01 | 262144 Hz      | 3   |
+     *   | 10 | 65536 Hz       | 5   |
+     *   | 11 | 16384 Hz       | 7   |
+     *   |----|----------------|-----|
+     */
+    fn counter_input(&self) -> bool {
+        let bit = match self.control & 0b11 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+
+        self.enabled() && (self.internal & (1 << bit)) != 0
+    }
+
+    // Returns true on overflow
+    fn increment_counter(&mut self) -> bool {
+        let (counter, overflow) = self.counter.overflowing_add(1);
+
+        self.counter = if overflow { self.modulo } else { counter };
+
+        overflow
     }
 
     /*
@@ -68,57 +122,77 @@ impl Timer {
      */
     pub fn tick(&mut self, cycles: u8) -> bool {
         /*
-        * cycles: raw clock cycles, always increments of 4
-        * cycles / 4: "M-clock" - memory clock, the 'effective' Hz of the gameboy CPU (1MHz)
-        * cycles / 16: "base clock" - the rate of the 1x timer speed ()
-
-        * divider always increments at 16384Hz
-        * counter increments according to the value of `speed`:
-        *   |----|----------------|------------------|
-        *   | 0b | Inc. Frequency | Base Clock Ratio |
-        *   |----|----------------|------------------|
-        *   | 00 | 4096 Hz        | 64x slower       |
-        *   | 01 | 262144 Hz      | 1x               |
-        *   | 10 | 65536 Hz       | 4x slower        |
-        *   | 11 | 16384 Hz       | 16x slower       |
-        *   |----|----------------|------------------|
-        */
-
-        self.remainder_cycles += cycles;
-
-        if self.remainder_cycles > 16 {
-            self.baseclock_count += 1;
-            self.remainder_cycles -= 16;
-        }
+         * cycles: raw clock cycles, always increments of 4
+         * Stepping an M-cycle at a time means the result doesn't depend on
+         * how the elapsed cycles are split between calls.
+         */
+        let mut interrupt = false;
 
-        if self.baseclock_count % 16 == 0 {
-            self.divider = self.divider.wrapping_add(1);
-        }
+        for _ in 0..cycles / 4 {
+            let before = self.counter_input();
+            self.internal = self.internal.wrapping_add(4);
 
-        // TODO Turn speed into an enum?
-        let ratio = match self.speed {
-            0b00 => 64,
-            0b01 => 1,
-            0b10 => 4,
-            0b11 => 16,
-            _ => panic!("Unknown speed value {}", self.speed),
-        };
+            if before && !self.counter_input() {
+                interrupt |= self.increment_counter();
+            }
+        }
 
-        let mut interrupt = false;
-        if self.baseclock_count % ratio == 0 {
-            let old_count = self.counter;
-            self.counter = self.counter.wrapping_add(1);
+        interrupt
+    }
+}
 
-            if old_count > self.counter {
-                self.counter = self.modulo;
-                interrupt = true
+#[cfg(test)]
+mod tests {
+    use crate::timer::Timer;
+
+    #[test]
+    fn tick_granularity() {
+        let mut coarse = Timer::new();
+        let mut fine = Timer::new();
+        coarse.write(0xFF07, 0b101);
+        fine.write(0xFF07, 0b101);
+
+        for _ in 0..1000 {
+            coarse.tick(24);
+            for _ in 0..6 {
+                fine.tick(4);
             }
         }
 
-        if self.baseclock_count > 64 {
-            self.baseclock_count -= 64;
+        assert_eq!(coarse.read(0xFF04), fine.read(0xFF04));
+        assert_eq!(coarse.read(0xFF05), fine.read(0xFF05));
+    }
+
+    #[test]
+    fn counter_overflow() {
+        let mut timer = Timer::with_divider(0);
+        timer.write(0xFF06, 0xF0);
+        timer.write(0xFF05, 0xFF);
+        // 262144 Hz, every 16 cycles
+        timer.write(0xFF07, 0b101);
+
+        assert!(!timer.tick(12));
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(0xFF05), 0xF0);
+
+        // DIV increments every 256 cycles
+        for _ in 0..64 {
+            timer.tick(4);
         }
+        assert_eq!(timer.read(0xFF04), 1);
+    }
 
-        interrupt && self.enabled
+    #[test]
+    fn div_reset_overflow() {
+        let mut timer = Timer::new();
+        timer.write(0xFF06, 0x42);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0b101);
+
+        // Bit 3 of the internal counter is set, so clearing it is a falling
+        // edge that overflows the counter
+        assert!(!timer.tick(8));
+        assert!(timer.write(0xFF04, 0));
+        assert_eq!(timer.read(0xFF05), 0x42);
     }
 }
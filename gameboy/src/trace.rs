@@ -0,0 +1,103 @@
+use std::io::Write;
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// Gameboy Doctor's log format, e.g.
+    /// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+    Doctor,
+    /// Registers, flags & the cycle count, for reading rather than diffing
+    Verbose,
+}
+
+impl TraceFormat {
+    /// CPU state before running the instruction at pc
+    pub fn line(self, cpu: &Cpu, mem: &Memory, cycles: u64) -> String {
+        match self {
+            TraceFormat::Doctor => {
+                let pcmem: Vec<String> = (0..4)
+                    .map(|i| format!("{:02X}", mem.get(cpu.pc.wrapping_add(i))))
+                    .collect();
+
+                format!(
+                    "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+                    cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc, pcmem.join(",")
+                )
+            }
+            TraceFormat::Verbose => {
+                let z = if cpu.z_flag() { 'Z' } else { '-' };
+                let n = if cpu.n_flag() { 'N' } else { '-' };
+                let c = if cpu.c_flag() { 'C' } else { '-' };
+                let h = if cpu.h_flag() { 'H' } else { '-' };
+
+                format!(
+                    "A:{:02X} F:{}{}{}{} BC:{:02X}{:02X} DE:{:02x}{:02x} HL:{:02x}{:02x} SP:{:04x} PC:{:04x} (cy: {}) gpu: {}",
+                    cpu.a, z, n, h, c, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc, cycles, mem.get(0xFF40) & (1 << 7)
+                )
+            }
+        }
+    }
+}
+
+/// Logs each instruction executed to any `Write`, e.g. a file, stdout or a
+/// compressing encoder wrapped around either
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    start: u64,
+    end: u64,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Tracer {
+        Tracer {
+            out,
+            format,
+            start: 0,
+            end: u64::MAX,
+        }
+    }
+
+    /// Only log instructions starting at cycle counts in `start..end`
+    pub fn set_window(&mut self, start: u64, end: u64) {
+        self.start = start;
+        self.end = end;
+    }
+
+    /// Log the instruction about to run. Halted cycles aren't instructions so
+    /// aren't logged.
+    pub fn trace(&mut self, cpu: &Cpu, mem: &Memory, cycles: u64) -> std::io::Result<()> {
+        if cpu.halted || cycles < self.start || cycles >= self.end {
+            return Ok(());
+        }
+
+        writeln!(self.out, "{}", self.format.line(cpu, mem, cycles))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::rom::Cartridge;
+
+    #[test]
+    fn doctor_format() {
+        let mut rom = vec![0; 32 * 1024];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x13, 0x02]);
+
+        let cpu = Cpu::post_boot(Model::Dmg, 0x33);
+        let mem = Memory::new(Model::Dmg, Cartridge::load_rom(rom), None).unwrap();
+
+        assert_eq!(
+            TraceFormat::Doctor.line(&cpu, &mem, 0),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Runs a directory of test ROMs (Blargg's, Mooneye's, ...) headlessly and
+//! compares the results against an expectations file of known failures.
+
+extern crate gameboy;
+extern crate gameboy_tools;
+
+pub mod screenshot;
+pub mod sm83;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use gameboy::gameboy::GameBoy;
+use gameboy::model::Model;
+use gameboy_tools::headless::{Runner, Verdict};
+
+/// About 30 seconds of emulated time, enough for any single test ROM
+pub const DEFAULT_CYCLES: u64 = 30 * 4_194_304;
+
+/// What's expected of one ROM, from a line of the expectations file:
+/// `<path relative to the ROM directory> [fail] [model=<model>]
+/// [cycles=<n>] [hash=<hex>]`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expectation {
+    /// Known to fail, so failing isn't a regression
+    pub known_failure: bool,
+    pub model: Option<Model>,
+    pub cycles: Option<u64>,
+    /// For ROMs which only show their result on screen, pass when the screen
+    /// at the end of the run hashes to this
+    pub screen_hash: Option<u64>,
+}
+
+pub fn parse_expectations(text: &str) -> Result<HashMap<String, Expectation>, String> {
+    let mut expectations = HashMap::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace();
+
+        let mut rom = match parts.next() {
+            Some(rom) => rom.to_string(),
+            None => continue,
+        };
+        // Some of Blargg's ROMs have spaces in their names
+        while !rom.ends_with(".gb") && !rom.ends_with(".gbc") {
+            match parts.next() {
+                Some(part) => {
+                    rom.push(' ');
+                    rom.push_str(part);
+                }
+                None => return Err(format!("line {}: no .gb or .gbc file", number + 1)),
+            }
+        }
+
+        let mut expectation = Expectation::default();
+        for part in parts {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next().unwrap_or("");
+            let value = kv.next();
+            let invalid = || format!("line {}: invalid {}", number + 1, part);
+
+            match (key, value) {
+                ("fail", None) => expectation.known_failure = true,
+                ("model", Some(model)) => expectation.model = Some(model.parse()?),
+                ("cycles", Some(cycles)) => {
+                    expectation.cycles = Some(cycles.parse().map_err(|_| invalid())?)
+                }
+                ("hash", Some(hash)) => {
+                    expectation.screen_hash =
+                        Some(u64::from_str_radix(hash, 16).map_err(|_| invalid())?)
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        expectations.insert(rom, expectation);
+    }
+
+    Ok(expectations)
+}
+
+/// `$GB_TEST_ROMS`, or `roms/` next to this crate
+pub fn rom_dir() -> PathBuf {
+    env::var_os("GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/roms")))
+}
+
+/// First ROM under `dir` called `name`
+pub fn find_rom(dir: &Path, name: &str) -> Option<PathBuf> {
+    find_roms(dir)
+        .into_iter()
+        .find(|path| path.file_name().is_some_and(|f| f == name))
+}
+
+/// Every .gb & .gbc file under `dir`, sorted
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("gb") | Some("gbc")
+            ) {
+                roms.push(path);
+            }
+        }
+    }
+
+    roms.sort();
+    roms
+}
+
+/// FNV-1a, stable across platforms & releases unlike `DefaultHasher`
+pub fn screen_hash(rgba: &[u8]) -> u64 {
+    rgba.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// Ran out of cycles without reporting a result, with the hash of the
+    /// screen at the end
+    Timeout(u64),
+    /// Expected a screen hash, but finished on this one
+    WrongScreen(u64),
+    /// The emulator panicked
+    Crashed(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        *self == Outcome::Passed
+    }
+}
+
+pub fn run_rom(path: &Path, expectation: &Expectation) -> Outcome {
+    let rom = match fs::read(path) {
+        Ok(rom) => rom,
+        Err(e) => return Outcome::Crashed(e.to_string()),
+    };
+    let model = expectation.model.unwrap_or(Model::Dmg);
+    let cycles = expectation.cycles.unwrap_or(DEFAULT_CYCLES);
+    let screen_hash = expectation.screen_hash;
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut gb = GameBoy::new(rom, model);
+        gb.set_serial_echo(false);
+
+        let mut runner = Runner::new(gb, Vec::new());
+
+        match screen_hash {
+            // Nothing to say when it's done, so run for the full time
+            Some(expected) => {
+                runner.run(None, Some(cycles), false);
+                match screen_hash_of(&runner) {
+                    hash if hash == expected => Outcome::Passed,
+                    hash => Outcome::WrongScreen(hash),
+                }
+            }
+            None => match runner.run(None, Some(cycles), true) {
+                Verdict::Passed => Outcome::Passed,
+                Verdict::Failed => Outcome::Failed,
+                Verdict::Unknown => Outcome::Timeout(screen_hash_of(&runner)),
+            },
+        }
+    }));
+
+    result.unwrap_or_else(|e| {
+        let message = e
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        Outcome::Crashed(message)
+    })
+}
+
+fn screen_hash_of(runner: &Runner) -> u64 {
+    screen_hash(runner.screen().0)
+}
+
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub known_failure: bool,
+}
+
+impl TestResult {
+    /// Failed without being listed as a known failure
+    pub fn regressed(&self) -> bool {
+        !self.outcome.passed() && !self.known_failure
+    }
+
+    /// Listed as a known failure, but now passes
+    pub fn fixed(&self) -> bool {
+        self.outcome.passed() && self.known_failure
+    }
+}
+
+/// Run every ROM under `dir`, printing a line as each finishes
+pub fn run_suite(dir: &Path, expectations: &HashMap<String, Expectation>) -> Vec<TestResult> {
+    // Keep panics from ROMs the emulator can't handle out of the table
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut results = Vec::new();
+
+    for path in find_roms(dir) {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let expectation = expectations.get(&name).cloned().unwrap_or_default();
+
+        let outcome = run_rom(&path, &expectation);
+        let result = TestResult {
+            name,
+            outcome,
+            known_failure: expectation.known_failure,
+        };
+        print_result(&result);
+        results.push(result);
+    }
+
+    panic::set_hook(default_hook);
+
+    results
+}
+
+pub fn print_result(result: &TestResult) {
+    let outcome = match &result.outcome {
+        Outcome::Passed => "pass".to_string(),
+        Outcome::Failed => "FAIL".to_string(),
+        Outcome::Timeout(hash) => format!("TIMEOUT screen {:016x}", hash),
+        Outcome::WrongScreen(hash) => format!("FAIL screen {:016x}", hash),
+        Outcome::Crashed(message) => format!("CRASH {}", message),
+    };
+
+    let note = if result.regressed() {
+        "regression"
+    } else if result.fixed() {
+        "fixed, remove from expectations"
+    } else if result.known_failure {
+        "known failure"
+    } else {
+        ""
+    };
+
+    println!("{:<60} {:<30} {}", result.name, outcome, note);
+}
+
+/// Totals line, returns true if nothing regressed
+pub fn print_summary(results: &[TestResult]) -> bool {
+    let passed = results.iter().filter(|r| r.outcome.passed()).count();
+    let regressed = results.iter().filter(|r| r.regressed()).count();
+    let fixed = results.iter().filter(|r| r.fixed()).count();
+
+    println!(
+        "\n{} ROMs: {} passed, {} failed ({} known, {} regressions), {} newly passing",
+        results.len(),
+        passed,
+        results.len() - passed,
+        results.len() - passed - regressed,
+        regressed,
+        fixed
+    );
+
+    regressed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expectations() {
+        let expectations = parse_expectations(
+            "# Known failures\n\
+             blargg/cpu_instrs/02-interrupts.gb fail\n\
+             mooneye/acceptance/boot_regs-sgb.gb model=sgb cycles=1000\n\
+             \n\
+             dmg-acid2.gb hash=0123456789abcdef\n\
+             blargg/dmg_sound/rom_singles/02-len ctr.gb fail\n",
+        )
+        .unwrap();
+
+        assert_eq!(expectations.len(), 4);
+        assert!(expectations["blargg/dmg_sound/rom_singles/02-len ctr.gb"].known_failure);
+        assert!(expectations["blargg/cpu_instrs/02-interrupts.gb"].known_failure);
+
+        let boot_regs = &expectations["mooneye/acceptance/boot_regs-sgb.gb"];
+        assert_eq!(boot_regs.model, Some(Model::Sgb));
+        assert_eq!(boot_regs.cycles, Some(1000));
+        assert!(!boot_regs.known_failure);
+
+        assert_eq!(
+            expectations["dmg-acid2.gb"].screen_hash,
+            Some(0x0123_4567_89AB_CDEF)
+        );
+
+        assert!(parse_expectations("rom.gb sometimes").is_err());
+        assert!(parse_expectations("rom.gb cycles=lots").is_err());
+        assert!(parse_expectations("rom fail").is_err());
+
+        let checked_in = parse_expectations(include_str!("../expectations.txt")).unwrap();
+        assert!(checked_in["blargg/halt_bug.gb"].known_failure);
+    }
+
+    #[test]
+    fn regressions() {
+        let result = |outcome, known_failure| TestResult {
+            name: String::new(),
+            outcome,
+            known_failure,
+        };
+
+        assert!(result(Outcome::Failed, false).regressed());
+        assert!(result(Outcome::Timeout(0), false).regressed());
+        assert!(!result(Outcome::Failed, true).regressed());
+        assert!(result(Outcome::Passed, true).fixed());
+        assert!(!result(Outcome::Passed, false).fixed());
+    }
+}
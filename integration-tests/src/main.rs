@@ -0,0 +1,45 @@
+extern crate gameboy_tests;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use gameboy_tests::{parse_expectations, print_summary, run_suite};
+
+use clap::{App, Arg};
+
+fn main() -> Result<(), std::io::Error> {
+    let matches = App::new("gameboy-tests")
+        .version("1.0")
+        .about("Run a directory of test ROMs and report which pass")
+        .arg(
+            Arg::with_name("expectations")
+                .long("expectations")
+                .takes_value(true)
+                .default_value(concat!(env!("CARGO_MANIFEST_DIR"), "/expectations.txt"))
+                .help("Known failures & per-ROM settings"),
+        )
+        .arg(
+            Arg::with_name("DIR")
+                .help("Directory of test ROMs")
+                .default_value(concat!(env!("CARGO_MANIFEST_DIR"), "/roms"))
+                .index(1),
+        )
+        .get_matches();
+
+    let expectations = parse_expectations(&fs::read_to_string(
+        matches.value_of("expectations").unwrap(),
+    )?)
+    .unwrap_or_else(|e| {
+        eprintln!("Invalid expectations: {}", e);
+        process::exit(2);
+    });
+
+    let results = run_suite(Path::new(matches.value_of("DIR").unwrap()), &expectations);
+
+    if !print_summary(&results) {
+        process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Screenshot regression tests: run a ROM to a given frame and compare the
+//! screen against a golden PNG. Set `UPDATE_GOLDEN=1` to write the goldens
+//! from the current output instead.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gameboy::gameboy::GameBoy;
+use gameboy::model::Model;
+use gameboy_tools::headless::{read_png, write_png, InputEvent, Runner};
+
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+pub struct Screenshot {
+    pub rom: PathBuf,
+    pub model: Model,
+    /// Frame to take the screenshot on
+    pub frame: u64,
+    pub inputs: Vec<InputEvent>,
+}
+
+impl Screenshot {
+    pub fn new(rom: &Path, frame: u64) -> Screenshot {
+        Screenshot {
+            rom: rom.to_path_buf(),
+            model: Model::Dmg,
+            frame,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn model(mut self, model: Model) -> Screenshot {
+        self.model = model;
+        self
+    }
+
+    pub fn inputs(mut self, inputs: Vec<InputEvent>) -> Screenshot {
+        self.inputs = inputs;
+        self
+    }
+
+    /// RGBA screen on `frame`, with its width & height
+    pub fn take(&self) -> Result<(Vec<u8>, u32, u32), String> {
+        let rom = fs::read(&self.rom).map_err(|e| format!("{}: {}", self.rom.display(), e))?;
+
+        let mut gb = GameBoy::new(rom, self.model);
+        gb.set_serial_echo(false);
+
+        let mut runner = Runner::new(gb, self.inputs.clone());
+        runner.run(Some(self.frame), None, false);
+
+        let (rgba, width, height) = runner.screen();
+        Ok((rgba.to_vec(), width, height))
+    }
+
+    /// Compare against `golden`. On a mismatch the actual screen is written
+    /// alongside it as `<name>.actual.png`, with the differences highlighted
+    /// in `<name>.diff.png`.
+    pub fn check(&self, golden: &Path) -> Result<(), String> {
+        let (actual, width, height) = self.take()?;
+
+        if env::var_os(UPDATE_ENV).is_some() {
+            return write_png(golden, &actual, width, height)
+                .map_err(|e| format!("{}: {}", golden.display(), e));
+        }
+
+        let (expected, golden_width, golden_height) = read_png(golden).map_err(|e| {
+            format!(
+                "{}: {} (run with {}=1 to create it)",
+                golden.display(),
+                e,
+                UPDATE_ENV
+            )
+        })?;
+
+        if (golden_width, golden_height) != (width, height) {
+            return Err(format!(
+                "{}: golden is {}x{}, screen is {}x{}",
+                golden.display(),
+                golden_width,
+                golden_height,
+                width,
+                height
+            ));
+        }
+
+        let (diff, differing) = diff_image(&expected, &actual);
+        if differing == 0 {
+            return Ok(());
+        }
+
+        let actual_path = golden.with_extension("actual.png");
+        let diff_path = golden.with_extension("diff.png");
+        write_png(&actual_path, &actual, width, height).map_err(|e| e.to_string())?;
+        write_png(&diff_path, &diff, width, height).map_err(|e| e.to_string())?;
+
+        Err(format!(
+            "{}: {} pixels differ, see {} & {}",
+            golden.display(),
+            differing,
+            actual_path.display(),
+            diff_path.display()
+        ))
+    }
+}
+
+/// Differing pixels in red over a faded copy of the expected image, along
+/// with how many differ
+pub fn diff_image(expected: &[u8], actual: &[u8]) -> (Vec<u8>, usize) {
+    let mut differing = 0;
+
+    let diff = expected
+        .chunks(4)
+        .zip(actual.chunks(4))
+        .flat_map(|(e, a)| {
+            if e == a {
+                let faded = |c: u8| 192 + c / 4;
+                [faded(e[0]), faded(e[1]), faded(e[2]), 255]
+            } else {
+                differing += 1;
+                [255, 0, 0, 255]
+            }
+        })
+        .collect();
+
+    (diff, differing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let expected = [0, 0, 0, 255, 255, 255, 255, 255];
+        let actual = [0, 0, 0, 255, 96, 96, 96, 255];
+
+        let (diff, differing) = diff_image(&expected, &actual);
+
+        assert_eq!(differing, 1);
+        assert_eq!(diff, vec![192, 192, 192, 255, 255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,405 @@
+//! Single-step CPU tests in the community "sm83" JSON format: one file per
+//! opcode, each holding a list of tests with the CPU & RAM state before and
+//! after one instruction, and the bus activity of every M-cycle in between.
+//!
+//! Each instruction runs against a flat 64K test memory instead of the real
+//! memory map, so only the CPU is being tested.
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use gameboy::bus::Bus;
+use gameboy::gameboy::Cpu;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    pub pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    pub ime: bool,
+    pub ram: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read(u16),
+    Write(u16, u8),
+    Idle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Test {
+    pub name: String,
+    pub initial: State,
+    pub expected: State,
+    pub cycles: Vec<Access>,
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, String> {
+    value.get(name).ok_or_else(|| format!("missing {}", name))
+}
+
+fn number(value: &Value, what: &str) -> Result<u64, String> {
+    value
+        .as_u64()
+        .ok_or_else(|| format!("invalid {}: {}", what, value))
+}
+
+fn byte(value: &Value, name: &str) -> Result<u8, String> {
+    Ok(number(field(value, name)?, name)? as u8)
+}
+
+fn word(value: &Value, name: &str) -> Result<u16, String> {
+    Ok(number(field(value, name)?, name)? as u16)
+}
+
+fn parse_state(value: &Value) -> Result<State, String> {
+    let ram = field(value, "ram")?
+        .as_array()
+        .ok_or("invalid ram")?
+        .iter()
+        .map(|entry| match entry.as_array().map(Vec::as_slice) {
+            Some([addr, val]) => Ok((
+                number(addr, "ram address")? as u16,
+                number(val, "ram value")? as u8,
+            )),
+            _ => Err(format!("invalid ram entry: {}", entry)),
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(State {
+        pc: word(value, "pc")?,
+        sp: word(value, "sp")?,
+        a: byte(value, "a")?,
+        b: byte(value, "b")?,
+        c: byte(value, "c")?,
+        d: byte(value, "d")?,
+        e: byte(value, "e")?,
+        f: byte(value, "f")?,
+        h: byte(value, "h")?,
+        l: byte(value, "l")?,
+        ime: value.get("ime").and_then(Value::as_u64).unwrap_or(0) != 0,
+        ram,
+    })
+}
+
+/// `[addr, value, "rwm"]`, where the last part says whether the cycle read
+/// or wrote memory. Internal cycles are `null` or have neither flag set.
+fn parse_cycle(value: &Value) -> Result<Access, String> {
+    let cycle = match value.as_array() {
+        Some(cycle) => cycle,
+        None if value.is_null() => return Ok(Access::Idle),
+        None => return Err(format!("invalid cycle: {}", value)),
+    };
+
+    let kind = cycle.get(2).and_then(Value::as_str).unwrap_or("");
+    let addr = cycle.first().and_then(Value::as_u64);
+    let val = cycle.get(1).and_then(Value::as_u64);
+
+    match (addr, val) {
+        (Some(addr), Some(val)) if kind.contains('w') => Ok(Access::Write(addr as u16, val as u8)),
+        (Some(addr), _) if kind.contains('r') => Ok(Access::Read(addr as u16)),
+        _ => Ok(Access::Idle),
+    }
+}
+
+pub fn parse_tests(json: &str) -> Result<Vec<Test>, String> {
+    let tests: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+    tests
+        .as_array()
+        .ok_or("expected a list of tests")?
+        .iter()
+        .map(|test| {
+            let name = field(test, "name")?.as_str().unwrap_or("").to_string();
+            let in_test = |e: String| format!("{}: {}", name, e);
+
+            Ok(Test {
+                initial: parse_state(field(test, "initial")?).map_err(in_test)?,
+                expected: parse_state(field(test, "final")?).map_err(in_test)?,
+                cycles: field(test, "cycles")?
+                    .as_array()
+                    .ok_or_else(|| in_test("invalid cycles".to_string()))?
+                    .iter()
+                    .map(parse_cycle)
+                    .collect::<Result<_, String>>()
+                    .map_err(in_test)?,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// 64K of RAM with nothing mapped, logging every M-cycle
+pub struct FlatBus {
+    pub ram: Vec<u8>,
+    pub log: Vec<Access>,
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus {
+            ram: vec![0; 0x10000],
+            log: Vec::new(),
+        }
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        FlatBus::new()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.log.push(Access::Read(addr));
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.log.push(Access::Write(addr, val));
+        self.ram[addr as usize] = val;
+    }
+
+    fn tick(&mut self) {
+        self.log.push(Access::Idle);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+/// Run one test, returning a description of everything which didn't match
+pub fn run_test(test: &Test) -> Vec<String> {
+    let initial = &test.initial;
+    let mut cpu = Cpu {
+        pc: initial.pc,
+        sp: initial.sp,
+        a: initial.a,
+        b: initial.b,
+        c: initial.c,
+        d: initial.d,
+        e: initial.e,
+        f: initial.f,
+        h: initial.h,
+        l: initial.l,
+        interrupts: initial.ime,
+        jumped: false,
+        halted: false,
+    };
+
+    let mut bus = FlatBus::new();
+    for &(addr, val) in &initial.ram {
+        bus.ram[addr as usize] = val;
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| cpu.cycle(&mut bus, false)));
+    let cycles = match result {
+        Ok(cycles) => cycles,
+        Err(e) => {
+            let message = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default();
+            return vec![format!("panicked: {}", message)];
+        }
+    };
+
+    let expected = &test.expected;
+    let mut mismatches = Vec::new();
+
+    let registers = [
+        ("a", cpu.a, expected.a),
+        ("b", cpu.b, expected.b),
+        ("c", cpu.c, expected.c),
+        ("d", cpu.d, expected.d),
+        ("e", cpu.e, expected.e),
+        ("f", cpu.f, expected.f),
+        ("h", cpu.h, expected.h),
+        ("l", cpu.l, expected.l),
+    ];
+    for (name, actual, expected) in registers.iter() {
+        if actual != expected {
+            mismatches.push(format!(
+                "{} {:02x}, expected {:02x}",
+                name, actual, expected
+            ));
+        }
+    }
+
+    for (name, actual, expected) in
+        [("pc", cpu.pc, expected.pc), ("sp", cpu.sp, expected.sp)].iter()
+    {
+        if actual != expected {
+            mismatches.push(format!(
+                "{} {:04x}, expected {:04x}",
+                name, actual, expected
+            ));
+        }
+    }
+
+    if cpu.interrupts != expected.ime {
+        mismatches.push(format!("ime {}, expected {}", cpu.interrupts, expected.ime));
+    }
+
+    for &(addr, val) in &expected.ram {
+        let actual = bus.ram[addr as usize];
+        if actual != val {
+            mismatches.push(format!(
+                "[{:04x}] {:02x}, expected {:02x}",
+                addr, actual, val
+            ));
+        }
+    }
+
+    let expected_cycles = test.cycles.len();
+    if bus.log.len() != expected_cycles {
+        mismatches.push(format!(
+            "{} bus cycles, expected {}",
+            bus.log.len(),
+            expected_cycles
+        ));
+    }
+    if usize::from(cycles) != expected_cycles * 4 {
+        mismatches.push(format!(
+            "returned {} cycles, expected {}",
+            cycles,
+            expected_cycles * 4
+        ));
+    }
+
+    let writes = |log: &[Access]| -> Vec<Access> {
+        log.iter()
+            .filter(|a| matches!(a, Access::Write(..)))
+            .cloned()
+            .collect()
+    };
+    let (actual_writes, expected_writes) = (writes(&bus.log), writes(&test.cycles));
+    if actual_writes != expected_writes {
+        mismatches.push(format!(
+            "writes {:?}, expected {:?}",
+            actual_writes, expected_writes
+        ));
+    }
+
+    mismatches
+}
+
+pub struct FileResult {
+    pub name: String,
+    pub total: usize,
+    /// Name & mismatches of each failing test
+    pub failures: Vec<(String, Vec<String>)>,
+}
+
+impl FileResult {
+    pub fn passed(&self) -> usize {
+        self.total - self.failures.len()
+    }
+}
+
+pub fn run_file(path: &Path) -> Result<FileResult, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let tests = parse_tests(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let failures = tests
+        .iter()
+        .filter_map(|test| {
+            let mismatches = run_test(test);
+            if mismatches.is_empty() {
+                None
+            } else {
+                Some((test.name.clone(), mismatches))
+            }
+        })
+        .collect();
+
+    Ok(FileResult {
+        name: path
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        total: tests.len(),
+        failures,
+    })
+}
+
+/// Every .json file in `dir`, sorted
+pub fn find_test_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|e| e == "json"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(pc: u16, a: u8, ram: &str) -> String {
+        format!(
+            r#"{{"pc": {}, "sp": 65534, "a": {}, "b": 0, "c": 0, "d": 0, "e": 0,
+                "f": 0, "h": 192, "l": 0, "ime": 0, "ram": {}}}"#,
+            pc, a, ram
+        )
+    }
+
+    #[test]
+    fn passing() {
+        // ld [hl],a
+        let json = format!(
+            r#"[{{"name": "77 0000", "initial": {}, "final": {},
+                  "cycles": [[256, 119, "r-m"], [49152, 66, "-wm"]]}}]"#,
+            state(0x100, 0x42, "[[256, 119], [49152, 0]]"),
+            state(0x101, 0x42, "[[256, 119], [49152, 66]]")
+        );
+
+        let tests = parse_tests(&json).unwrap();
+        assert_eq!(
+            tests[0].cycles,
+            vec![Access::Read(0x100), Access::Write(0xC000, 0x42)]
+        );
+        assert_eq!(run_test(&tests[0]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn mismatches() {
+        // nop, expecting the wrong pc & an extra idle cycle
+        let json = format!(
+            r#"[{{"name": "00 0000", "initial": {}, "final": {},
+                  "cycles": [[256, 0, "r-m"], null]}}]"#,
+            state(0x100, 0, "[[256, 0]]"),
+            state(0x102, 0, "[[256, 0]]")
+        );
+
+        let mismatches = run_test(&parse_tests(&json).unwrap()[0]);
+        assert_eq!(
+            mismatches,
+            vec![
+                "pc 0101, expected 0102",
+                "1 bus cycles, expected 2",
+                "returned 4 cycles, expected 8",
+            ]
+        );
+    }
+}
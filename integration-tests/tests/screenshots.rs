@@ -0,0 +1,117 @@
+//! Golden image tests for rendering. `synthetic` builds its own ROM so always
+//! runs against the checked in `golden/synthetic.png`. The rest use ROMs from
+//! the test ROM directory (see `rom_dir`), which aren't checked in, so they're
+//! ignored by default: run them with `cargo test -- --ignored` once the ROMs
+//! are there, setting `UPDATE_GOLDEN=1` the first time to create the images
+//! in `golden/`.
+
+extern crate gameboy_tests;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gameboy_tests::screenshot::Screenshot;
+use gameboy_tests::{find_rom, rom_dir};
+
+fn golden(name: &str) -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/golden")).join(name)
+}
+
+fn check(rom: &str, frame: u64, golden_name: &str) {
+    let dir = rom_dir();
+    let rom = match find_rom(&dir, rom) {
+        Some(rom) => rom,
+        None => panic!("{} not found in {}", rom, dir.display()),
+    };
+
+    if let Err(e) = Screenshot::new(&rom, frame).check(&golden(golden_name)) {
+        panic!("{}", e);
+    }
+}
+
+/// Draws a diagonal pattern of four tiles over the whole background, with a
+/// sprite on top using its own palette
+fn synthetic_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0xF0, 0x44,             // wait: ldh a,[rLY]
+        0xFE, 0x90,             //       cp 144
+        0x38, 0xFA,             //       jr c,wait
+        0xAF,                   //       xor a
+        0xE0, 0x40,             //       ldh [rLCDC],a
+        0x21, 0x00, 0x80,       //       ld hl,$8000
+        0x11, 0x00, 0x02,       //       ld de,tiles
+        0x01, 0x40, 0x00,       //       ld bc,4 * 16
+        0x1A,                   // copy: ld a,[de]
+        0x22,                   //       ld [hl+],a
+        0x13,                   //       inc de
+        0x0B,                   //       dec bc
+        0x78,                   //       ld a,b
+        0xB1,                   //       or c
+        0x20, 0xF8,             //       jr nz,copy
+        0x21, 0x00, 0x98,       //       ld hl,$9800
+        0x7D,                   // map:  ld a,l
+        0xCB, 0x37,             //       swap a
+        0x0F,                   //       rrca
+        0x85,                   //       add l   ; row + column
+        0xE6, 0x03,             //       and 3
+        0x22,                   //       ld [hl+],a
+        0x7C,                   //       ld a,h
+        0xFE, 0x9C,             //       cp $9C
+        0x20, 0xF3,             //       jr nz,map
+        0x21, 0x00, 0xFE,       //       ld hl,$FE00
+        0x3E, 0x38,             //       ld a,56 ; y
+        0x22,                   //       ld [hl+],a
+        0x3E, 0x30,             //       ld a,48 ; x
+        0x22,                   //       ld [hl+],a
+        0x3E, 0x02,             //       ld a,2  ; tile
+        0x22,                   //       ld [hl+],a
+        0xAF,                   //       xor a   ; attributes
+        0x77,                   //       ld [hl],a
+        0x3E, 0xE4,             //       ld a,%11100100
+        0xE0, 0x47,             //       ldh [rBGP],a
+        0x3E, 0x1B,             //       ld a,%00011011
+        0xE0, 0x48,             //       ldh [rOBP0],a
+        0x3E, 0x93,             //       ld a,LCDCF_ON | LCDCF_BG8000 | LCDCF_OBJON | LCDCF_BGON
+        0xE0, 0x40,             //       ldh [rLCDC],a
+        0x18, 0xFE,             //       jr @
+    ];
+    #[rustfmt::skip]
+    let tiles = [
+        // Blank
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Checkerboard of colours 1 & 2
+        0xAA, 0x55, 0x55, 0xAA, 0xAA, 0x55, 0x55, 0xAA,
+        0xAA, 0x55, 0x55, 0xAA, 0xAA, 0x55, 0x55, 0xAA,
+        // Stripes of colours 1 & 3
+        0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0xFF, 0xFF,
+        0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0xFF, 0xFF,
+        // Colour 3 box
+        0xFF, 0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81,
+        0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF, 0xFF,
+    ];
+
+    let mut rom = vec![0; 32 * 1024];
+    // nop / jp $0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+    rom[0x200..0x200 + tiles.len()].copy_from_slice(&tiles);
+    rom
+}
+
+#[test]
+fn synthetic() {
+    let rom = Path::new(env!("CARGO_TARGET_TMPDIR")).join("synthetic.gb");
+    fs::write(&rom, synthetic_rom()).unwrap();
+
+    if let Err(e) = Screenshot::new(&rom, 10).check(&golden("synthetic.png")) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+#[ignore = "needs dmg-acid2.gb & golden/dmg-acid2.png"]
+fn dmg_acid2() {
+    check("dmg-acid2.gb", 60, "dmg-acid2.png");
+}
@@ -0,0 +1,62 @@
+//! `cargo test` entry point for the sm83 single-step CPU tests. Runs the
+//! JSON files in `$SM83_TESTS`, or `sm83/` next to this crate, failing on
+//! any mismatch. Skipped when there are no test files.
+
+extern crate gameboy_tests;
+
+use std::env;
+use std::panic;
+use std::path::PathBuf;
+use std::process;
+
+use gameboy_tests::sm83::{find_test_files, run_file};
+
+// Mismatching tests to show for each failing file
+const SHOWN_FAILURES: usize = 3;
+
+fn main() {
+    let dir = env::var_os("SM83_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/sm83")));
+
+    let files = find_test_files(&dir);
+    if files.is_empty() {
+        println!("No sm83 tests in {}, skipping", dir.display());
+        return;
+    }
+
+    // Unimplemented opcodes panic, keep that out of the table
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failed = 0;
+
+    for path in files {
+        let result = match run_file(&path) {
+            Ok(result) => result,
+            Err(e) => {
+                println!("{}", e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        println!(
+            "{:<12} {:>5}/{:<5}",
+            result.name,
+            result.passed(),
+            result.total
+        );
+
+        if !result.failures.is_empty() {
+            failed += 1;
+            for (name, mismatches) in result.failures.iter().take(SHOWN_FAILURES) {
+                println!("    {}: {}", name, mismatches.join(", "));
+            }
+        }
+    }
+
+    if failed > 0 {
+        println!("\n{} files with failures", failed);
+        process::exit(1);
+    }
+}
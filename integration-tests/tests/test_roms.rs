@@ -0,0 +1,29 @@
+//! `cargo test` entry point for the test ROM suite. Runs the ROMs in
+//! `$GB_TEST_ROMS`, or `roms/` next to this crate, failing on any result
+//! which doesn't match `expectations.txt`. Skipped when there are no ROMs.
+
+extern crate gameboy_tests;
+
+use std::fs;
+use std::process;
+
+use gameboy_tests::{find_roms, parse_expectations, print_summary, rom_dir, run_suite};
+
+fn main() {
+    let dir = rom_dir();
+
+    if find_roms(&dir).is_empty() {
+        println!("No test ROMs in {}, skipping", dir.display());
+        return;
+    }
+
+    let expectations = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/expectations.txt"))
+        .expect("Failed to read expectations.txt");
+    let expectations = parse_expectations(&expectations).expect("Invalid expectations.txt");
+
+    let results = run_suite(&dir, &expectations);
+
+    if !print_summary(&results) {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,464 @@
+//! Keyboard & gamepad bindings. Every control (a key, a gamepad button or
+//! one direction of a gamepad axis) maps to a joypad button, a turbo button
+//! or one of the frontend's hotkeys. The defaults can be replaced a section
+//! at a time from a TOML file:
+//!
+//! ```toml
+//! # Frames each turbo press & release lasts
+//! turbo-period = 2
+//! # How far an axis has to move to count as pressed, out of 32767
+//! axis-threshold = 16384
+//!
+//! [keyboard]
+//! A = "a"
+//! Z = "b"
+//! X = "turbo-a"
+//! Escape = "quit"
+//!
+//! [gamepad.buttons]
+//! 0 = "a"
+//! 1 = "b"
+//!
+//! [gamepad.axes]
+//! "0-" = "left"
+//! "0+" = "right"
+//! ```
+//!
+//! Keys go by minifb's names (`Key1`, `Backquote`, `LeftShift`, ...).
+//! Actions are the joypad buttons, `turbo-` followed by one, or the hotkeys
+//! `quit`, `break`, `trace-on`, `trace-off`, `dump-watch`, `gpu-trace`,
+//! `fast-forward`, `slow-motion` & `rewind`.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use gameboy::input::{Button, Input};
+use minifb::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    Quit,
+    Break,
+    TraceOn,
+    TraceOff,
+    DumpWatch,
+    GpuTrace,
+    FastForward,
+    SlowMotion,
+    Rewind,
+}
+
+const HOTKEYS: [(&str, Hotkey); 9] = [
+    ("quit", Hotkey::Quit),
+    ("break", Hotkey::Break),
+    ("trace-on", Hotkey::TraceOn),
+    ("trace-off", Hotkey::TraceOff),
+    ("dump-watch", Hotkey::DumpWatch),
+    ("gpu-trace", Hotkey::GpuTrace),
+    ("fast-forward", Hotkey::FastForward),
+    ("slow-motion", Hotkey::SlowMotion),
+    ("rewind", Hotkey::Rewind),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Button(Button),
+    /// Presses & releases the button over & over while held
+    Turbo(Button),
+    Hotkey(Hotkey),
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        if let Some(button) = s.strip_prefix("turbo-") {
+            return Ok(Action::Turbo(button.parse()?));
+        }
+        if let Some((_, hotkey)) = HOTKEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(Action::Hotkey(*hotkey));
+        }
+
+        s.parse()
+            .map(Action::Button)
+            .map_err(|_| format!("Unknown action {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Control {
+    Key(Key),
+    PadButton(u8),
+    /// Axis number & whether it's the positive direction
+    PadAxis(u8, bool),
+}
+
+// Every key which can be bound, named as in `Debug`
+#[rustfmt::skip]
+const KEYS: &[Key] = &[
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5,
+    Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8,
+    Key::F9, Key::F10, Key::F11, Key::F12,
+    Key::Down, Key::Left, Key::Right, Key::Up,
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal,
+    Key::LeftBracket, Key::Minus, Key::Period, Key::RightBracket,
+    Key::Semicolon, Key::Slash, Key::Backspace, Key::Delete, Key::End,
+    Key::Enter, Key::Escape, Key::Home, Key::Insert, Key::Menu,
+    Key::PageDown, Key::PageUp, Key::Pause, Key::Space, Key::Tab,
+    Key::NumLock, Key::CapsLock, Key::ScrollLock,
+    Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl,
+    Key::LeftAlt, Key::RightAlt, Key::LeftSuper, Key::RightSuper,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadDot, Key::NumPadSlash, Key::NumPadAsterisk,
+    Key::NumPadMinus, Key::NumPadPlus, Key::NumPadEnter,
+];
+
+fn key_from_name(name: &str) -> Option<Key> {
+    KEYS.iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// `0-` or `0+`, an axis number & direction
+fn axis_from_name(name: &str) -> Option<Control> {
+    let (number, positive) = match name.strip_suffix('+') {
+        Some(number) => (number, true),
+        None => (name.strip_suffix('-')?, false),
+    };
+
+    Some(Control::PadAxis(number.parse().ok()?, positive))
+}
+
+pub struct Bindings {
+    map: HashMap<Control, Action>,
+    /// Frames each turbo press & release lasts
+    pub turbo_period: u64,
+    pub axis_threshold: i16,
+}
+
+impl Bindings {
+    /// The keyboard layout the emulator has always had, & an Xbox style
+    /// gamepad as Linux numbers its controls
+    pub fn new() -> Bindings {
+        let keyboard = [
+            (Key::A, Action::Button(Button::A)),
+            (Key::Z, Action::Button(Button::B)),
+            (Key::M, Action::Button(Button::Start)),
+            (Key::N, Action::Button(Button::Select)),
+            (Key::Up, Action::Button(Button::Up)),
+            (Key::Down, Action::Button(Button::Down)),
+            (Key::Left, Action::Button(Button::Left)),
+            (Key::Right, Action::Button(Button::Right)),
+            (Key::Escape, Action::Hotkey(Hotkey::Quit)),
+            (Key::B, Action::Hotkey(Hotkey::Break)),
+            (Key::D, Action::Hotkey(Hotkey::TraceOn)),
+            (Key::E, Action::Hotkey(Hotkey::TraceOff)),
+            (Key::W, Action::Hotkey(Hotkey::DumpWatch)),
+            (Key::G, Action::Hotkey(Hotkey::GpuTrace)),
+            (Key::Tab, Action::Hotkey(Hotkey::FastForward)),
+            (Key::Backquote, Action::Hotkey(Hotkey::SlowMotion)),
+            (Key::Backspace, Action::Hotkey(Hotkey::Rewind)),
+        ];
+        let buttons = [
+            (0, Action::Button(Button::A)),
+            (1, Action::Button(Button::B)),
+            (2, Action::Turbo(Button::B)),
+            (3, Action::Turbo(Button::A)),
+            (4, Action::Hotkey(Hotkey::Rewind)),
+            (5, Action::Hotkey(Hotkey::FastForward)),
+            (6, Action::Button(Button::Select)),
+            (7, Action::Button(Button::Start)),
+        ];
+        // Left stick, then the d-pad which Linux reports as a hat
+        let axes = [
+            (0, Button::Left, Button::Right),
+            (1, Button::Up, Button::Down),
+            (6, Button::Left, Button::Right),
+            (7, Button::Up, Button::Down),
+        ];
+
+        let mut map = HashMap::new();
+        for &(key, action) in keyboard.iter() {
+            map.insert(Control::Key(key), action);
+        }
+        for &(button, action) in buttons.iter() {
+            map.insert(Control::PadButton(button), action);
+        }
+        for &(axis, negative, positive) in axes.iter() {
+            map.insert(Control::PadAxis(axis, false), Action::Button(negative));
+            map.insert(Control::PadAxis(axis, true), Action::Button(positive));
+        }
+
+        Bindings {
+            map,
+            turbo_period: 2,
+            axis_threshold: 16384,
+        }
+    }
+
+    /// The defaults, with each section given in `text` replacing that part
+    /// of them
+    pub fn parse(text: &str) -> Result<Bindings, String> {
+        let config: toml::Value = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut bindings = Bindings::new();
+
+        if let Some(period) = config.get("turbo-period") {
+            bindings.turbo_period = period
+                .as_integer()
+                .filter(|&period| period > 0)
+                .ok_or("turbo-period should be a number of frames")?
+                as u64;
+        }
+        if let Some(threshold) = config.get("axis-threshold") {
+            bindings.axis_threshold = threshold
+                .as_integer()
+                .filter(|&threshold| threshold > 0 && threshold <= i64::from(i16::MAX))
+                .ok_or("axis-threshold should be between 1 and 32767")?
+                as i16;
+        }
+
+        if let Some(keyboard) = config.get("keyboard") {
+            bindings.replace(
+                keyboard,
+                "keyboard",
+                |c| matches!(c, Control::Key(_)),
+                |name| key_from_name(name).map(Control::Key),
+            )?;
+        }
+        if let Some(gamepad) = config.get("gamepad") {
+            if let Some(buttons) = gamepad.get("buttons") {
+                bindings.replace(
+                    buttons,
+                    "gamepad.buttons",
+                    |c| matches!(c, Control::PadButton(_)),
+                    |name| name.parse().ok().map(Control::PadButton),
+                )?;
+            }
+            if let Some(axes) = gamepad.get("axes") {
+                bindings.replace(
+                    axes,
+                    "gamepad.axes",
+                    |c| matches!(c, Control::PadAxis(..)),
+                    axis_from_name,
+                )?;
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    /// Swap the controls of one `kind` for those in `section`, named as
+    /// `control` reads them
+    fn replace<F>(
+        &mut self,
+        section: &toml::Value,
+        name: &str,
+        kind: fn(&Control) -> bool,
+        control: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(&str) -> Option<Control>,
+    {
+        let table = section
+            .as_table()
+            .ok_or_else(|| format!("[{}] should be a table", name))?;
+
+        let mut controls = HashMap::new();
+        for (control_name, action) in table {
+            let control = control(control_name)
+                .ok_or_else(|| format!("[{}] unknown control {}", name, control_name))?;
+            let action = action
+                .as_str()
+                .ok_or_else(|| format!("[{}] {} should be an action name", name, control_name))?
+                .parse()
+                .map_err(|e| format!("[{}] {}", name, e))?;
+            controls.insert(control, action);
+        }
+
+        self.map.retain(|c, _| !kind(c));
+        self.map.extend(controls);
+
+        Ok(())
+    }
+
+    pub fn action(&self, control: Control) -> Option<Action> {
+        self.map.get(&control).copied()
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings::new()
+    }
+}
+
+/// Follows which controls are held to work out the joypad each frame, from
+/// the keyboard & gamepads alike
+pub struct Controls {
+    bindings: Bindings,
+    held: HashSet<Control>,
+    // Frames since a turbo button was first held
+    turbo_frames: u64,
+}
+
+impl Controls {
+    pub fn new(bindings: Bindings) -> Controls {
+        Controls {
+            bindings,
+            held: HashSet::new(),
+            turbo_frames: 0,
+        }
+    }
+
+    /// Returns the hotkey it's bound to, to act on once
+    pub fn press(&mut self, control: Control) -> Option<Hotkey> {
+        if !self.held.insert(control) {
+            return None;
+        }
+
+        match self.bindings.action(control) {
+            Some(Action::Hotkey(hotkey)) => Some(hotkey),
+            _ => None,
+        }
+    }
+
+    pub fn release(&mut self, control: Control) {
+        self.held.remove(&control);
+    }
+
+    /// Gamepad axis moved, pressing one of its directions once past the
+    /// threshold
+    pub fn axis(&mut self, axis: u8, value: i16) -> Option<Hotkey> {
+        let threshold = self.bindings.axis_threshold;
+        let negative = Control::PadAxis(axis, false);
+        let positive = Control::PadAxis(axis, true);
+
+        if value >= threshold {
+            self.release(negative);
+            self.press(positive)
+        } else if value <= -threshold {
+            self.release(positive);
+            self.press(negative)
+        } else {
+            self.release(negative);
+            self.release(positive);
+            None
+        }
+    }
+
+    pub fn is_held(&self, hotkey: Hotkey) -> bool {
+        self.actions()
+            .any(|action| action == Action::Hotkey(hotkey))
+    }
+
+    fn actions(&self) -> impl Iterator<Item = Action> + '_ {
+        self.held
+            .iter()
+            .filter_map(move |&control| self.bindings.action(control))
+    }
+
+    /// Set the joypad from the controls held, once a frame
+    pub fn update(&mut self, input: &mut Input) {
+        let turbo_held = self
+            .actions()
+            .any(|action| matches!(action, Action::Turbo(_)));
+        let turbo_down = (self.turbo_frames / self.bindings.turbo_period).is_multiple_of(2);
+        self.turbo_frames = if turbo_held { self.turbo_frames + 1 } else { 0 };
+
+        for &button in Button::ALL.iter() {
+            let pressed = self.actions().any(|action| {
+                action == Action::Button(button) || (turbo_down && action == Action::Turbo(button))
+            });
+            input.set_input(button, pressed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let bindings = Bindings::parse(
+            r#"
+            turbo-period = 3
+
+            [keyboard]
+            X = "turbo-a"
+            Key1 = "fast-forward"
+
+            [gamepad.axes]
+            "2+" = "up"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(bindings.turbo_period, 3);
+        assert_eq!(
+            bindings.action(Control::Key(Key::X)),
+            Some(Action::Turbo(Button::A))
+        );
+        assert_eq!(
+            bindings.action(Control::Key(Key::Key1)),
+            Some(Action::Hotkey(Hotkey::FastForward))
+        );
+        // Replaced sections lose their defaults, the others keep them
+        assert_eq!(bindings.action(Control::Key(Key::A)), None);
+        assert_eq!(bindings.action(Control::PadAxis(0, false)), None);
+        assert_eq!(
+            bindings.action(Control::PadAxis(2, true)),
+            Some(Action::Button(Button::Up))
+        );
+        assert_eq!(
+            bindings.action(Control::PadButton(0)),
+            Some(Action::Button(Button::A))
+        );
+
+        assert!(Bindings::parse("[keyboard]\nNotAKey = \"a\"").is_err());
+        assert!(Bindings::parse("[keyboard]\nA = \"jump\"").is_err());
+        assert!(Bindings::parse("turbo-period = 0").is_err());
+    }
+
+    #[test]
+    fn controls() {
+        let mut input = Input::new();
+        let mut controls = Controls::new(Bindings::new());
+
+        assert_eq!(controls.press(Control::Key(Key::A)), None);
+        assert_eq!(controls.press(Control::PadButton(7)), None);
+        assert_eq!(controls.axis(0, -20000), None);
+        assert_eq!(
+            controls.press(Control::Key(Key::Tab)),
+            Some(Hotkey::FastForward)
+        );
+        assert!(controls.is_held(Hotkey::FastForward));
+        controls.update(&mut input);
+        assert!(input.is_pressed(0, Button::A));
+        assert!(input.is_pressed(0, Button::Start));
+        assert!(input.is_pressed(0, Button::Left));
+
+        controls.release(Control::Key(Key::A));
+        controls.axis(0, 100);
+        controls.update(&mut input);
+        assert!(!input.is_pressed(0, Button::A));
+        assert!(!input.is_pressed(0, Button::Left));
+
+        // Turbo B, down for two frames then up for two
+        controls.press(Control::PadButton(2));
+        let mut turbo = Vec::new();
+        for _ in 0..5 {
+            controls.update(&mut input);
+            turbo.push(input.is_pressed(0, Button::B));
+        }
+        assert_eq!(turbo, vec![true, true, false, false, true]);
+    }
+}
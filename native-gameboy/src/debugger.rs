@@ -0,0 +1,287 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use gameboy::debugger::{Interrupt, Register, StopReason, WatchAction, WatchKind};
+use gameboy::disassemble::{disassemble, disassemble_bank, Disassembly, Symbols};
+use gameboy::gameboy::GameBoy;
+
+const HELP: &str = "\
+c | continue          resume execution
+s | step [n]          execute n instructions (default 1)
+n | next              step over CALL / RST
+f | finish            run until the current function returns
+b | break [bb:]<addr> add a breakpoint at addr (hex), optionally only in ROM bank bb
+d | delete <addr>     remove the breakpoints at addr
+bl                    list breakpoints & watchpoints
+w | watch <start> [end] [r|w|a]
+                      break on reads, writes (default) or any access to a range
+cond <reg> <value>    break when a register becomes value (hex)
+int <vblank|stat|timer|joypad>
+                      break after the interrupt is serviced
+r | regs              dump registers
+x <addr> [len]        hexdump len bytes (hex, default 0x40) from addr
+l | list [n]          disassemble n instructions around pc (default 10)
+q | quit              exit the emulator";
+
+/// Command line front end to the core `gameboy::debugger::Debugger`
+pub struct Repl {
+    break_requested: Arc<AtomicBool>,
+    symbols: Symbols,
+}
+
+impl Repl {
+    pub fn new(symbols: Symbols) -> Repl {
+        Repl {
+            break_requested: Arc::new(AtomicBool::new(false)),
+            symbols,
+        }
+    }
+
+    /// Flag which breaks into the debugger before the next instruction when
+    /// set, e.g. from a Ctrl-C handler
+    pub fn break_flag(&self) -> Arc<AtomicBool> {
+        self.break_requested.clone()
+    }
+
+    pub fn request_break(&self) {
+        self.break_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Pass on any requested break, called before each `GameBoy::cycle`
+    pub fn poll(&self, gb: &mut GameBoy) {
+        if self.break_requested.swap(false, Ordering::SeqCst) {
+            gb.debugger().pause();
+        }
+    }
+
+    /// Read commands until execution should resume. Returns false if the
+    /// user quit.
+    pub fn run(&mut self, gb: &mut GameBoy, reason: StopReason) -> bool {
+        match reason {
+            StopReason::Breakpoint { pc, bank } => {
+                println!("Breakpoint at {:02X}:{:04X}", bank, pc)
+            }
+            StopReason::Watchpoint(hit) => println!(
+                "Watchpoint: pc {:04X} {} {:04X}: {:02X} -> {:02X} at cycle {}",
+                hit.pc,
+                if hit.write { "wrote" } else { "read" },
+                hit.addr,
+                hit.old,
+                hit.new,
+                hit.cycles
+            ),
+            StopReason::Condition { reg, value } => println!("{:?} == {:X}", reg, value),
+            StopReason::Interrupt(int) => println!("Interrupt {:?}", int),
+            StopReason::Step | StopReason::Pause => {}
+        }
+        self.print_location(gb);
+
+        let stdin = io::stdin();
+
+        loop {
+            print!("(gbdb) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                // EOF, nothing more to read so carry on running
+                gb.debugger().resume();
+                return true;
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            let (cmd, args) = match args.split_first() {
+                Some((cmd, args)) => (*cmd, args),
+                None => continue,
+            };
+
+            match cmd {
+                "c" | "continue" => {
+                    gb.debugger().resume();
+                    return true;
+                }
+                "s" | "step" => {
+                    let n = args.first().and_then(|n| n.parse().ok()).unwrap_or(1);
+                    gb.debugger().step(n);
+                    return true;
+                }
+                "n" | "next" => {
+                    gb.step_over();
+                    return true;
+                }
+                "f" | "finish" => {
+                    gb.step_out();
+                    return true;
+                }
+                "b" | "break" => match args.first().and_then(|a| parse_location(a)) {
+                    Some((bank, addr)) => gb.debugger().add_breakpoint(addr, bank),
+                    None => println!("usage: break [bank:]<addr>"),
+                },
+                "d" | "delete" => match args.first().and_then(|a| parse_hex(a)) {
+                    Some(addr) => gb.debugger().remove_breakpoint(addr),
+                    None => println!("usage: delete <addr>"),
+                },
+                "bl" => {
+                    for bp in gb.debugger().breakpoints() {
+                        match bp.bank {
+                            Some(bank) => println!("break {:02X}:{:04X}", bank, bp.addr),
+                            None => println!("break {:04X}", bp.addr),
+                        }
+                    }
+                    for wp in gb.debugger().watchpoints() {
+                        println!("watch {:04X}-{:04X} {:?}", wp.start, wp.end, wp.kind);
+                    }
+                }
+                "w" | "watch" => match args.first().and_then(|a| parse_hex(a)) {
+                    Some(start) => {
+                        let end = args.get(1).and_then(|a| parse_hex(a)).unwrap_or(start);
+                        let kind = match args.get(2).copied() {
+                            Some("r") => WatchKind::Read,
+                            Some("a") => WatchKind::Access,
+                            _ => WatchKind::Write,
+                        };
+                        gb.debugger()
+                            .add_watchpoint(start, end, kind, WatchAction::Break);
+                    }
+                    None => println!("usage: watch <start> [end] [r|w|a]"),
+                },
+                "cond" => {
+                    let reg = args.first().and_then(|r| r.parse::<Register>().ok());
+                    let value = args.get(1).and_then(|v| parse_hex(v));
+                    match (reg, value) {
+                        (Some(reg), Some(value)) => gb.debugger().add_condition(reg, value),
+                        _ => println!("usage: cond <reg> <value>"),
+                    }
+                }
+                "int" => {
+                    let int = match args.first().copied() {
+                        Some("vblank") => Some(Interrupt::VBlank),
+                        Some("stat") => Some(Interrupt::LcdStat),
+                        Some("timer") => Some(Interrupt::Timer),
+                        Some("joypad") => Some(Interrupt::Joypad),
+                        _ => None,
+                    };
+                    match int {
+                        Some(int) => gb.debugger().set_break_on_interrupt(int, true),
+                        None => println!("usage: int <vblank|stat|timer|joypad>"),
+                    }
+                }
+                "r" | "regs" => print_registers(gb),
+                "x" => match args.first().and_then(|a| parse_hex(a)) {
+                    Some(start) => {
+                        let len = args.get(1).and_then(|a| parse_hex(a)).unwrap_or(0x40);
+                        hexdump(gb, start, len);
+                    }
+                    None => println!("usage: x <addr> [len]"),
+                },
+                "l" | "list" => {
+                    let n = args.first().and_then(|n| n.parse().ok()).unwrap_or(10);
+                    self.list(gb, n);
+                }
+                "q" | "quit" => return false,
+                "h" | "help" => println!("{}", HELP),
+                _ => println!("Unknown command {:?}, try 'help'", cmd),
+            }
+        }
+    }
+
+    /// Disassemble code in ROM from the bank it's in so labels are right,
+    /// anything else as currently mapped
+    fn disassemble(&self, gb: &GameBoy, start: u16, end: u16) -> Vec<Disassembly> {
+        if end < 0x8000 {
+            let bank = gb.mem.rom_bank(start);
+            disassemble_bank(gb.rom(), bank, start, end, &self.symbols)
+        } else {
+            disassemble(start, end, &gb.mem)
+        }
+    }
+
+    fn print_location(&self, gb: &GameBoy) {
+        let pc = gb.cpu.pc;
+        if let Some(d) = self.disassemble(gb, pc, pc).first() {
+            if let Some(label) = &d.label {
+                println!("{}:", label);
+            }
+            println!("{:02X}:{:04X}: {}", d.bank, pc, d.desc);
+        }
+    }
+
+    fn list(&self, gb: &GameBoy, count: usize) {
+        let pc = gb.cpu.pc;
+
+        // Instructions are variable length, so find a start point a little
+        // before pc which decodes back onto it
+        let start = (1..=8)
+            .rev()
+            .map(|back| pc.saturating_sub(back))
+            .find(|&start| self.disassemble(gb, start, pc).iter().any(|d| d.addr == pc))
+            .unwrap_or(pc);
+
+        let end = pc.saturating_add(count as u16 * 3);
+
+        for d in self.disassemble(gb, start, end).iter().take(count) {
+            if let Some(label) = &d.label {
+                println!("{}:", label);
+            }
+            let marker = if d.addr == pc { "=>" } else { "  " };
+            let bytes: Vec<String> = d.data.iter().map(|b| format!("{:02X}", b)).collect();
+            println!(
+                "{} {:02X}:{:04X}: {:<9} {}",
+                marker,
+                d.bank,
+                d.addr,
+                bytes.join(" "),
+                d.desc
+            );
+        }
+    }
+}
+
+pub fn parse_hex(s: &str) -> Option<u16> {
+    let s = s.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(s, 16).ok()
+}
+
+/// Either `addr` or `bank:addr`, in hex
+pub fn parse_location(s: &str) -> Option<(Option<u8>, u16)> {
+    match s.find(':') {
+        Some(split) => {
+            let bank = u8::from_str_radix(&s[..split], 16).ok()?;
+            Some((Some(bank), parse_hex(&s[split + 1..])?))
+        }
+        None => Some((None, parse_hex(s)?)),
+    }
+}
+
+fn print_registers(gb: &GameBoy) {
+    let cpu = &gb.cpu;
+
+    println!(
+        "AF {:02X}{:02X} BC {:02X}{:02X} DE {:02X}{:02X} HL {:02X}{:02X} SP {:04X} PC {:04X}",
+        cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc
+    );
+    println!(
+        "Flags Z{} N{} H{} C{} IME {} halted {} cycles {}",
+        cpu.z_flag() as u8,
+        cpu.n_flag() as u8,
+        cpu.h_flag() as u8,
+        cpu.c_flag() as u8,
+        cpu.interrupts,
+        cpu.halted,
+        gb.cycles
+    );
+}
+
+fn hexdump(gb: &GameBoy, start: u16, len: u16) {
+    if len == 0 {
+        return;
+    }
+    let end = start.saturating_add(len - 1);
+    let bytes = gb.read_region(start, end);
+
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("{:04X}: {}", start as usize + row * 16, hex.join(" "));
+    }
+}
@@ -0,0 +1,133 @@
+//! Gamepads through the Linux joystick interface, `/dev/input/js*`. Reads
+//! block until something moves, so each device is read on its own thread.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+const JS_EVENT_BUTTON: u8 = 0x01;
+const JS_EVENT_AXIS: u8 = 0x02;
+// Set on the events reporting each control's state when the device opens
+const JS_EVENT_INIT: u8 = 0x80;
+const JS_EVENT_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadEvent {
+    Button(u8, bool),
+    Axis(u8, i16),
+}
+
+pub struct Gamepads {
+    events: Receiver<PadEvent>,
+    connected: usize,
+}
+
+impl Gamepads {
+    /// Open each of `paths`, or every joystick device there is if none are
+    /// given. Ones which can't be opened are reported & skipped.
+    pub fn open(paths: &[PathBuf]) -> Gamepads {
+        let paths = if paths.is_empty() {
+            find_devices()
+        } else {
+            paths.to_vec()
+        };
+
+        let (sender, events) = mpsc::channel();
+        let mut connected = 0;
+
+        for path in paths {
+            match File::open(&path) {
+                Ok(device) => {
+                    let sender = sender.clone();
+                    thread::spawn(move || read_events(device, sender));
+                    connected += 1;
+                }
+                Err(e) => println!("Couldn't open gamepad {}: {}", path.display(), e),
+            }
+        }
+
+        Gamepads { events, connected }
+    }
+
+    pub fn connected(&self) -> usize {
+        self.connected
+    }
+
+    /// Everything that's happened since the last call
+    pub fn events(&self) -> impl Iterator<Item = PadEvent> + '_ {
+        self.events.try_iter()
+    }
+}
+
+fn find_devices() -> Vec<PathBuf> {
+    let mut devices: Vec<PathBuf> = fs::read_dir(Path::new("/dev/input"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("js"))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    devices.sort();
+    devices
+}
+
+/// Until the device is unplugged or nobody's listening
+fn read_events(mut device: File, events: Sender<PadEvent>) {
+    let mut event = [0; JS_EVENT_SIZE];
+
+    while device.read_exact(&mut event).is_ok() {
+        if let Some(event) = decode(&event) {
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// A `struct js_event`: u32 timestamp, i16 value, u8 type & u8 number
+fn decode(event: &[u8; JS_EVENT_SIZE]) -> Option<PadEvent> {
+    let value = i16::from_ne_bytes([event[4], event[5]]);
+    let number = event[7];
+
+    match event[6] & !JS_EVENT_INIT {
+        JS_EVENT_BUTTON => Some(PadEvent::Button(number, value != 0)),
+        JS_EVENT_AXIS => Some(PadEvent::Axis(number, value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_events() {
+        let event = |value: i16, kind: u8, number: u8| {
+            let value = value.to_ne_bytes();
+            [0, 0, 0, 0, value[0], value[1], kind, number]
+        };
+
+        assert_eq!(
+            decode(&event(1, JS_EVENT_BUTTON, 3)),
+            Some(PadEvent::Button(3, true))
+        );
+        assert_eq!(
+            decode(&event(0, JS_EVENT_BUTTON | JS_EVENT_INIT, 0)),
+            Some(PadEvent::Button(0, false))
+        );
+        assert_eq!(
+            decode(&event(-32767, JS_EVENT_AXIS, 1)),
+            Some(PadEvent::Axis(1, -32767))
+        );
+        assert_eq!(decode(&event(0, 0x04, 0)), None);
+    }
+}
@@ -1,15 +1,42 @@
 extern crate gameboy;
 
-use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, Instant};
+mod bindings;
+mod debugger;
+mod gamepad;
+mod pacing;
+mod video;
 
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Instant;
+
+use gameboy::debugger::{WatchAction, WatchHit, WatchKind};
+use gameboy::disassemble::Symbols;
+use gameboy::frame::PixelFormat;
 use gameboy::gameboy::GameBoy;
 use gameboy::gpu::{GB_HSIZE, GB_VSIZE};
-use gameboy::input::Button;
+use gameboy::model::Model;
+use gameboy::movie::Movie;
+use gameboy::palette::Palettes;
+use gameboy::sgb::{SGB_HSIZE, SGB_VSIZE};
+use gameboy::trace::{TraceFormat, Tracer};
+
+use crate::bindings::{Bindings, Control, Controls, Hotkey};
+use crate::debugger::{parse_hex, parse_location, Repl};
+use crate::gamepad::{Gamepads, PadEvent};
+use crate::pacing::{Pacer, Speed, SpeedMeter, FRAME_RATE};
+use crate::video::{Effects, Video, MAX_GHOSTING};
+
+use clap::{App, Arg, Error, ErrorKind};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use minifb::{Scale, ScaleMode, Window, WindowOptions};
 
-use clap::{App, Arg};
-use minifb::{Key, Window, WindowOptions};
+// Frames between rewind snapshots
+const REWIND_INTERVAL: u64 = 2;
 
 fn main() -> Result<(), std::io::Error> {
     let matches = App::new("gb-rust")
@@ -22,123 +49,631 @@ fn main() -> Result<(), std::io::Error> {
                 .help("print debug information verbosely"),
         )
         .arg(
-            Arg::with_name("watch start")
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .help("Log every instruction to a file, gzip compressed if it ends in .gz"),
+        )
+        .arg(
+            Arg::with_name("trace-format")
+                .long("trace-format")
+                .takes_value(true)
+                .possible_values(&["doctor", "verbose"])
+                .default_value("doctor")
+                .help("Format of --trace, doctor matches Gameboy Doctor's logs"),
+        )
+        .arg(
+            Arg::with_name("trace-start")
+                .long("trace-start")
+                .takes_value(true)
+                .validator(validate_cycles)
+                .help("Cycle count to start tracing at"),
+        )
+        .arg(
+            Arg::with_name("trace-end")
+                .long("trace-end")
+                .takes_value(true)
+                .validator(validate_cycles)
+                .help("Cycle count to stop tracing at"),
+        )
+        .arg(
+            Arg::with_name("watch-start")
                 .long("watch-start")
-                .help("Watch Address Start"),
+                .takes_value(true)
+                .validator(validate_address)
+                .help("Watch Address Start, logs accesses to the watched range"),
         )
         .arg(
-            Arg::with_name("watch end")
+            Arg::with_name("watch-end")
                 .long("watch-end")
-                .help("Watch Address End"),
+                .takes_value(true)
+                .validator(validate_address)
+                .help("Watch Address End, defaults to the start"),
+        )
+        .arg(
+            Arg::with_name("watch-access")
+                .long("watch-access")
+                .takes_value(true)
+                .possible_values(&["read", "write", "any"])
+                .default_value("write")
+                .help("Accesses to the watched range to log"),
+        )
+        .arg(
+            Arg::with_name("watch-break")
+                .long("watch-break")
+                .help("Break into the debugger on a watched access instead of logging it"),
         )
         .arg(
             Arg::with_name("panic-at-pc")
                 .long("panic-at-pc")
-                .help("Panic at given Program Counter value")
-                .default_value("FFFF"),
+                .takes_value(true)
+                .validator(validate_address)
+                .help("Break into the debugger at given Program Counter value"),
+        )
+        .arg(
+            Arg::with_name("boot-rom")
+                .long("boot-rom")
+                .takes_value(true)
+                .help("Boot ROM image to run before the cartridge"),
+        )
+        .arg(
+            Arg::with_name("model")
+                .long("model")
+                .takes_value(true)
+                .possible_values(&["dmg0", "dmg", "mgb", "sgb", "cgb", "agb"])
+                .default_value("dmg")
+                .help("Hardware model to emulate"),
+        )
+        .arg(
+            Arg::with_name("debugger")
+                .long("debugger")
+                .help("Start paused in the interactive debugger (Ctrl-C or B breaks into it)"),
+        )
+        .arg(
+            Arg::with_name("break")
+                .long("break")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_location)
+                .help("Debugger breakpoint at the given Program Counter value, optionally bank:pc"),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .takes_value(true)
+                .help("RGBDS / no$gmb .sym file for labels in the debugger"),
+        )
+        .arg(
+            Arg::with_name("mcycle-timing")
+                .long("mcycle-timing")
+                .help("Advance the Gpu & timer on each memory access (slower, more accurate)"),
+        )
+        .arg(
+            Arg::with_name("fast-forward")
+                .long("fast-forward")
+                .takes_value(true)
+                .default_value("4")
+                .validator(validate_fast_forward)
+                .help("Speed multiplier while Tab is held, 0 for as fast as possible"),
+        )
+        .arg(
+            Arg::with_name("slow-motion")
+                .long("slow-motion")
+                .takes_value(true)
+                .default_value("0.25")
+                .validator(validate_slow_motion)
+                .help("Speed multiplier while ` is held"),
+        )
+        .arg(
+            Arg::with_name("frame-skip")
+                .long("frame-skip")
+                .takes_value(true)
+                .default_value("4")
+                .validator(validate_frame_skip)
+                .help("Most frames in a row to skip drawing when running behind"),
+        )
+        .arg(
+            Arg::with_name("rewind-seconds")
+                .long("rewind-seconds")
+                .takes_value(true)
+                .default_value("60")
+                .help("History to keep for rewinding with Backspace, 0 to turn it off"),
+        )
+        .arg(
+            Arg::with_name("palette")
+                .long("palette")
+                .takes_value(true)
+                .default_value("greys")
+                .validator(validate_palette)
+                .help(
+                    "Colours for the DMG's shades: greys, dmg, pocket, light, cgb, four hex \
+                     colours like e0f8d0,88c070,346856,081820, three palettes for the \
+                     background, OBJ0 & OBJ1 separated by /, or auto to pick them like the \
+                     Game Boy Color",
+                ),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .default_value("2")
+                .validator(validate_scale)
+                .help("Times bigger to draw the screen, a multiple of the filter's own scale"),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .possible_values(&["nearest", "scale2x", "scale3x"])
+                .default_value("nearest")
+                .help("Scaling filter"),
+        )
+        .arg(
+            Arg::with_name("lcd-grid")
+                .long("lcd-grid")
+                .help("Darken the gaps between pixels like an LCD"),
+        )
+        .arg(
+            Arg::with_name("ghosting")
+                .long("ghosting")
+                .takes_value(true)
+                .validator(validate_ghosting)
+                .help(
+                    "Blend in this much (0 - 0.9) of the previous frame, like the DMG's slow LCD",
+                ),
+        )
+        .arg(
+            Arg::with_name("colour-correction")
+                .long("colour-correction")
+                .help("Mix colours as the Game Boy Color's LCD does"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Borderless window filling the screen"),
+        )
+        .arg(
+            Arg::with_name("bindings")
+                .long("bindings")
+                .takes_value(true)
+                .help("TOML file of keyboard, gamepad & hotkey bindings"),
+        )
+        .arg(
+            Arg::with_name("gamepad")
+                .long("gamepad")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Joystick device to read, defaults to all of /dev/input/js*"),
+        )
+        .arg(
+            Arg::with_name("record-movie")
+                .long("record-movie")
+                .takes_value(true)
+                .help("Record every input from power on to a movie file, written on exit"),
+        )
+        .arg(
+            Arg::with_name("play-movie")
+                .long("play-movie")
+                .takes_value(true)
+                .conflicts_with("record-movie")
+                .help("Play back a movie, or a BizHawk Input Log.txt or VBA .vbm"),
         )
         .arg(Arg::with_name("INPUT").help("Input Gameboy file").index(1))
         .get_matches();
 
     let filename = matches.value_of("INPUT").unwrap_or("tetris.gb");
 
-    let watch_start =
-        u16::from_str_radix(matches.value_of("watch-start").unwrap_or("FF0F"), 16).unwrap();
-    let watch_end =
-        u16::from_str_radix(matches.value_of("watch-end").unwrap_or("FFFF"), 16).unwrap();
+    let watch = matches
+        .value_of("watch-start")
+        .map(|start| parse_hex(start).unwrap());
+    let watch_start = watch.unwrap_or(0xFF0F);
+    let watch_end = match matches.value_of("watch-end") {
+        Some(end) => parse_hex(end).unwrap(),
+        None if watch.is_some() => watch_start,
+        None => 0xFFFF,
+    };
+    if watch_end < watch_start {
+        Error::with_description(
+            "--watch-end is before --watch-start",
+            ErrorKind::ValueValidation,
+        )
+        .exit();
+    }
 
     let mut file = File::open(filename)?;
     let mut rom_contents = Vec::new();
     file.read_to_end(&mut rom_contents)?;
 
-    let mut gb = GameBoy::new(rom_contents);
+    let model: Model = matches.value_of("model").unwrap().parse().unwrap();
+
+    let mut gb = match matches.value_of("boot-rom") {
+        Some(boot_filename) => {
+            let mut boot_rom = Vec::new();
+            File::open(boot_filename)?.read_to_end(&mut boot_rom)?;
+
+            GameBoy::with_boot_rom(rom_contents, model, boot_rom)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        }
+        None => GameBoy::new(rom_contents, model),
+    };
+
+    gb.set_mcycle_timing(matches.is_present("mcycle-timing"));
+    gb.set_frame_format(PixelFormat::Argb32);
+
+    let palettes = match matches.value_of("palette").unwrap() {
+        "auto" => Palettes::for_rom(gb.rom()),
+        palettes => palettes.parse().unwrap(),
+    };
+    gb.set_palettes(palettes);
 
     println!("Loaded rom: {:?}", gb.title());
 
-    let mut window = Window::new(
-        "Rust Gameboy",
-        GB_HSIZE,
-        GB_VSIZE,
+    // Super Game Boy output includes the border around the screen
+    let (width, height) = if gb.sgb_enabled() {
+        (SGB_HSIZE, SGB_VSIZE)
+    } else {
+        (GB_HSIZE, GB_VSIZE)
+    };
+
+    let effects = Effects {
+        filter: matches.value_of("filter").unwrap().parse().unwrap(),
+        scale: matches.value_of("scale").unwrap().parse().unwrap(),
+        lcd_grid: matches.is_present("lcd-grid"),
+        ghosting: matches
+            .value_of("ghosting")
+            .map_or(0.0, |g| g.parse().unwrap()),
+        colour_correction: matches.is_present("colour-correction"),
+    };
+    // The scale & filter are checked together, so clap can't validate them
+    let mut video = Video::new(width, height, effects)
+        .unwrap_or_else(|e| Error::with_description(&e, ErrorKind::ValueValidation).exit());
+    let (window_width, window_height) = video.output_size();
+
+    // minifb has no fullscreen mode, so the closest is a borderless window
+    // stretched over the screen
+    let window_options = if matches.is_present("fullscreen") {
         WindowOptions {
-            scale: minifb::Scale::X2,
+            borderless: true,
+            title: false,
+            scale: Scale::FitScreen,
+            scale_mode: ScaleMode::AspectRatioStretch,
             ..Default::default()
-        },
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
+        }
+    } else {
+        WindowOptions::default()
+    };
 
-    let mut screen_rgba: Vec<u8> = vec![255; GB_HSIZE * GB_VSIZE * 4];
+    let mut window = Window::new("Rust Gameboy", window_width, window_height, window_options)
+        .unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
 
     let mut debugging = matches.is_present("debug");
 
-    let pc_panic = u16::from_str_radix(matches.value_of("panic-at-pc").unwrap(), 16).unwrap();
-
-    println!("Will panic when pc == {:X?}", pc_panic);
-
-    let mut last_draw = Instant::now();
-
-    loop {
-        let drawn = gb.cycle(debugging, Some(pc_panic));
-
-        if drawn || last_draw.elapsed().as_millis() > 20 {
-            last_draw = Instant::now();
-            screen_rgba.copy_from_slice(gb.buffer_vec());
-
-            // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
-            window
-                .update_with_buffer(
-                    unsafe {
-                        std::slice::from_raw_parts(
-                            screen_rgba.as_ptr() as *const u32,
-                            screen_rgba.len() / 4,
-                        )
-                    },
-                    GB_HSIZE,
-                    GB_VSIZE,
-                )
-                .unwrap();
-
-            for keyset in window.get_keys_pressed(minifb::KeyRepeat::No) {
-                for k in keyset {
-                    match k {
-                        Key::Escape => return Ok(()),
-                        Key::D => debugging = true,
-                        Key::E => debugging = false,
-                        Key::W => println!("{:?}", gb.read_region(watch_start, watch_end)),
-                        Key::G => println!("{:?}", gb.gpu_trace()),
-                        Key::A => gb.input().set_input(Button::A, true),
-                        Key::Z => gb.input().set_input(Button::B, true),
-                        Key::M => gb.input().set_input(Button::Start, true),
-                        Key::N => gb.input().set_input(Button::Select, true),
-                        Key::Up => gb.input().set_input(Button::Up, true),
-                        Key::Down => gb.input().set_input(Button::Down, true),
-                        Key::Left => gb.input().set_input(Button::Left, true),
-                        Key::Right => gb.input().set_input(Button::Right, true),
-                        _ => (),
+    let tracing_to_file = matches.is_present("trace");
+    if let Some(trace_filename) = matches.value_of("trace") {
+        let format = match matches.value_of("trace-format").unwrap() {
+            "verbose" => TraceFormat::Verbose,
+            _ => TraceFormat::Doctor,
+        };
+        let mut tracer = file_tracer(trace_filename, format)?;
+
+        let start = matches
+            .value_of("trace-start")
+            .map_or(0, |c| c.parse().unwrap());
+        let end = matches
+            .value_of("trace-end")
+            .map_or(u64::MAX, |c| c.parse().unwrap());
+        tracer.set_window(start, end);
+
+        gb.set_tracer(Some(tracer));
+    } else if debugging {
+        gb.set_tracer(Some(stdout_tracer()));
+    }
+
+    let symbols = match matches.value_of("symbols") {
+        Some(sym_filename) => Symbols::parse(&fs::read_to_string(sym_filename)?),
+        None => Symbols::new(),
+    };
+
+    let mut repl = Repl::new(symbols);
+    if matches.is_present("debugger") {
+        gb.debugger().pause();
+    }
+    if watch.is_some() {
+        let kind = match matches.value_of("watch-access").unwrap() {
+            "read" => WatchKind::Read,
+            "any" => WatchKind::Access,
+            _ => WatchKind::Write,
+        };
+        let action = if matches.is_present("watch-break") {
+            WatchAction::Break
+        } else {
+            WatchAction::Record
+        };
+        gb.debugger()
+            .add_watchpoint(watch_start, watch_end, kind, action);
+    }
+    for location in matches.values_of("break").into_iter().flatten() {
+        let (bank, addr) = parse_location(location).unwrap();
+        gb.debugger().add_breakpoint(addr, bank);
+    }
+    if let Some(pc) = matches.value_of("panic-at-pc") {
+        gb.debugger().add_breakpoint(parse_hex(pc).unwrap(), None);
+    }
+
+    let break_flag = repl.break_flag();
+    ctrlc::set_handler(move || {
+        // A second Ctrl-C before the debugger picked up the first exits
+        if break_flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(1);
+        }
+    })
+    .expect("Failed to set Ctrl-C handler");
+
+    let fast_forward =
+        Speed::from_multiplier(matches.value_of("fast-forward").unwrap().parse().unwrap());
+    let slow_motion = Speed::Scaled(matches.value_of("slow-motion").unwrap().parse().unwrap());
+    let frame_skip = matches.value_of("frame-skip").unwrap().parse().unwrap();
+
+    let rewind_seconds: f64 = matches
+        .value_of("rewind-seconds")
+        .unwrap()
+        .parse()
+        .expect("Invalid rewind-seconds");
+    let rewind_capacity = (rewind_seconds * FRAME_RATE / REWIND_INTERVAL as f64) as usize;
+    gb.enable_rewind(rewind_capacity, REWIND_INTERVAL);
+    let mut rewinding = false;
+
+    let record_movie = matches.value_of("record-movie");
+    if record_movie.is_some() {
+        gb.start_recording();
+    }
+    if let Some(movie_filename) = matches.value_of("play-movie") {
+        let played = Movie::parse(&fs::read(movie_filename)?, gb.rom(), model)
+            .and_then(|movie| gb.play_movie(movie));
+        if let Err(e) = played {
+            panic!("{}: {}", movie_filename, e);
+        }
+    }
+
+    let bindings = match matches.value_of("bindings") {
+        Some(bindings_filename) => Bindings::parse(&fs::read_to_string(bindings_filename)?)
+            .unwrap_or_else(|e| panic!("{}: {}", bindings_filename, e)),
+        None => Bindings::new(),
+    };
+    let mut controls = Controls::new(bindings);
+
+    let gamepad_paths: Vec<PathBuf> = matches
+        .values_of("gamepad")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    let gamepads = Gamepads::open(&gamepad_paths);
+    if gamepads.connected() > 0 {
+        println!("Gamepads connected: {}", gamepads.connected());
+    }
+
+    // Paced here instead
+    window.limit_update_rate(None);
+
+    let mut pacer = Pacer::new(frame_skip, Instant::now());
+    let mut speed_meter = SpeedMeter::new(Instant::now(), gb.cycles);
+    let mut speed = Speed::NORMAL;
+
+    'emulate: loop {
+        repl.poll(&mut gb);
+
+        let drawn = if rewinding {
+            // Back a snapshot each frame, so twice as fast as it was played
+            gb.rewind(REWIND_INTERVAL);
+            true
+        } else {
+            match gb.cycle(debugging) {
+                Ok(drawn) => drawn,
+                Err(reason) => {
+                    if !repl.run(&mut gb, reason) {
+                        break;
                     }
+                    // Don't race to catch up on time spent in the debugger
+                    pacer.reset(Instant::now());
+                    false
                 }
             }
+        };
 
-            for keyset in window.get_keys_released() {
-                for k in keyset {
-                    match k {
-                        Key::A => gb.input().set_input(Button::A, false),
-                        Key::Z => gb.input().set_input(Button::B, false),
-                        Key::M => gb.input().set_input(Button::Start, false),
-                        Key::N => gb.input().set_input(Button::Select, false),
-                        Key::Up => gb.input().set_input(Button::Up, false),
-                        Key::Down => gb.input().set_input(Button::Down, false),
-                        Key::Left => gb.input().set_input(Button::Left, false),
-                        Key::Right => gb.input().set_input(Button::Right, false),
-                        _ => (),
+        if drawn {
+            for hit in gb.debugger().take_watch_hits() {
+                print_watch_hit(&hit);
+            }
+            if let Some(e) = gb.take_tracer_error() {
+                eprintln!("Stopped tracing: {}", e);
+            }
+
+            let now = Instant::now();
+            if pacer.end_frame(speed, now) {
+                let pixels = video.render(gb.frame().argb32().unwrap());
+
+                // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
+                window
+                    .update_with_buffer(pixels, window_width, window_height)
+                    .unwrap();
+            } else {
+                // Skipping this frame, but still picking up input
+                window.update();
+            }
+
+            if let Some(percent) = speed_meter.update(now, gb.cycles) {
+                window.set_title(&format!("Rust Gameboy - {} - {:.0}%", gb.title(), percent));
+            }
+
+            let mut hotkeys = Vec::new();
+            for key in window
+                .get_keys_pressed(minifb::KeyRepeat::No)
+                .into_iter()
+                .flatten()
+            {
+                hotkeys.extend(controls.press(Control::Key(key)));
+            }
+            for key in window.get_keys_released().into_iter().flatten() {
+                controls.release(Control::Key(key));
+            }
+            for event in gamepads.events() {
+                match event {
+                    PadEvent::Button(button, true) => {
+                        hotkeys.extend(controls.press(Control::PadButton(button)))
                     }
+                    PadEvent::Button(button, false) => controls.release(Control::PadButton(button)),
+                    PadEvent::Axis(axis, value) => hotkeys.extend(controls.axis(axis, value)),
                 }
             }
 
+            // The movie has the controls while it's playing
+            if !gb.playing_movie() {
+                controls.update(gb.input());
+            }
+
+            for hotkey in hotkeys {
+                match hotkey {
+                    Hotkey::Quit => break 'emulate,
+                    Hotkey::Break => repl.request_break(),
+                    Hotkey::TraceOn => {
+                        debugging = true;
+                        if !tracing_to_file {
+                            gb.set_tracer(Some(stdout_tracer()));
+                        }
+                    }
+                    Hotkey::TraceOff => {
+                        debugging = false;
+                        if !tracing_to_file {
+                            gb.set_tracer(None);
+                        }
+                    }
+                    Hotkey::DumpWatch => println!("{:?}", gb.read_region(watch_start, watch_end)),
+                    Hotkey::GpuTrace => println!("{:?}", gb.gpu_trace()),
+                    // Acted on for as long as they're held
+                    Hotkey::FastForward | Hotkey::SlowMotion | Hotkey::Rewind => {}
+                }
+            }
+
+            speed = if controls.is_held(Hotkey::FastForward) {
+                fast_forward
+            } else if controls.is_held(Hotkey::SlowMotion) {
+                slow_motion
+            } else {
+                Speed::NORMAL
+            };
+            rewinding = controls.is_held(Hotkey::Rewind);
+
             if !window.is_open() {
-                return Ok(());
+                break;
+            }
+
+            if let Some(wait) = pacer.wait_time(Instant::now()) {
+                thread::sleep(wait);
             }
         }
     }
+
+    if let (Some(movie_filename), Some(movie)) = (record_movie, gb.stop_recording()) {
+        fs::write(movie_filename, movie.to_bytes())?;
+        println!("Recorded {} frames to {}", movie.frames(), movie_filename);
+    }
+
+    Ok(())
+}
+
+fn validate_address(arg: String) -> Result<(), String> {
+    parse_hex(&arg)
+        .map(|_| ())
+        .ok_or_else(|| format!("Invalid address {}", arg))
+}
+
+fn validate_cycles(arg: String) -> Result<(), String> {
+    arg.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("Invalid cycle count {}", arg))
+}
+
+fn validate_fast_forward(arg: String) -> Result<(), String> {
+    match arg.parse::<f64>() {
+        Ok(multiplier) if multiplier.is_finite() && multiplier >= 0.0 => Ok(()),
+        _ => Err(format!("Invalid speed {}, expected 0 or more", arg)),
+    }
+}
+
+fn validate_slow_motion(arg: String) -> Result<(), String> {
+    match arg.parse::<f64>() {
+        Ok(multiplier) if multiplier.is_finite() && multiplier > 0.0 => Ok(()),
+        _ => Err(format!("Invalid speed {}, expected more than 0", arg)),
+    }
+}
+
+fn validate_frame_skip(arg: String) -> Result<(), String> {
+    arg.parse::<u32>()
+        .map(|_| ())
+        .map_err(|_| format!("Invalid frame count {}", arg))
+}
+
+fn validate_scale(arg: String) -> Result<(), String> {
+    match arg.parse::<usize>() {
+        Ok(scale) if scale > 0 => Ok(()),
+        _ => Err(format!("Invalid scale {}, expected 1 or more", arg)),
+    }
+}
+
+fn validate_ghosting(arg: String) -> Result<(), String> {
+    match arg.parse::<f32>() {
+        Ok(ghosting) if (0.0..=MAX_GHOSTING).contains(&ghosting) => Ok(()),
+        _ => Err(format!(
+            "Invalid ghosting {}, expected 0 - {}",
+            arg, MAX_GHOSTING
+        )),
+    }
+}
+
+fn validate_palette(arg: String) -> Result<(), String> {
+    if arg == "auto" {
+        return Ok(());
+    }
+    arg.parse::<Palettes>().map(|_| ())
+}
+
+fn validate_location(arg: String) -> Result<(), String> {
+    parse_location(&arg)
+        .map(|_| ())
+        .ok_or_else(|| format!("Invalid location {}, expected pc or bank:pc", arg))
+}
+
+fn print_watch_hit(hit: &WatchHit) {
+    if hit.write {
+        println!(
+            "[{}] pc {:04X} wrote {:04X}: {:02X} -> {:02X}",
+            hit.cycles, hit.pc, hit.addr, hit.old, hit.new
+        );
+    } else {
+        println!(
+            "[{}] pc {:04X} read {:04X}: {:02X}",
+            hit.cycles, hit.pc, hit.addr, hit.new
+        );
+    }
+}
+
+fn stdout_tracer() -> Tracer {
+    Tracer::new(Box::new(io::stdout()), TraceFormat::Verbose)
+}
+
+fn file_tracer(filename: &str, format: TraceFormat) -> io::Result<Tracer> {
+    let file = BufWriter::new(File::create(filename)?);
+
+    let out: Box<dyn io::Write> = if filename.ends_with(".gz") {
+        Box::new(GzEncoder::new(file, Compression::fast()))
+    } else {
+        Box::new(file)
+    };
+
+    Ok(Tracer::new(out, format))
 }
@@ -0,0 +1,182 @@
+//! Real time pacing for the frontend. Frames are timed against the wall
+//! clock; there's no sound output yet for audio driven sync to follow.
+
+use std::time::{Duration, Instant};
+
+const CLOCK_HZ: f64 = 4_194_304.0;
+const FRAME_CYCLES: f64 = 70224.0;
+
+/// About 59.7275 frames per second
+pub const FRAME_RATE: f64 = CLOCK_HZ / FRAME_CYCLES;
+
+/// Emulation speed relative to the real hardware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    Scaled(f64),
+    Uncapped,
+}
+
+impl Speed {
+    pub const NORMAL: Speed = Speed::Scaled(1.0);
+
+    /// `--fast-forward` style multiplier, where 0 means as fast as possible
+    pub fn from_multiplier(multiplier: f64) -> Speed {
+        if multiplier <= 0.0 {
+            Speed::Uncapped
+        } else {
+            Speed::Scaled(multiplier)
+        }
+    }
+}
+
+fn frame_period() -> Duration {
+    Duration::from_secs_f64(1.0 / FRAME_RATE)
+}
+
+/// Keeps emulated frames in step with the wall clock, skipping drawing some
+/// when the host can't keep up
+pub struct Pacer {
+    next_frame: Instant,
+    last_draw: Instant,
+    max_frame_skip: u32,
+    skipped: u32,
+}
+
+impl Pacer {
+    pub fn new(max_frame_skip: u32, now: Instant) -> Pacer {
+        Pacer {
+            next_frame: now,
+            last_draw: now,
+            max_frame_skip,
+            skipped: 0,
+        }
+    }
+
+    /// Start timing afresh, e.g. after sitting in the debugger
+    pub fn reset(&mut self, now: Instant) {
+        self.next_frame = now;
+        self.skipped = 0;
+    }
+
+    /// Called as each emulated frame completes, returns whether to draw it
+    pub fn end_frame(&mut self, speed: Speed, now: Instant) -> bool {
+        let scale = match speed {
+            Speed::Scaled(scale) => scale,
+            Speed::Uncapped => {
+                // No point drawing faster than the screen can show
+                self.next_frame = now;
+                if now.duration_since(self.last_draw) < frame_period() {
+                    return false;
+                }
+                self.last_draw = now;
+                return true;
+            }
+        };
+
+        self.next_frame += frame_period().div_f64(scale);
+
+        if now > self.next_frame {
+            if self.skipped < self.max_frame_skip {
+                self.skipped += 1;
+                return false;
+            }
+
+            // Too far behind to catch up, so give up on the lost time
+            self.next_frame = now;
+        }
+
+        self.skipped = 0;
+        self.last_draw = now;
+        true
+    }
+
+    /// How long to wait before emulating the next frame
+    pub fn wait_time(&self, now: Instant) -> Option<Duration> {
+        self.next_frame.checked_duration_since(now)
+    }
+}
+
+/// Emulated time as a percentage of wall clock time, measured each second
+pub struct SpeedMeter {
+    start: Instant,
+    start_cycles: u64,
+}
+
+impl SpeedMeter {
+    pub fn new(now: Instant, cycles: u64) -> SpeedMeter {
+        SpeedMeter {
+            start: now,
+            start_cycles: cycles,
+        }
+    }
+
+    pub fn update(&mut self, now: Instant, cycles: u64) -> Option<f64> {
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
+        }
+
+        // Cycles go backwards when rewinding
+        let emulated = cycles.saturating_sub(self.start_cycles) as f64 / CLOCK_HZ;
+        self.start = now;
+        self.start_cycles = cycles;
+
+        Some(emulated / elapsed * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_skip() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(2, start);
+
+        // On time, waiting for the rest of the frame
+        assert!(pacer.end_frame(Speed::NORMAL, start));
+        assert!(pacer.wait_time(start).unwrap() > Duration::from_millis(16));
+
+        // Running slow, skips two frames then draws one & resyncs
+        let late = start + Duration::from_millis(200);
+        assert!(!pacer.end_frame(Speed::NORMAL, late));
+        assert!(!pacer.end_frame(Speed::NORMAL, late));
+        assert!(pacer.end_frame(Speed::NORMAL, late));
+        assert_eq!(pacer.wait_time(late), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn speeds() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(0, start);
+
+        pacer.end_frame(Speed::Scaled(2.0), start);
+        let fast = pacer.wait_time(start).unwrap();
+        pacer.reset(start);
+        pacer.end_frame(Speed::Scaled(0.5), start);
+        let slow = pacer.wait_time(start).unwrap();
+
+        assert!(fast < Duration::from_millis(9));
+        assert!(slow > Duration::from_millis(33));
+
+        // Uncapped never waits, & draws at most once per real frame
+        let mut pacer = Pacer::new(0, start);
+        assert!(!pacer.end_frame(Speed::Uncapped, start));
+        assert_eq!(pacer.wait_time(start), Some(Duration::from_secs(0)));
+        assert!(pacer.end_frame(Speed::Uncapped, start + Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn speed_meter() {
+        let start = Instant::now();
+        let mut meter = SpeedMeter::new(start, 0);
+
+        assert_eq!(meter.update(start + Duration::from_millis(500), 1000), None);
+
+        let percent = meter
+            .update(start + Duration::from_secs(2), 4_194_304)
+            .unwrap();
+        assert!((percent - 50.0).abs() < 0.01);
+    }
+}
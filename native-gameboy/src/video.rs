@@ -0,0 +1,371 @@
+//! Turns the emulator's ARGB frames into the window's pixels, scaled up and
+//! with optional effects. It all runs on the CPU, one frame at a time:
+//!
+//! 1. Colour correction, mixing the channels as the Game Boy Color's LCD
+//!    does, for palettes picked to look right on one
+//! 2. Ghosting, blending in the previous frames like the DMG's slow LCD
+//! 3. The scaling filter, then plain pixel doubling up to the full scale
+//! 4. The LCD grid, darkening the gaps between the pixels
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    /// AdvMAME2x, rounding off diagonal edges
+    Scale2x,
+    /// AdvMAME3x, the same at three times the size
+    Scale3x,
+}
+
+impl Filter {
+    /// How many times bigger the filter itself makes the frame
+    pub fn factor(self) -> usize {
+        match self {
+            Filter::Nearest => 1,
+            Filter::Scale2x => 2,
+            Filter::Scale3x => 3,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Filter, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            _ => Err(format!("Unknown filter {}", s)),
+        }
+    }
+}
+
+/// Most ghosting allowed, any more and the screen barely changes
+pub const MAX_GHOSTING: f32 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Effects {
+    pub filter: Filter,
+    /// Total scale, a multiple of the filter's factor
+    pub scale: usize,
+    pub lcd_grid: bool,
+    /// How much of the previous frame shows through, 0 - `MAX_GHOSTING`
+    pub ghosting: f32,
+    pub colour_correction: bool,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Effects {
+            filter: Filter::Nearest,
+            scale: 2,
+            lcd_grid: false,
+            ghosting: 0.0,
+            colour_correction: false,
+        }
+    }
+}
+
+pub struct Video {
+    effects: Effects,
+    width: usize,
+    height: usize,
+    // Source resolution frame after colour correction & ghosting, kept to
+    // blend into the next one
+    frame: Vec<u32>,
+    // Whether `frame` holds a rendered frame yet, the first has nothing to
+    // blend with
+    rendered: bool,
+    filtered: Vec<u32>,
+    output: Vec<u32>,
+}
+
+impl Video {
+    /// For `width` x `height` frames
+    pub fn new(width: usize, height: usize, effects: Effects) -> Result<Video, String> {
+        let factor = effects.filter.factor();
+        if effects.scale == 0 || !effects.scale.is_multiple_of(factor) {
+            return Err(format!(
+                "Scale {} isn't a multiple of {:?}'s {}x",
+                effects.scale, effects.filter, factor
+            ));
+        }
+
+        let (out_width, out_height) = (width * effects.scale, height * effects.scale);
+        Ok(Video {
+            effects,
+            width,
+            height,
+            frame: vec![0; width * height],
+            rendered: false,
+            filtered: vec![0; width * factor * height * factor],
+            output: vec![0; out_width * out_height],
+        })
+    }
+
+    /// Width & height of the pixels `render` produces
+    pub fn output_size(&self) -> (usize, usize) {
+        (
+            self.width * self.effects.scale,
+            self.height * self.effects.scale,
+        )
+    }
+
+    /// Run a frame of ARGB through the effects, giving 0RGB pixels for the
+    /// window
+    pub fn render(&mut self, argb: &[u32]) -> &[u32] {
+        assert_eq!(argb.len(), self.width * self.height);
+
+        let ghosting = if self.rendered {
+            (self.effects.ghosting.clamp(0.0, MAX_GHOSTING) * 256.0) as u32
+        } else {
+            0
+        };
+        self.rendered = true;
+        for (&pixel, previous) in argb.iter().zip(self.frame.iter_mut()) {
+            let current = if self.effects.colour_correction {
+                pack(correct_colour(unpack(pixel)))
+            } else {
+                pixel & 0xFF_FFFF
+            };
+
+            *previous = if ghosting > 0 {
+                blend(current, *previous, ghosting)
+            } else {
+                current
+            };
+        }
+
+        let factor = self.effects.filter.factor();
+        match self.effects.filter {
+            Filter::Nearest => self.filtered.copy_from_slice(&self.frame),
+            Filter::Scale2x => scale2x(&self.frame, self.width, self.height, &mut self.filtered),
+            Filter::Scale3x => scale3x(&self.frame, self.width, self.height, &mut self.filtered),
+        }
+
+        let repeat = self.effects.scale / factor;
+        let filtered_width = self.width * factor;
+        let (out_width, _) = self.output_size();
+        let scale = self.effects.scale;
+        let lcd_grid = self.effects.lcd_grid && scale > 1;
+
+        for (y, row) in self.output.chunks_exact_mut(out_width).enumerate() {
+            let source = &self.filtered[(y / repeat) * filtered_width..][..filtered_width];
+            for (x, out) in row.iter_mut().enumerate() {
+                let pixel = source[x / repeat];
+                *out = if lcd_grid && (x % scale == scale - 1 || y % scale == scale - 1) {
+                    darken(pixel)
+                } else {
+                    pixel
+                };
+            }
+        }
+
+        &self.output
+    }
+}
+
+fn pack([r, g, b]: [u32; 3]) -> u32 {
+    (r << 16) | (g << 8) | b
+}
+
+fn unpack(pixel: u32) -> [u32; 3] {
+    [(pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF]
+}
+
+/// Each channel picks up some of the others on the GBC's LCD, out of 32
+fn correct_colour([r, g, b]: [u32; 3]) -> [u32; 3] {
+    [
+        (r * 26 + g * 4 + b * 2) / 32,
+        (g * 24 + b * 8) / 32,
+        (r * 6 + g * 4 + b * 22) / 32,
+    ]
+}
+
+/// `previous` weighted by `amount` out of 256
+fn blend(current: u32, previous: u32, amount: u32) -> u32 {
+    let (current, previous) = (unpack(current), unpack(previous));
+    let mix = |c: usize| (current[c] * (256 - amount) + previous[c] * amount) >> 8;
+    (mix(0) << 16) | (mix(1) << 8) | mix(2)
+}
+
+/// Three quarters brightness
+fn darken(pixel: u32) -> u32 {
+    let [r, g, b] = unpack(pixel);
+    ((r * 3 / 4) << 16) | ((g * 3 / 4) << 8) | (b * 3 / 4)
+}
+
+/// The 3x3 block around (x, y), repeating the edges
+fn neighbours(frame: &[u32], width: usize, height: usize, x: usize, y: usize) -> [u32; 9] {
+    let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+    let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+    let at = |x: usize, y: usize| frame[y * width + x];
+
+    [
+        at(left, up),
+        at(x, up),
+        at(right, up),
+        at(left, y),
+        at(x, y),
+        at(right, y),
+        at(left, down),
+        at(x, down),
+        at(right, down),
+    ]
+}
+
+fn scale2x(frame: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 2;
+
+    for y in 0..height {
+        for x in 0..width {
+            let [_, b, _, d, e, f, _, h, _] = neighbours(frame, width, height, x, y);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 4]
+            };
+
+            let top = (y * 2) * out_width + x * 2;
+            out[top..top + 2].copy_from_slice(&block[..2]);
+            out[top + out_width..top + out_width + 2].copy_from_slice(&block[2..]);
+        }
+    }
+}
+
+fn scale3x(frame: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 3;
+
+    for y in 0..height {
+        for x in 0..width {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(frame, width, height, x, y);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) {
+                        b
+                    } else {
+                        e
+                    },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) {
+                        d
+                    } else {
+                        e
+                    },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) {
+                        f
+                    } else {
+                        e
+                    },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) {
+                        h
+                    } else {
+                        e
+                    },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            for (row, pixels) in block.chunks(3).enumerate() {
+                let start = (y * 3 + row) * out_width + x * 3;
+                out[start..start + 3].copy_from_slice(pixels);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: u32 = 0x000000;
+    const WHITE: u32 = 0xFFFFFF;
+
+    #[test]
+    fn nearest() {
+        let mut video = Video::new(2, 1, Effects::default()).unwrap();
+        assert_eq!(video.output_size(), (4, 2));
+        assert_eq!(
+            video.render(&[0xFF12_3456, WHITE]),
+            &[0x123456, 0x123456, WHITE, WHITE, 0x123456, 0x123456, WHITE, WHITE]
+        );
+
+        let effects = Effects {
+            filter: Filter::Scale3x,
+            scale: 4,
+            ..Effects::default()
+        };
+        assert!(Video::new(2, 1, effects).is_err());
+    }
+
+    #[test]
+    fn scale_filters() {
+        // The diagonal pixels get joined up rather than doubled
+        let frame = [WHITE, BLACK, BLACK, WHITE];
+
+        let mut out = vec![0; 16];
+        scale2x(&frame, 2, 2, &mut out);
+        #[rustfmt::skip]
+        assert_eq!(out, vec![
+            WHITE, WHITE, BLACK, BLACK,
+            WHITE, BLACK, WHITE, BLACK,
+            BLACK, WHITE, BLACK, WHITE,
+            BLACK, BLACK, WHITE, WHITE,
+        ]);
+
+        let frame = [
+            WHITE, WHITE, BLACK, WHITE, BLACK, BLACK, BLACK, BLACK, BLACK,
+        ];
+        let mut out = vec![0; 81];
+        scale3x(&frame, 3, 3, &mut out);
+        // The centre pixel's top left corner takes the white above & left
+        assert_eq!(out[3 * 9 + 3], WHITE);
+        assert_eq!(out[4 * 9 + 4], BLACK);
+    }
+
+    #[test]
+    fn effects() {
+        let effects = Effects {
+            scale: 2,
+            lcd_grid: true,
+            ghosting: 0.5,
+            colour_correction: true,
+            ..Effects::default()
+        };
+        let mut video = Video::new(1, 1, effects).unwrap();
+
+        // Greys stay grey with colour correction, and the first frame has
+        // nothing to blend with
+        let first = video.render(&[WHITE]).to_vec();
+        assert_eq!(first[0], WHITE);
+        // Darkened between pixels
+        assert_eq!(first[3], 0xBFBFBF);
+
+        let second = video.render(&[BLACK]).to_vec();
+        assert_eq!(second[0], 0x7F7F7F);
+
+        // Full ghosting is capped so the screen still changes
+        let effects = Effects {
+            ghosting: 1.0,
+            ..Effects::default()
+        };
+        let mut video = Video::new(1, 1, effects).unwrap();
+        video.render(&[WHITE]);
+        assert_ne!(video.render(&[BLACK])[0], WHITE);
+        assert_eq!(correct_colour([255, 0, 0]), [207, 0, 47]);
+    }
+}
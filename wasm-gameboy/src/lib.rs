@@ -2,9 +2,13 @@ mod utils;
 
 use std::mem;
 
+use gameboy::debugger::{StopReason, WatchAction, WatchKind};
 use gameboy::disassemble::{disassemble, Disassembly};
-use gameboy::gameboy::GameBoy;
+use gameboy::frame::PixelFormat;
+use gameboy::gameboy::{GameBoy, RunStop};
 use gameboy::gpu::{GB_HSIZE, GB_VSIZE};
+use gameboy::model::Model;
+use gameboy::palette::Palettes;
 
 use wasm_bindgen::prelude::*;
 
@@ -32,6 +36,7 @@ pub struct WasmGameboy {
     debug: bool,
 
     last_disassembly: Vec<Disassembly>,
+    last_stop: Option<StopReason>,
 }
 
 #[wasm_bindgen]
@@ -61,6 +66,7 @@ impl WasmGameboy {
             rom_buffer: vec![0; rom_size],
             debug: false,
             last_disassembly: Vec::new(),
+            last_stop: None,
         }
     }
 
@@ -69,15 +75,16 @@ impl WasmGameboy {
     }
 
     pub fn start(&mut self) {
-        self.gb = Some(GameBoy::new(self.rom_buffer.clone()))
+        self.gb = Some(GameBoy::new(self.rom_buffer.clone(), Model::Dmg))
     }
 
+    /// Returns false if the debugger stopped first, see `stop_reason`
     pub fn cycle_until_vsync(&mut self) -> bool {
         if let Some(gb) = self.gb.as_mut() {
-            for _ in 0..100_000 {
-                if gb.cycle(false, None) {
-                    return true;
-                }
+            match gb.run_frame().stop {
+                RunStop::FrameComplete => return true,
+                RunStop::Debugger(reason) => self.last_stop = Some(reason),
+                _ => {}
             }
         } else {
             consolelog!("Gameboy null");
@@ -87,8 +94,9 @@ impl WasmGameboy {
 
     pub fn cycle(&mut self) -> bool {
         if let Some(gb) = self.gb.as_mut() {
-            if gb.cycle(false, None) {
-                return true;
+            match gb.cycle(false) {
+                Ok(drawn) => return drawn,
+                Err(reason) => self.last_stop = Some(reason),
             }
         } else {
             consolelog!("Gameboy null");
@@ -96,6 +104,75 @@ impl WasmGameboy {
         false
     }
 
+    /// Why the debugger last stopped execution, if it has since the last call
+    pub fn stop_reason(&mut self) -> Option<String> {
+        self.last_stop.take().map(|reason| format!("{:?}", reason))
+    }
+
+    /// Keep up to `capacity` snapshots to rewind to, one every `interval`
+    /// frames. 0 capacity turns rewinding off.
+    pub fn enable_rewind(&mut self, capacity: usize, interval: u32) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.enable_rewind(capacity, u64::from(interval));
+        }
+    }
+
+    pub fn push_snapshot(&mut self) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.push_snapshot();
+        }
+    }
+
+    /// Returns how many frames it went back
+    pub fn rewind(&mut self, frames: u32) -> u32 {
+        match self.gb.as_mut() {
+            Some(gb) => gb.rewind(u64::from(frames)) as u32,
+            None => 0,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.debugger().add_breakpoint(addr, None);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.debugger().remove_breakpoint(addr);
+        }
+    }
+
+    pub fn add_write_watchpoint(&mut self, start: u16, end: u16) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.debugger().add_watchpoint(start, end, WatchKind::Write, WatchAction::Break);
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.debugger().pause();
+        }
+    }
+
+    pub fn step(&mut self) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.debugger().step(1);
+        }
+    }
+
+    pub fn step_over(&mut self) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.step_over();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(gb) = self.gb.as_mut() {
+            gb.debugger().resume();
+        }
+    }
+
     /// Populates an internal buffer with the decoding of the memory address
     /// range [start..end]. Returns the decoded instruction info
     pub fn disassemble(&mut self, start: u16, end: u16) -> JsValue {
@@ -134,8 +211,8 @@ impl WasmGameboy {
                 f: gb.cpu.f,
                 h: gb.cpu.h,
                 l: gb.cpu.l,
-                lcd_pwr: gb.gpu.debug_lcd_pwr,
-                ly: gb.gpu.line,
+                lcd_pwr: gb.gpu().debug_lcd_pwr,
+                ly: gb.gpu().line,
                 stat: gb.mem.get(0xFF41),
                 lcdc: gb.mem.get(0xFF40),
             }
@@ -144,22 +221,60 @@ impl WasmGameboy {
         }
     }
 
+    /// Colours for the DMG's shades, as a preset name, hex colours or
+    /// "auto" for the Game Boy Color's choice. Returns false if it's not
+    /// understood.
+    pub fn set_palette(&mut self, palette: &str) -> bool {
+        if let Some(gb) = self.gb.as_mut() {
+            let palettes = if palette == "auto" {
+                Ok(Palettes::for_rom(gb.rom()))
+            } else {
+                palette.parse::<Palettes>()
+            };
+
+            match palettes {
+                Ok(palettes) => {
+                    gb.set_palettes(palettes);
+                    return true;
+                }
+                Err(e) => {
+                    consolelog!("{}", e);
+                }
+            }
+        }
+        false
+    }
+
     pub fn rom_buffer(&mut self) -> *mut u8 {
         self.rom_buffer.as_mut_ptr()
     }
 
+    /// The last completed frame, in the format picked by `set_screen_format`
     pub fn screen_buffer(&self) -> *const u8 {
-        self.gb.as_ref().unwrap().buffer()
+        self.gb.as_ref().unwrap().frame().as_ptr()
     }
 
+    /// Size of `screen_buffer` in bytes
     pub fn screen_size(&self) -> usize {
-        self.gb.as_ref().map(|gb| gb.buffer_vec().len()).unwrap()
+        self.gb.as_ref().map(|gb| gb.frame().byte_len()).unwrap()
+    }
+
+    /// rgba8888 (the default), argb32, rgb565 or shades. Returns false if
+    /// it's not one of those.
+    pub fn set_screen_format(&mut self, format: &str) -> bool {
+        match (self.gb.as_mut(), format.parse::<PixelFormat>()) {
+            (Some(gb), Ok(format)) => {
+                gb.set_frame_format(format);
+                true
+            }
+            _ => false,
+        }
     }
 
     pub fn screen_width(&self) -> usize {
-        GB_HSIZE
+        self.gb.as_ref().map_or(GB_HSIZE, |gb| gb.frame().width())
     }
     pub fn screen_height(&self) -> usize {
-        GB_VSIZE
+        self.gb.as_ref().map_or(GB_VSIZE, |gb| gb.frame().height())
     }
 }